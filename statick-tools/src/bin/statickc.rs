@@ -4,9 +4,10 @@ extern crate structopt;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
+use std::process;
 use structopt::StructOpt;
 
-use simlib::statick::compile;
+use simlib::statick::{compile_with_sources, CompileOptions, SourceFiles};
 
 #[derive(StructOpt, Debug)]
 struct Opts {
@@ -26,21 +27,46 @@ struct Opts {
     /// Output definition types in standard out. Note the assembler includes this in its output
     /// too.
     output_types: bool,
+
+    #[structopt(short = "y", long = "auto-yield")]
+    /// Yield at the end of every loop iteration, so that processes that never block can't starve
+    /// others when there are more processes than cores
+    auto_yield: bool,
 }
 
 fn main() {
     let opts = Opts::from_args();
 
-    let assembly = match compile(&opts.input, opts.output_types) {
+    let options = CompileOptions {
+        output_types: opts.output_types,
+        auto_yield: opts.auto_yield,
+    };
+    let mut files = SourceFiles::default();
+    let assembly = match compile_with_sources(&opts.input, options, &mut files) {
         Ok(a) => a,
-        Err(reason) => panic!("Error generating assembly: {}", reason),
+        Err(error) => {
+            eprint!("{}", error.render(&files));
+            process::exit(1);
+        }
     };
 
     let mut file = match File::create(&opts.output) {
         Ok(f) => f,
-        Err(reason) => panic!("Error creating file {}", reason),
+        Err(reason) => {
+            eprintln!(
+                "error: could not create {}: {}",
+                opts.output.display(),
+                reason
+            );
+            process::exit(1);
+        }
     };
     if let Err(reason) = file.write_all(&assembly.as_bytes()) {
-        panic!("Failed to write to {:?} because {}", opts.output, reason)
+        eprintln!(
+            "error: could not write to {}: {}",
+            opts.output.display(),
+            reason
+        );
+        process::exit(1);
     }
 }
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt;
+
+/**
+ * A numeric literal, as written in either Statick or Stannel assembly. Negative literals are
+ * distinguished so that Statick can give them a signed type, but both kinds are pushed as the same
+ * (two's complement) word.
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Literal {
+    Unsigned(u16),
+    Signed(i16),
+}
+
+impl Literal {
+    pub fn as_word(self) -> u16 {
+        match self {
+            Literal::Unsigned(n) => n,
+            Literal::Signed(n) => n as u16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LiteralError {
+    Malformed,
+    OutOfRange,
+}
+
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiteralError::Malformed => write!(f, "malformed literal"),
+            LiteralError::OutOfRange => write!(f, "literal doesn't fit in a 16-bit word"),
+        }
+    }
+}
+
+impl Error for LiteralError {}
+
+/** True if `text` should be read as a literal rather than as a name or instruction. */
+pub fn starts_literal(text: &str) -> bool {
+    let text = text.strip_prefix('-').unwrap_or(text);
+    match text.chars().next() {
+        Some(c) => c.is_ascii_digit() || c == '\'',
+        None => false,
+    }
+}
+
+/**
+ * Parses decimal (`42`), hexadecimal (`0x2A`), binary (`0b101010`) and character (`'*'`)
+ * literals. Digits may be separated by underscores (`0b0010_1010`), and a leading minus sign
+ * makes the literal negative, in which case it must fit in a signed word.
+ */
+pub fn parse_literal(text: &str) -> Result<Literal, LiteralError> {
+    let (negative, unsigned_text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let magnitude = if unsigned_text.starts_with('\'') {
+        parse_character(unsigned_text)?
+    } else {
+        parse_integer(unsigned_text)?
+    };
+    if negative {
+        if magnitude > 0x8000 {
+            Err(LiteralError::OutOfRange)
+        } else {
+            Ok(Literal::Signed((magnitude as i32).wrapping_neg() as i16))
+        }
+    } else if magnitude > 0xFFFF {
+        Err(LiteralError::OutOfRange)
+    } else {
+        Ok(Literal::Unsigned(magnitude as u16))
+    }
+}
+
+fn parse_integer(text: &str) -> Result<u32, LiteralError> {
+    let (radix, digits) = if let Some(digits) = text.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = text.strip_prefix("0b") {
+        (2, digits)
+    } else {
+        (10, text)
+    };
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return Err(LiteralError::Malformed);
+    }
+
+    let mut value: u32 = 0;
+    let mut any_digits = false;
+    for c in digits.chars().filter(|c| *c != '_') {
+        let digit = c.to_digit(radix).ok_or(LiteralError::Malformed)?;
+        any_digits = true;
+        // Saturate so that very long literals are reported as out of range rather than wrapping
+        value = value.saturating_mul(radix).saturating_add(digit);
+    }
+    if any_digits {
+        Ok(value)
+    } else {
+        Err(LiteralError::Malformed)
+    }
+}
+
+fn parse_character(text: &str) -> Result<u32, LiteralError> {
+    let mut chars = text.chars();
+    if chars.next() != Some('\'') {
+        return Err(LiteralError::Malformed);
+    }
+    let c = match chars.next() {
+        Some('\\') => match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            _ => return Err(LiteralError::Malformed),
+        },
+        Some('\'') | None => return Err(LiteralError::Malformed),
+        Some(c) => c,
+    };
+    if chars.next() != Some('\'') || chars.next().is_some() {
+        return Err(LiteralError::Malformed);
+    }
+    Ok(c as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_radix() {
+        assert_eq!(parse_literal("42"), Ok(Literal::Unsigned(42)));
+        assert_eq!(parse_literal("0x2A"), Ok(Literal::Unsigned(42)));
+        assert_eq!(parse_literal("0xffff"), Ok(Literal::Unsigned(0xFFFF)));
+        assert_eq!(parse_literal("0b101010"), Ok(Literal::Unsigned(42)));
+        assert_eq!(parse_literal("0b0010_1010"), Ok(Literal::Unsigned(42)));
+        assert_eq!(parse_literal("65_535"), Ok(Literal::Unsigned(65535)));
+    }
+
+    #[test]
+    fn parses_characters() {
+        assert_eq!(parse_literal("'a'"), Ok(Literal::Unsigned(97)));
+        assert_eq!(parse_literal("'\\n'"), Ok(Literal::Unsigned(10)));
+        assert_eq!(parse_literal("'\\''"), Ok(Literal::Unsigned(39)));
+        assert_eq!(parse_literal("'λ'"), Ok(Literal::Unsigned(0x3BB)));
+        assert_eq!(parse_literal("'ab'"), Err(LiteralError::Malformed));
+        assert_eq!(parse_literal("''"), Err(LiteralError::Malformed));
+    }
+
+    #[test]
+    fn parses_negative_literals() {
+        assert_eq!(parse_literal("-1"), Ok(Literal::Signed(-1)));
+        assert_eq!(parse_literal("-0x8000"), Ok(Literal::Signed(-32768)));
+        assert_eq!(parse_literal("-'a'"), Ok(Literal::Signed(-97)));
+        assert_eq!(Literal::Signed(-1).as_word(), 0xFFFF);
+    }
+
+    #[test]
+    fn rejects_bad_literals() {
+        assert_eq!(parse_literal("65536"), Err(LiteralError::OutOfRange));
+        assert_eq!(parse_literal("0x1_0000"), Err(LiteralError::OutOfRange));
+        assert_eq!(parse_literal("-32769"), Err(LiteralError::OutOfRange));
+        assert_eq!(
+            parse_literal("99999999999999999999"),
+            Err(LiteralError::OutOfRange)
+        );
+        assert_eq!(parse_literal("0x"), Err(LiteralError::Malformed));
+        assert_eq!(parse_literal("0b102"), Err(LiteralError::Malformed));
+        assert_eq!(parse_literal("12ab"), Err(LiteralError::Malformed));
+        assert_eq!(parse_literal("1_"), Err(LiteralError::Malformed));
+    }
+}
@@ -4,6 +4,9 @@ use std::str::FromStr;
 
 use crate::isa::{Instruction, Op};
 
+mod literal;
+pub use literal::{parse_literal, starts_literal, Literal, LiteralError};
+
 pub struct IOLineIteratorWrapper {
     pub lines: io::Lines<io::BufReader<std::fs::File>>,
 }
@@ -53,8 +56,9 @@ where
     I: Iterator<Item = String>,
 {
     let mut tokens = Vec::new();
+    let mut errors = Vec::new();
 
-    for line in line_iter {
+    for (i, line) in line_iter.enumerate() {
         let line = line;
         let word_iter = line.split_whitespace();
         for word in word_iter {
@@ -66,15 +70,31 @@ where
             if word.ends_with(':') {
                 let label = word.split_at(word.find(':').unwrap()).0;
                 tokens.push(ParserToken::Label(label.to_string()));
-            } else if let Ok(n) = word.parse::<u16>() {
-                tokens.push(ParserToken::Number(n));
+            } else if starts_literal(word) {
+                match parse_literal(word) {
+                    Ok(n) => tokens.push(ParserToken::Number(n.as_word())),
+                    Err(e) => {
+                        let column = word.as_ptr() as usize - line.as_ptr() as usize + 1;
+                        errors.push(format!(
+                            "Line {}, column {}: {} ({})",
+                            i + 1,
+                            column,
+                            word,
+                            e
+                        ));
+                    }
+                }
             } else {
                 tokens.push(ParserToken::Identifier(word.to_string()));
             }
         }
     }
 
-    Ok(tokens)
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors.join("\n"))
+    }
 }
 
 enum Block {
@@ -257,3 +277,32 @@ pub fn assemble(tokens: Vec<ParserToken>) -> Result<Vec<u8>, String> {
     let instructions = flatten_blocks(blocks);
     Ok(instructions.iter().map(|i| i.encode().unwrap()).collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lexes_literals() -> Result<(), String> {
+        let tokens = lex_str("0x10 0b11 1_000 'a' -1 # 12ab\nlabel: -0x8000")?;
+        let numbers: Vec<u16> = tokens
+            .iter()
+            .filter_map(|t| match t {
+                ParserToken::Number(n) => Some(*n),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(numbers, vec![16, 3, 1000, 97, 0xFFFF, 0x8000]);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_out_of_range_literals() {
+        let error = lex_str("push\n  1 65536").unwrap_err();
+        assert_eq!(
+            error,
+            "Line 2, column 5: 65536 (literal doesn't fit in a 16-bit word)"
+        );
+        assert!(lex_str("0xFG").is_err());
+    }
+}
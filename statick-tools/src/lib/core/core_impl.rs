@@ -2,7 +2,7 @@ use super::{Condition, ControllerMessage, CoreMessage, ExecutionUnit, Flags};
 use crate::isa::{FunctionOp, Instruction, Op, ProcessOp, StackOp};
 use crate::memory::WordIO;
 use crate::process::{CallStack, Process, ValueStack};
-use std::ops::{BitAnd, BitOr, BitXor, Shl, Shr};
+use std::ops::{BitAnd, BitOr, BitXor};
 
 #[derive(Default)]
 pub struct Core {
@@ -150,22 +150,36 @@ impl Core {
         match op {
             Op::Add => set_flags_overflow_push!(overflowing_add),
             Op::Sub => set_flags_overflow_push!(overflowing_sub),
+            // Shifting by the width of a word or more shifts out every bit, rather than wrapping
+            // the shift amount.
             Op::ArithmeticShiftLeft => {
                 // Rust generates arithmetic shifts for signed integers and logical shifts for
                 // unsigned integers, hence the need for casting here.
-                let b = memory.stack_pop()? as i16;
+                let b = memory.stack_pop()?;
                 let a = memory.stack_pop()? as i16;
-                let result = set_zero_and_sign_flags!(a << b);
+                let result = set_zero_and_sign_flags!(a.checked_shl(b.into()).unwrap_or(0));
                 memory.stack_push(result)?;
             }
             Op::ArithmeticShiftRight => {
-                let b = memory.stack_pop()? as i16;
+                let b = memory.stack_pop()?;
                 let a = memory.stack_pop()? as i16;
-                let result = set_zero_and_sign_flags!(a >> b);
+                let result = set_zero_and_sign_flags!(a
+                    .checked_shr(b.into())
+                    .unwrap_or(if a < 0 { -1 } else { 0 }));
+                memory.stack_push(result)?;
+            }
+            Op::LogicalShiftLeft => {
+                let b = memory.stack_pop()?;
+                let a = memory.stack_pop()?;
+                let result = set_zero_and_sign_flags!(a.checked_shl(b.into()).unwrap_or(0));
+                memory.stack_push(result)?;
+            }
+            Op::LogicalShiftRight => {
+                let b = memory.stack_pop()?;
+                let a = memory.stack_pop()?;
+                let result = set_zero_and_sign_flags!(a.checked_shr(b.into()).unwrap_or(0));
                 memory.stack_push(result)?;
             }
-            Op::LogicalShiftLeft => do_op_push!(shl),
-            Op::LogicalShiftRight => do_op_push!(shr),
             Op::LogicalNot => {
                 let a = memory.stack_pop()?;
                 let result = set_zero_and_sign_flags!(!a);
@@ -280,10 +294,18 @@ impl Core {
                 Ok(CoreMessage::Send(channel, message))
             }
             ProcessOp::CreateChannel => Ok(CoreMessage::CreateChannel),
+            ProcessOp::CreateBufferedChannel => {
+                let capacity = memory.stack_pop()?;
+                Ok(CoreMessage::CreateBufferedChannel(capacity))
+            }
             ProcessOp::DestroyChannel => {
                 let channel = memory.stack_pop()?;
                 Ok(CoreMessage::DeleteChannel(channel))
             }
+            ProcessOp::ShareChannel => {
+                let channel = memory.stack_pop()?;
+                Ok(CoreMessage::ShareChannel(channel))
+            }
             ProcessOp::Receive => {
                 let channel = memory.stack_peek(0)?;
                 Ok(CoreMessage::Receive(channel))
@@ -308,6 +330,15 @@ impl Core {
                     self.has_alternation_value,
                 ))
             }
+            ProcessOp::EnableTimer => {
+                let cycles = memory.stack_pop()?;
+                Ok(CoreMessage::EnableTimer(cycles))
+            }
+            ProcessOp::DisableTimer => {
+                let dest = memory.stack_pop()?;
+                let cycles = memory.stack_pop()?;
+                Ok(CoreMessage::DisableTimer(cycles, dest))
+            }
         }
     }
 
@@ -854,6 +885,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn shifts() -> Result<(), String> {
+        let is = assemble(lex_str(
+            "3 2 lsl 65535 12 lsr 65528 1 asr 65535 16 asr 1 16 lsl .",
+        )?)?;
+        let mut memory = MemoryCell::new(0);
+        let mut core = Core::default();
+        core.run(&is, &mut memory, true)?;
+        assert_eq!(memory.stack_size()?, 5);
+        assert_eq!(memory.stack_peek(4)?, 12);
+        assert_eq!(memory.stack_peek(3)?, 15);
+        assert_eq!(memory.stack_peek(2)?, 65532);
+        assert_eq!(memory.stack_peek(1)?, 65535);
+        assert_eq!(memory.stack_peek(0)?, 0);
+        Ok(())
+    }
+
     #[test]
     fn read_local_by_offset() -> Result<(), String> {
         let is = assemble(lex_str("1 0 get + .")?)?;
@@ -7,7 +7,9 @@ pub enum CoreMessage {
     Yield,
     Halt,
     CreateChannel,
+    CreateBufferedChannel(u16), // Capacity
     DeleteChannel(Channel),
+    ShareChannel(Channel),
     Send(Channel, u16),
     Receive(Channel),
     AlternationStart,
@@ -15,6 +17,8 @@ pub enum CoreMessage {
     AlternationEnd,
     EnableChannel(Channel),
     DisableChannel(Channel, u16, bool),
+    EnableTimer(u16),       // Number of cycles after the start of the alternation
+    DisableTimer(u16, u16), // Number of cycles and jump destination
 }
 
 #[allow(dead_code)]
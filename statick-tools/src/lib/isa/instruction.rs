@@ -49,6 +49,16 @@ pub enum ProcessOp {
     DestroyChannel = 10,
     // i.e. end the process but don't destroy it
     Yield = 11,
+    // Timer guards for alternations, which become ready a number of cycles after the alternation
+    // started. These are used in the same way as EnableChannel and DisableChannel.
+    EnableTimer = 12,
+    DisableTimer = 13,
+    // Pops a capacity, and creates a channel that holds up to that many values before a sender
+    // blocks
+    CreateBufferedChannel = 14,
+    // Pops a channel, and counts one more reference to it. A channel is only freed once it has
+    // been destroyed as many times as it was created and shared.
+    ShareChannel = 15,
 }
 
 impl ProcessOp {
@@ -57,7 +67,7 @@ impl ProcessOp {
     // TODO: Abstract this out as a macro at some point
     pub fn decode(raw: u8) -> Result<ProcessOp, ()> {
         // NOTE: Must be kept in sync with ProcessOp definition
-        if raw <= 11 {
+        if raw <= 15 {
             return Ok(unsafe { transmute(raw) });
         }
         Err(())
@@ -197,7 +207,7 @@ impl Instruction {
         })
     }
 
-    fn fits_immediate(value: u8) -> bool {
+    pub fn fits_immediate(value: u8) -> bool {
         value & 0x0F == value
     }
 
@@ -277,6 +287,10 @@ impl FromStr for Instruction {
             "enable" => Ok(Instruction::Process(ProcessOp::EnableChannel)),
             "disable" => Ok(Instruction::Process(ProcessOp::DisableChannel)),
             "yield" => Ok(Instruction::Process(ProcessOp::Yield)),
+            "enabletimer" => Ok(Instruction::Process(ProcessOp::EnableTimer)),
+            "disabletimer" => Ok(Instruction::Process(ProcessOp::DisableTimer)),
+            "chanbuf" => Ok(Instruction::Process(ProcessOp::CreateBufferedChannel)),
+            "share" => Ok(Instruction::Process(ProcessOp::ShareChannel)),
             _ => Err(()),
         }
     }
@@ -337,6 +351,10 @@ impl fmt::Display for Instruction {
             Instruction::Process(ProcessOp::EnableChannel) => write!(f, "enable"),
             Instruction::Process(ProcessOp::DisableChannel) => write!(f, "disable"),
             Instruction::Process(ProcessOp::Yield) => write!(f, "yield"),
+            Instruction::Process(ProcessOp::EnableTimer) => write!(f, "enabletimer"),
+            Instruction::Process(ProcessOp::DisableTimer) => write!(f, "disabletimer"),
+            Instruction::Process(ProcessOp::CreateBufferedChannel) => write!(f, "chanbuf"),
+            Instruction::Process(ProcessOp::ShareChannel) => write!(f, "share"),
             _ => Err(fmt::Error::default()),
         }
     }
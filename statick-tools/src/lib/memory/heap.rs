@@ -1,11 +1,16 @@
 use crate::memory::WordIO;
+use std::collections::HashMap;
 
+/**
+ * Allocates blocks from a region of memory. Freed blocks are kept on a free list for their size, so
+ * they can only be reused by an allocation of the same size.
+ */
 #[derive(Debug)]
 pub struct Heap {
     heap_base: u16,
     heap_end: u16,
     heap_max: u16,
-    heap_free: u16,
+    free_lists: HashMap<u16, u16>,
     alloc_size: u16,
 }
 
@@ -18,28 +23,45 @@ impl Heap {
             heap_base,
             heap_end: heap_base,
             heap_max,
-            heap_free: 0,
+            free_lists: HashMap::new(),
             alloc_size,
         }
     }
 
     pub fn alloc(&mut self, memory: &WordIO) -> Result<u16, String> {
-        if self.heap_end + self.alloc_size <= self.heap_max {
+        self.alloc_sized(memory, self.alloc_size)
+    }
+
+    pub fn free(&mut self, memory: &mut WordIO, addr: u16) -> Result<(), String> {
+        self.free_sized(memory, addr, self.alloc_size)
+    }
+
+    /// Allocates `size` bytes, which must be at least two so that the block can be freed
+    pub fn alloc_sized(&mut self, memory: &dyn WordIO, size: u16) -> Result<u16, String> {
+        assert!(size >= 2);
+        let free = self.free_lists.get(&size).cloned().unwrap_or(0);
+        if u32::from(self.heap_end) + u32::from(size) <= u32::from(self.heap_max) {
             let addr = self.heap_end;
-            self.heap_end += self.alloc_size;
-            Ok(addr)
-        } else if self.heap_free != 0 {
-            let addr = self.heap_free;
-            self.heap_free = memory.read_word(self.heap_free)?;
+            self.heap_end += size;
             Ok(addr)
+        } else if free != 0 {
+            self.free_lists.insert(size, memory.read_word(free)?);
+            Ok(free)
         } else {
             Err("Out of memory".to_string())
         }
     }
 
-    pub fn free(&mut self, memory: &mut WordIO, addr: u16) -> Result<(), String> {
-        memory.write_word(addr, self.heap_free)?;
-        self.heap_free = addr;
+    /// Frees a block, which must have been allocated with the same size
+    pub fn free_sized(
+        &mut self,
+        memory: &mut dyn WordIO,
+        addr: u16,
+        size: u16,
+    ) -> Result<(), String> {
+        let free = self.free_lists.get(&size).cloned().unwrap_or(0);
+        memory.write_word(addr, free)?;
+        self.free_lists.insert(size, addr);
         Ok(())
     }
 }
@@ -66,4 +88,19 @@ mod heap_tests {
         let _addr = heap.alloc(&memory)?;
         Ok(())
     }
+
+    #[test]
+    fn freed_blocks_are_reused_by_allocations_of_the_same_size() -> Result<(), String> {
+        let mut memory = MemoryCell::default();
+        let mut heap = Heap::new(2, 14, 4);
+        let small = heap.alloc(&memory)?;
+        let large = heap.alloc_sized(&memory, 8)?;
+        assert!(heap.alloc(&memory).is_err());
+        heap.free_sized(&mut memory, large, 8)?;
+        assert!(heap.alloc(&memory).is_err());
+        assert_eq!(heap.alloc_sized(&memory, 8)?, large);
+        heap.free(&mut memory, small)?;
+        assert_eq!(heap.alloc(&memory)?, small);
+        Ok(())
+    }
 }
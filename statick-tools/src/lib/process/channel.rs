@@ -3,6 +3,7 @@ use super::NO_PROCESS;
 pub struct Channel {
     pub pid: u16,
     pub in_alternation: bool,
+    pub is_sender: bool,
 }
 
 impl Channel {
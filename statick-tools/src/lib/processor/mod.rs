@@ -4,6 +4,9 @@ use crate::process;
 use crate::process::{ValueStack, NO_PROCESS};
 use std::collections::{HashMap, HashSet};
 
+/// The most values that a buffered channel can hold while still fitting in a memory cell
+pub const MAX_CHANNEL_CAPACITY: u16 = (MEMORY_CELL_SIZE - 12) / 2;
+
 pub struct Processor {
     cycle_count: u32,
     cores: Vec<Core>,
@@ -15,6 +18,12 @@ pub struct Processor {
     has_instructions: bool,
     alternation_set: HashSet<u16>,
     alternation_ready_set: HashSet<u16>,
+    /// The cycle on which each process in an alternation started it
+    alternation_start_cycles: HashMap<u16, u32>,
+    /// The earliest cycle on which a timer guard of each process in an alternation becomes ready
+    timer_deadlines: HashMap<u16, u32>,
+    /// The channels that each process in an alternation has enabled
+    alternation_channels: HashMap<u16, Vec<Channel>>,
 }
 
 impl Default for Processor {
@@ -34,13 +43,15 @@ enum SchedulerMessage {
     Schedule(u16),
     Deschedule(u16),
     Destroy(u16),
+    Yield(u16),
 }
 
 /// For a processor with N cores and M memory cells of size K:
 ///   - The first N memory cells are used for instruction caches for each core
 ///   - The last memory cell (M-1) is used for storing metadata:
-///         - Bytes [0..M) of the cell are used for storing process metadata
-///         - Bytes [M..K) are used for storing channels (4 bytes each)
+///     - Bytes [0..M) of the cell are used for storing process metadata
+///     - The next 4M bytes hold the process queued behind each waiting sender, and its value
+///     - The rest of the cell is used for storing channels
 ///   - Memory cells [N..M-1) are used for storing process data
 impl Processor {
     pub fn new(core_count: u16, cell_count: u16) -> Processor {
@@ -61,11 +72,18 @@ impl Processor {
         }
         let has_instructions = false;
         let cycle_count = 0;
-        let channel_heap = Heap::new(cell_count, MEMORY_CELL_SIZE, 4);
+        let channel_heap = Heap::new(
+            Processor::waiting_sender_base(cell_count) + 4 * cell_count,
+            MEMORY_CELL_SIZE,
+            8,
+        );
         let current_pid_to_alloc_number = HashMap::new();
         let final_stacks = HashMap::new();
         let alternation_set = HashSet::new();
         let alternation_ready_set = HashSet::new();
+        let alternation_start_cycles = HashMap::new();
+        let timer_deadlines = HashMap::new();
+        let alternation_channels = HashMap::new();
 
         Processor {
             has_instructions,
@@ -78,6 +96,9 @@ impl Processor {
             final_stacks,
             alternation_set,
             alternation_ready_set,
+            alternation_start_cycles,
+            timer_deadlines,
+            alternation_channels,
         }
     }
 
@@ -125,6 +146,11 @@ impl Processor {
         self.cells.len() - 1
     }
 
+    /// The first byte after the process metadata, rounded up so that words are aligned
+    fn waiting_sender_base(cell_count: u16) -> u16 {
+        (cell_count + 1) & !1
+    }
+
     fn channel_cell_index(&self) -> usize {
         // This can be shared with the |meta_cell_index|
         self.cells.len() - 1
@@ -167,8 +193,8 @@ impl Processor {
                 "################ CYCLE {} ################",
                 self.cycle_count
             );
-            self.cycle_count += 1;
         }
+        self.cycle_count += 1;
         let mut messages = Vec::new();
         let mut channel_messages = HashMap::new();
         let mut channel_listeners = HashSet::new();
@@ -186,12 +212,14 @@ impl Processor {
                 if verbose {
                     println!(" -> {:?}", message);
                 }
-                // For same-cycle channel delivery
+                // For same-cycle channel delivery, which would overtake any values held in a buffer or
+                // senders already waiting on the channel. Of several senders on the same cycle, the
+                // one on the lowest core is delivered and the rest queue in the order of their cores.
                 match message {
-                    CoreMessage::Send(chan, val) => {
-                        channel_messages.insert(chan, val);
+                    CoreMessage::Send(chan, val) if self.is_idle_unbuffered_channel(chan)? => {
+                        channel_messages.entry(chan).or_insert((core, val));
                     }
-                    CoreMessage::Receive(chan) => {
+                    CoreMessage::Receive(chan) if self.is_idle_unbuffered_channel(chan)? => {
                         channel_listeners.insert(chan);
                     }
                     _ => {}
@@ -216,7 +244,7 @@ impl Processor {
                 match msg {
                     CoreMessage::Yield => {
                         message_to_return = Some(ControllerMessage::SaveToMemory);
-                        scheduler_tasks.push(SchedulerMessage::Deschedule(pid));
+                        scheduler_tasks.push(SchedulerMessage::Yield(pid));
                     }
                     CoreMessage::Halt => {
                         message_to_return = Some(ControllerMessage::SaveToMemory);
@@ -239,16 +267,23 @@ impl Processor {
                         self.cells[pid as usize].stack_pop_many(num_words)?;
                     }
                     CoreMessage::CreateChannel => {
-                        let addr = self.create_channel()?;
+                        let addr = self.create_channel(0)?;
+                        message_to_return = Some(ControllerMessage::CreatedChannel(addr));
+                    }
+                    CoreMessage::CreateBufferedChannel(capacity) => {
+                        let addr = self.create_channel(*capacity)?;
                         message_to_return = Some(ControllerMessage::CreatedChannel(addr));
                     }
                     CoreMessage::DeleteChannel(channel) => {
-                        let chan_idx = self.channel_cell_index();
-                        self.channel_heap
-                            .free(&mut self.cells[chan_idx], *channel)?;
+                        self.delete_channel(*channel)?;
+                    }
+                    CoreMessage::ShareChannel(channel) => {
+                        self.share_channel(*channel)?;
                     }
                     CoreMessage::Send(channel, value) => {
-                        if !channel_listeners.contains(channel) {
+                        let delivered = channel_listeners.contains(channel)
+                            && channel_messages[channel].0 == core;
+                        if !delivered {
                             // No other process core is currently listening on this channel so we
                             // must write its value to memory and continue
                             message_to_return = Some(ControllerMessage::SaveToMemory);
@@ -258,16 +293,18 @@ impl Processor {
                     }
                     CoreMessage::Receive(channel) => {
                         // This allows for same cycle message delivery between processor cores
-                        if let Some(val) = channel_messages.get(channel) {
+                        if let Some((_, val)) = channel_messages.get(channel) {
                             message_to_return = Some(ControllerMessage::Receive(*channel, *val));
                         } else {
                             // Otherwise we have to schedule in a save
                             message_to_return = Some(ControllerMessage::SaveToMemory);
-                            scheduler_tasks.push(self.receive(*channel, pid)?);
+                            let mut tasks = self.receive(*channel, pid)?;
+                            scheduler_tasks.append(&mut tasks);
                         }
                     }
                     CoreMessage::AlternationStart => {
                         self.alternation_set.insert(pid);
+                        self.alternation_start_cycles.insert(pid, self.cycle_count);
                     }
                     CoreMessage::AlternationWait => {
                         if !self.alternation_ready_set.contains(&pid) {
@@ -275,18 +312,28 @@ impl Processor {
                         }
                     }
                     CoreMessage::AlternationEnd => {
-                        self.alternation_set.remove(&pid);
-                        self.alternation_ready_set.remove(&pid);
+                        self.end_alternation(pid)?;
                     }
                     CoreMessage::EnableChannel(channel) => {
                         self.enable_channel(*channel, pid)?;
                     }
                     CoreMessage::DisableChannel(channel, jump_dest, has_alternation_value) => {
-                        if let Some(msg) =
+                        if let Some(mut tasks) =
                             self.disable_channel(*channel, pid, *has_alternation_value)?
                         {
+                            // Jumping to the guarded process skips the alternation end
+                            self.end_alternation(pid)?;
+                            message_to_return = Some(ControllerMessage::Jump(*jump_dest));
+                            scheduler_tasks.append(&mut tasks);
+                        }
+                    }
+                    CoreMessage::EnableTimer(cycles) => {
+                        self.enable_timer(*cycles, pid);
+                    }
+                    CoreMessage::DisableTimer(cycles, jump_dest) => {
+                        if self.timer_has_expired(*cycles, pid) {
+                            self.end_alternation(pid)?;
                             message_to_return = Some(ControllerMessage::Jump(*jump_dest));
-                            scheduler_tasks.push(msg);
                         }
                     }
                     CoreMessage::Nothing => {}
@@ -302,6 +349,20 @@ impl Processor {
             }
         }
 
+        // Wake any process waiting in an alternation whose timer has expired
+        let expired: Vec<u16> = self
+            .timer_deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= self.cycle_count)
+            .map(|(pid, _)| *pid)
+            .collect();
+        for pid in expired {
+            self.timer_deadlines.remove(&pid);
+            if self.alternation_ready_set.insert(pid) {
+                scheduler_tasks.push(SchedulerMessage::Schedule(pid));
+            }
+        }
+
         let mut inactive_cores: Vec<u16> = Vec::new();
         for core in 0..(self.cores.len() as u16) {
             if !self.core_to_process.contains_key(&core) {
@@ -334,6 +395,10 @@ impl Processor {
                 SchedulerMessage::Deschedule(pid) => {
                     deschedule(*pid, process::State::Inactive)?;
                 }
+                // A process that yields can run again as soon as there is a free core
+                SchedulerMessage::Yield(pid) => {
+                    deschedule(*pid, process::State::Waiting)?;
+                }
                 _ => {}
             }
         }
@@ -357,7 +422,18 @@ impl Processor {
 
         // Schedule processes if there are still cores available
         if !inactive_cores.is_empty() {
-            for pid in self.first_process_index()..self.last_process_index() {
+            // Processes that have just yielded go behind every other waiting process
+            let yielded: Vec<u16> = scheduler_tasks
+                .iter()
+                .filter_map(|task| match task {
+                    SchedulerMessage::Yield(pid) => Some(*pid),
+                    _ => None,
+                })
+                .collect();
+            let pids = (self.first_process_index()..self.last_process_index())
+                .filter(|pid| !yielded.contains(pid))
+                .chain(yielded.iter().cloned());
+            for pid in pids {
                 let state = self.get_process_state(pid)?;
                 if state == process::State::Waiting {
                     match inactive_cores.pop() {
@@ -373,7 +449,8 @@ impl Processor {
             }
         }
 
-        if self.core_to_process.is_empty() {
+        // A process waiting on a timer will be woken even if nothing else is running
+        if self.core_to_process.is_empty() && self.timer_deadlines.is_empty() {
             Ok(State::Halted)
         } else {
             Ok(State::Running)
@@ -383,11 +460,13 @@ impl Processor {
     fn get_channel_process(&self, channel: Channel) -> Result<process::Channel, String> {
         let chan_cell_idx = self.channel_cell_index();
         let word = self.cells[chan_cell_idx].read_word(channel)?;
-        let pid = word & 0x7FFF;
+        let pid = word & 0x3FFF;
         let in_alternation = word & 0x8000 != 0;
+        let is_sender = word & 0x4000 != 0;
         Ok(process::Channel {
             pid,
             in_alternation,
+            is_sender,
         })
     }
 
@@ -402,7 +481,7 @@ impl Processor {
         pid: u16,
         in_alternation: bool,
     ) -> Result<(), String> {
-        assert!(pid & 0x8000 == 0);
+        assert!(pid & 0xC000 == 0);
         let chan_cell_idx = self.channel_cell_index();
         let mut word = pid;
         if in_alternation {
@@ -412,32 +491,208 @@ impl Processor {
         Ok(())
     }
 
+    /// Marks a process as waiting to send the channel's value. Any senders queued behind it are
+    /// kept.
+    fn set_channel_sender(
+        &mut self,
+        channel: Channel,
+        pid: u16,
+        in_alternation: bool,
+    ) -> Result<(), String> {
+        assert!(pid & 0xC000 == 0);
+        self.set_channel_process(channel, pid, in_alternation)?;
+        let chan_cell_idx = self.channel_cell_index();
+        let word = self.cells[chan_cell_idx].read_word(channel)?;
+        self.cells[chan_cell_idx].write_word(channel, word | 0x4000)
+    }
+
+    /// Makes a sender the one waiting on the channel, with no senders queued behind it yet
+    fn wait_to_send(
+        &mut self,
+        channel: Channel,
+        tx_pid: u16,
+        in_alternation: bool,
+    ) -> Result<(), String> {
+        let meta_cell_idx = self.meta_cell_index();
+        let record = self.waiting_sender_record(tx_pid);
+        self.cells[meta_cell_idx].write_word(record, NO_PROCESS)?;
+        self.set_channel_sender(channel, tx_pid, in_alternation)
+    }
+
+    /// Where the process queued behind `pid` and the value that `pid` is sending are kept
+    fn waiting_sender_record(&self, pid: u16) -> u16 {
+        Processor::waiting_sender_base(self.cells.len() as u16) + 4 * pid
+    }
+
+    /// Queues a sender behind the last of those already waiting on the channel
+    fn queue_sender(
+        &mut self,
+        channel: Channel,
+        value: u16,
+        tx_pid: u16,
+    ) -> Result<Vec<SchedulerMessage>, String> {
+        let meta_cell_idx = self.meta_cell_index();
+        let record = self.waiting_sender_record(tx_pid);
+        self.cells[meta_cell_idx].write_word(record, NO_PROCESS)?;
+        self.cells[meta_cell_idx].write_word(record + 2, value)?;
+        let mut last = self.get_channel_process(channel)?.pid;
+        loop {
+            let next = self.cells[meta_cell_idx].read_word(self.waiting_sender_record(last))?;
+            if next == NO_PROCESS {
+                break;
+            }
+            last = next;
+        }
+        let last_record = self.waiting_sender_record(last);
+        self.cells[meta_cell_idx].write_word(last_record, tx_pid)?;
+        Ok(vec![SchedulerMessage::Deschedule(tx_pid)])
+    }
+
+    /// Replaces the sender that was waiting on the channel with the next queued sender, if any
+    fn next_sender(&mut self, channel: Channel) -> Result<(), String> {
+        let meta_cell_idx = self.meta_cell_index();
+        let sender = self.get_channel_process(channel)?.pid;
+        let next = self.cells[meta_cell_idx].read_word(self.waiting_sender_record(sender))?;
+        if next == NO_PROCESS {
+            return self.set_channel_process(channel, NO_PROCESS, false);
+        }
+        let value = self.cells[meta_cell_idx].read_word(self.waiting_sender_record(next) + 2)?;
+        self.set_channel_value(channel, value)?;
+        self.set_channel_sender(channel, next, false)
+    }
+
+    fn is_idle_unbuffered_channel(&self, channel: Channel) -> Result<bool, String> {
+        Ok(self.channel_capacity(channel)? == 0 && self.get_channel_process(channel)?.is_empty())
+    }
+
     fn set_channel_value(&mut self, channel: Channel, value: u16) -> Result<(), String> {
         let chan_cell_idx = self.channel_cell_index();
         self.cells[chan_cell_idx].write_word(channel + 2, value)
     }
 
-    fn create_channel(&mut self) -> Result<Channel, String> {
+    /**
+     * Every channel holds the process waiting on it, the value being sent, its capacity, and how
+     * many references to it haven't yet been destroyed. A channel with a non-zero capacity is
+     * followed by the number of values it holds, the index of the oldest value, and a circular
+     * buffer of values.
+     */
+    fn channel_size(capacity: u16) -> Result<u16, String> {
+        if capacity == 0 {
+            Ok(8)
+        } else if capacity <= MAX_CHANNEL_CAPACITY {
+            Ok(12 + 2 * capacity)
+        } else {
+            Err(format!("A channel can't hold {} values", capacity))
+        }
+    }
+
+    fn create_channel(&mut self, capacity: u16) -> Result<Channel, String> {
         let chan_cell_idx = self.channel_cell_index();
-        let channel = self.channel_heap.alloc(&self.cells[chan_cell_idx])?;
+        let size = Processor::channel_size(capacity)?;
+        let channel = self
+            .channel_heap
+            .alloc_sized(&self.cells[chan_cell_idx], size)?;
         self.cells[chan_cell_idx].write_word(channel, NO_PROCESS)?;
         self.cells[chan_cell_idx].write_word(channel + 2, 0)?;
+        self.cells[chan_cell_idx].write_word(channel + 4, capacity)?;
+        self.cells[chan_cell_idx].write_word(channel + 6, 1)?;
+        if capacity > 0 {
+            self.cells[chan_cell_idx].write_word(channel + 8, 0)?;
+            self.cells[chan_cell_idx].write_word(channel + 10, 0)?;
+        }
         Ok(channel)
     }
 
+    fn share_channel(&mut self, channel: Channel) -> Result<(), String> {
+        let chan_cell_idx = self.channel_cell_index();
+        let references = self.cells[chan_cell_idx].read_word(channel + 6)?;
+        self.cells[chan_cell_idx].write_word(channel + 6, references + 1)
+    }
+
+    /// Frees the channel once every reference to it has been destroyed
+    fn delete_channel(&mut self, channel: Channel) -> Result<(), String> {
+        let chan_cell_idx = self.channel_cell_index();
+        let references = self.cells[chan_cell_idx].read_word(channel + 6)?;
+        if references > 1 {
+            return self.cells[chan_cell_idx].write_word(channel + 6, references - 1);
+        }
+        // A sender holds a reference, so only a program written by hand can get here
+        let process = self.get_channel_process(channel)?;
+        if !process.is_empty() && process.is_sender {
+            return Err(format!(
+                "Channel {} was deleted while process {} was waiting to send on it",
+                channel, process.pid
+            ));
+        }
+        let size = Processor::channel_size(self.channel_capacity(channel)?)?;
+        self.channel_heap
+            .free_sized(&mut self.cells[chan_cell_idx], channel, size)
+    }
+
+    fn channel_capacity(&self, channel: Channel) -> Result<u16, String> {
+        let chan_cell_idx = self.channel_cell_index();
+        self.cells[chan_cell_idx].read_word(channel + 4)
+    }
+
+    fn buffered_count(&self, channel: Channel) -> Result<u16, String> {
+        let chan_cell_idx = self.channel_cell_index();
+        self.cells[chan_cell_idx].read_word(channel + 8)
+    }
+
+    fn enqueue(&mut self, channel: Channel, value: u16) -> Result<(), String> {
+        let chan_cell_idx = self.channel_cell_index();
+        let capacity = self.channel_capacity(channel)?;
+        let count = self.buffered_count(channel)?;
+        let head = self.cells[chan_cell_idx].read_word(channel + 10)?;
+        assert!(count < capacity);
+        let slot = (head + count) % capacity;
+        self.cells[chan_cell_idx].write_word(channel + 12 + 2 * slot, value)?;
+        self.cells[chan_cell_idx].write_word(channel + 8, count + 1)
+    }
+
+    fn dequeue(&mut self, channel: Channel) -> Result<u16, String> {
+        let chan_cell_idx = self.channel_cell_index();
+        let capacity = self.channel_capacity(channel)?;
+        let count = self.buffered_count(channel)?;
+        let head = self.cells[chan_cell_idx].read_word(channel + 10)?;
+        assert!(count > 0);
+        let value = self.cells[chan_cell_idx].read_word(channel + 12 + 2 * head)?;
+        self.cells[chan_cell_idx].write_word(channel + 10, (head + 1) % capacity)?;
+        self.cells[chan_cell_idx].write_word(channel + 8, count - 1)?;
+        Ok(value)
+    }
+
+    /// Moves the value of a sender that was waiting for space in the buffer into it
+    fn admit_waiting_sender(&mut self, channel: Channel) -> Result<Vec<SchedulerMessage>, String> {
+        let tx_proc = self.get_channel_process(channel)?;
+        if !tx_proc.is_sender {
+            return Ok(vec![]);
+        }
+        let value = self.get_channel_value(channel)?;
+        self.enqueue(channel, value)?;
+        self.next_sender(channel)?;
+        Ok(vec![SchedulerMessage::Schedule(tx_proc.pid)])
+    }
+
     fn send(
         &mut self,
         channel: Channel,
         message: u16,
         tx_pid: u16,
     ) -> Result<Vec<SchedulerMessage>, String> {
-        self.set_channel_value(channel, message)?;
+        if self.channel_capacity(channel)? > 0 {
+            return self.send_buffered(channel, message, tx_pid);
+        }
         let rx_proc = self.get_channel_process(channel)?;
+        if rx_proc.is_sender {
+            return self.queue_sender(channel, message, tx_pid);
+        }
+        self.set_channel_value(channel, message)?;
         if rx_proc.is_empty() {
-            self.set_channel_process(channel, tx_pid, false)?;
+            self.wait_to_send(channel, tx_pid, false)?;
             Ok(vec![SchedulerMessage::Deschedule(tx_pid)])
         } else if rx_proc.in_alternation {
-            self.set_channel_process(channel, tx_pid, true)?;
+            self.wait_to_send(channel, tx_pid, true)?;
             if !self.alternation_ready_set.contains(&rx_proc.pid) {
                 // This is the first message to reach the receiving process
                 self.alternation_ready_set.insert(rx_proc.pid);
@@ -460,20 +715,76 @@ impl Processor {
         }
     }
 
-    fn receive(&mut self, channel: Channel, rx_pid: u16) -> Result<SchedulerMessage, String> {
+    /// A sender only waits once the buffer is full
+    fn send_buffered(
+        &mut self,
+        channel: Channel,
+        message: u16,
+        tx_pid: u16,
+    ) -> Result<Vec<SchedulerMessage>, String> {
+        let rx_proc = self.get_channel_process(channel)?;
+        if rx_proc.is_sender {
+            return self.queue_sender(channel, message, tx_pid);
+        }
+        let count = self.buffered_count(channel)?;
+        if count == 0 && !rx_proc.is_empty() && !rx_proc.in_alternation {
+            self.cells[rx_proc.pid as usize].stack_push(message)?;
+            self.set_channel_process(channel, NO_PROCESS, false)?;
+            Ok(vec![SchedulerMessage::Schedule(rx_proc.pid)])
+        } else if count < self.channel_capacity(channel)? {
+            self.enqueue(channel, message)?;
+            // As for an unbuffered channel, the alternation is woken by the first value
+            if rx_proc.in_alternation && self.alternation_ready_set.insert(rx_proc.pid) {
+                Ok(vec![SchedulerMessage::Schedule(rx_proc.pid)])
+            } else {
+                Ok(vec![])
+            }
+        } else {
+            self.set_channel_value(channel, message)?;
+            self.wait_to_send(channel, tx_pid, false)?;
+            Ok(vec![SchedulerMessage::Deschedule(tx_pid)])
+        }
+    }
+
+    fn receive(&mut self, channel: Channel, rx_pid: u16) -> Result<Vec<SchedulerMessage>, String> {
+        if self.channel_capacity(channel)? > 0 {
+            return self.receive_buffered(channel, rx_pid);
+        }
         let tx_proc = self.get_channel_process(channel)?;
         if tx_proc.is_empty() {
             self.set_channel_process(channel, rx_pid, false)?;
-            Ok(SchedulerMessage::Deschedule(rx_pid))
+            Ok(vec![SchedulerMessage::Deschedule(rx_pid)])
         } else {
             let message = self.get_channel_value(channel)?;
-            self.set_channel_process(channel, NO_PROCESS, false)?;
+            self.next_sender(channel)?;
             self.cells[rx_pid as usize].stack_push(message)?;
-            Ok(SchedulerMessage::Schedule(tx_proc.pid))
+            Ok(vec![SchedulerMessage::Schedule(tx_proc.pid)])
         }
     }
 
+    fn receive_buffered(
+        &mut self,
+        channel: Channel,
+        rx_pid: u16,
+    ) -> Result<Vec<SchedulerMessage>, String> {
+        if self.buffered_count(channel)? == 0 {
+            self.set_channel_process(channel, rx_pid, false)?;
+            return Ok(vec![SchedulerMessage::Deschedule(rx_pid)]);
+        }
+        let message = self.dequeue(channel)?;
+        self.cells[rx_pid as usize].stack_push(message)?;
+        self.admit_waiting_sender(channel)
+    }
+
     fn enable_channel(&mut self, channel: Channel, rx_pid: u16) -> Result<(), String> {
+        self.alternation_channels
+            .entry(rx_pid)
+            .or_default()
+            .push(channel);
+        if self.channel_capacity(channel)? > 0 && self.buffered_count(channel)? > 0 {
+            self.alternation_ready_set.insert(rx_pid);
+            return Ok(());
+        }
         let tx_proc = self.get_channel_process(channel)?;
         if tx_proc.is_empty() {
             // No sending process has yet sent to this channel, so we therefore mark that this
@@ -495,7 +806,10 @@ impl Processor {
         channel: Channel,
         rx_pid: u16,
         has_alternation_value: bool,
-    ) -> Result<Option<SchedulerMessage>, String> {
+    ) -> Result<Option<Vec<SchedulerMessage>>, String> {
+        if self.channel_capacity(channel)? > 0 {
+            return self.disable_buffered_channel(channel, rx_pid, has_alternation_value);
+        }
         let tx_proc = self.get_channel_process(channel)?;
         if tx_proc.is_empty() {
             Ok(None)
@@ -508,13 +822,62 @@ impl Processor {
             // Received a message on the channel from some other process
             let value = self.get_channel_value(channel)?;
             self.cells[rx_pid as usize].stack_push(value)?;
-            self.set_channel_process(channel, NO_PROCESS, false)?;
-            Ok(Some(SchedulerMessage::Schedule(tx_proc.pid)))
+            self.next_sender(channel)?;
+            Ok(Some(vec![SchedulerMessage::Schedule(tx_proc.pid)]))
         } else {
             Ok(None)
         }
     }
 
+    fn disable_buffered_channel(
+        &mut self,
+        channel: Channel,
+        rx_pid: u16,
+        has_alternation_value: bool,
+    ) -> Result<Option<Vec<SchedulerMessage>>, String> {
+        let proc = self.get_channel_process(channel)?;
+        if proc.pid == rx_pid && !proc.is_sender {
+            self.set_channel_process(channel, NO_PROCESS, false)?;
+        }
+        if has_alternation_value || self.buffered_count(channel)? == 0 {
+            return Ok(None);
+        }
+        let value = self.dequeue(channel)?;
+        self.cells[rx_pid as usize].stack_push(value)?;
+        Ok(Some(self.admit_waiting_sender(channel)?))
+    }
+
+    fn enable_timer(&mut self, cycles: u16, pid: u16) {
+        let deadline = self.alternation_start_cycles[&pid] + u32::from(cycles);
+        if deadline <= self.cycle_count {
+            // As with a channel that has already been sent to, the alternation won't wait
+            self.alternation_ready_set.insert(pid);
+        } else {
+            let earliest = self.timer_deadlines.entry(pid).or_insert(deadline);
+            *earliest = (*earliest).min(deadline);
+        }
+    }
+
+    fn timer_has_expired(&self, cycles: u16, pid: u16) -> bool {
+        self.alternation_start_cycles[&pid] + u32::from(cycles) <= self.cycle_count
+    }
+
+    fn end_alternation(&mut self, pid: u16) -> Result<(), String> {
+        self.alternation_set.remove(&pid);
+        self.alternation_ready_set.remove(&pid);
+        self.alternation_start_cycles.remove(&pid);
+        self.timer_deadlines.remove(&pid);
+        // A guard that was chosen before the remaining guards were disabled leaves this process
+        // listening on their channels, which would otherwise wake it later
+        for channel in self.alternation_channels.remove(&pid).unwrap_or_default() {
+            let proc = self.get_channel_process(channel)?;
+            if proc.pid == pid && proc.in_alternation && !proc.is_sender {
+                self.set_channel_process(channel, NO_PROCESS, false)?;
+            }
+        }
+        Ok(())
+    }
+
     fn new_process(&mut self) -> Result<u16, String> {
         for pid in self.first_process_index()..self.last_process_index() {
             let status = self.get_process_state(pid)?;
@@ -676,6 +1039,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn yield_lets_waiting_processes_run() -> Result<(), String> {
+        let is = assemble(lex_str(
+            "
+            p0:
+                chan dup
+                spin 0 start        # Started first, so is scheduled before p1
+                p1 1 start
+                ? swap drop .
+            spin:
+                yield spin j
+            p1:
+                42 ! del .
+        ",
+        )?)?;
+        // With one core p1 only gets to run because spin yields. Spin never halts, so the
+        // processor is only run for long enough for p0 to finish.
+        let mut processor = Processor::new(1, 8);
+        processor.set_instructions(&is)?;
+        for _ in 0..200 {
+            processor.tick(false)?;
+        }
+        assert_eq!(processor.final_stack(0), &vec![42]);
+        Ok(())
+    }
+
     #[test]
     fn alternation() -> Result<(), String> {
         let is = assemble(lex_str(
@@ -786,4 +1175,212 @@ mod tests {
         assert!(stack[0] == 5 || stack[0] == (4 as u16).overflowing_sub(7).0);
         Ok(())
     }
+
+    #[test]
+    fn alternation_timer_expires() -> Result<(), String> {
+        let is = assemble(lex_str(
+            "
+            main:
+                chan                    # Nothing is ever sent on this channel
+                altstart
+                0 get enable
+                20 enabletimer
+                altwait
+                0 get received disable
+                20 timeout disabletimer
+                altend
+            received:
+                drop del 1 .
+            timeout:
+                del 2 .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        processor.run(true)?;
+        let stack = processor.final_stack(0);
+        assert_eq!(stack, &vec![2]);
+        Ok(())
+    }
+
+    #[test]
+    fn alternation_without_waiting() -> Result<(), String> {
+        // Nothing has been sent when the channel is disabled, so execution continues past altend
+        // and the channel can then be used normally
+        let is = assemble(lex_str(
+            "
+            main:
+                chan
+                altstart
+                0 get enable
+                0 get received disable
+                altend
+                0 get sender 1 start
+                ? swap del 100 + .
+            received:
+                swap del .
+            sender:
+                9 ! .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        processor.run(true)?;
+        let stack = processor.final_stack(0);
+        assert_eq!(stack, &vec![109]);
+        Ok(())
+    }
+
+    #[test]
+    fn waiting_senders_are_served_in_order() -> Result<(), String> {
+        let is = assemble(lex_str(
+            "
+            main:
+                chan
+                0 get sender1 1 start
+                0 get sender2 1 start
+                0 get sender3 1 start
+                nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop
+                ? swap ? swap ? swap del .
+            sender1:
+                1 ! .
+            sender2:
+                2 ! .
+            sender3:
+                3 ! .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        processor.run(true)?;
+        assert_eq!(processor.final_stack(0), &vec![3, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn cant_delete_a_channel_with_a_waiting_sender() -> Result<(), String> {
+        let is = assemble(lex_str(
+            "
+            main:
+                chan
+                0 get sender 1 start
+                nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop
+                del .
+            sender:
+                1 ! .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        assert!(processor.run(true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn senders_on_every_core_contend_in_the_same_cycle() -> Result<(), String> {
+        // The senders are started four cycles apart, so those started earlier wait longer before all
+        // four cores reach the channel on the same cycle
+        let is = assemble(lex_str(
+            "
+            main:
+                chan
+                0 get sender1 1 start
+                0 get sender2 1 start
+                0 get sender3 1 start
+                nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop
+                nop
+                ? swap ? swap ? swap del .
+            sender1:
+                nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop
+                nop nop nop nop nop nop nop nop
+                1 ! .
+            sender2:
+                nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop
+                nop nop nop nop
+                2 ! .
+            sender3:
+                nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop
+                3 ! .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        processor.run(true)?;
+        // The sender on the lowest core is delivered first, and the others queue by core
+        assert_eq!(processor.final_stack(0), &vec![1, 2, 3]);
+        for sender in 1..=3 {
+            assert_eq!(processor.final_stack(sender).len(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn buffered_send_doesnt_block() -> Result<(), String> {
+        // With an unbuffered channel the first send would never complete
+        let is = assemble(lex_str(
+            "
+            main:
+                3 chanbuf
+                1 ! 2 ! 3 !
+                ? swap ? swap ? swap del .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        processor.run(true)?;
+        let stack = processor.final_stack(0);
+        assert_eq!(stack, &vec![3, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn buffered_sender_blocks_when_full() -> Result<(), String> {
+        let is = assemble(lex_str(
+            "
+            main:
+                1 chanbuf
+                0 get sender 1 start
+                nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop nop
+                ? swap ? swap ? swap del .
+            sender:
+                1 ! 2 ! 3 ! .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        processor.run(true)?;
+        let stack = processor.final_stack(0);
+        assert_eq!(stack, &vec![3, 2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn alternation_prefers_ready_channel_to_timer() -> Result<(), String> {
+        let is = assemble(lex_str(
+            "
+            main:
+                chan
+                0 get sender 1 start
+                altstart
+                0 get enable
+                1000 enabletimer
+                altwait
+                0 get received disable
+                1000 timeout disabletimer
+                altend
+            received:
+                swap del .
+            timeout:
+                del 2 .
+            sender:
+                9 ! .
+            ",
+        )?)?;
+        let mut processor = Processor::default();
+        processor.set_instructions(&is)?;
+        processor.run(true)?;
+        let stack = processor.final_stack(0);
+        assert_eq!(stack, &vec![9]);
+        Ok(())
+    }
 }
@@ -1,8 +1,10 @@
 mod ast;
 mod codegen;
 mod compiler;
+mod diagnostic;
 mod lexer;
 mod parser;
 mod types;
 
-pub use compiler::compile;
+pub use compiler::{compile, compile_str, compile_with_sources, CompileError, CompileOptions};
+pub use diagnostic::SourceFiles;
@@ -1,15 +1,36 @@
-use super::types::{subscripted, Type};
+use super::lexer::{Source, Span, Token};
+use super::types::{subscripted, Direction, Hole, Type};
 
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::ops::DerefMut;
 
+/**
+ * Modules are identified by the name they were imported with. The root module (i.e. the file
+ * passed to the compiler) has no name.
+ */
+pub type ModuleName = Option<String>;
+
 #[derive(Debug, Default)]
 pub struct Program {
     pub declarations: Vec<Declaration>,
+    /// The imports made by each module in the program
+    pub imports: HashMap<ModuleName, Vec<Import>>,
+    /// Names whose declarations had syntax errors, so were left out of the program
+    pub unparsed: HashSet<String>,
+    /// The constants that the parser replaced with their values
+    pub constants: Vec<Constant>,
+    /// The holes that the type checker found, in the order that it checked them
+    pub holes: Vec<Hole>,
 }
 
 impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(imports) = self.imports.get(&None) {
+            for import in imports {
+                writeln!(f, "{}", import)?;
+            }
+        }
         for decl in &self.declarations {
             writeln!(f, "{}", decl)?;
         }
@@ -17,16 +38,152 @@ impl fmt::Display for Program {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub module: String,
+    pub source: Source,
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "import {}", self.module)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Constant {
+    pub name: String,
+    pub span: Span,
+    /// The module that declared the constant, which is the only one that can use it
+    pub module: ModuleName,
+}
+
 #[derive(Debug)]
 pub struct Declaration {
     pub name: String,
+    /// Where the name is defined
+    pub span: Span,
     pub term: Box<Term>,
+    pub module: ModuleName,
+    /// The declared type, which the inferred type must unify with
+    pub signature: Option<SignatureType>,
+    /// Set for a declaration with a numeric parameter, whose term is left empty
+    pub template: Option<Template>,
     // Type is derived from the term's type
 }
 
+/**
+ * The body of a declaration with a numeric parameter, such as `sendAll_count = repeat_count (...)`.
+ * The parameter may be used wherever a constant can, so the body is parsed again for each value
+ * that the declaration is used with.
+ */
+#[derive(Clone, Debug)]
+pub struct Template {
+    pub parameter: String,
+    pub tokens: Vec<Token>,
+    /// The constants of the file that the declaration is in
+    pub constants: HashMap<String, u16>,
+}
+
+/**
+ * Declarations in imported modules are qualified with the module's name so that every declaration
+ * in a program has a unique name. Statick identifiers can't contain a period, so a qualified name
+ * never collides with a name in the root module.
+ */
+pub fn qualified_name(module: &ModuleName, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}.{}", module, name),
+        None => name.to_string(),
+    }
+}
+
 impl fmt::Display for Declaration {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} = {}", self.name, self.term)
+        if let Some(signature) = &self.signature {
+            writeln!(f, "{} :: {}", self.name, signature)?;
+        }
+        match &self.template {
+            Some(template) => write!(f, "{}_{} = ...", self.name, template.parameter),
+            None => write!(f, "{} = {}", self.name, self.term),
+        }
+    }
+}
+
+/**
+ * A type as written by the user in a signature such as `sender :: Rest × chan(1, Tx, int) → Rest`.
+ * Variables are referred to by name, and are only turned in to type variables by the type checker.
+ */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SignatureType {
+    Integer,
+    SignedInteger,
+    Boolean,
+    Generic(String),
+    Channel(SignatureChannelUse, Direction, Box<SignatureType>),
+    Function(SignatureStack, SignatureStack),
+}
+
+impl fmt::Display for SignatureType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureType::Integer => write!(f, "int"),
+            SignatureType::SignedInteger => write!(f, "sint"),
+            SignatureType::Boolean => write!(f, "bool"),
+            SignatureType::Generic(name) => write!(f, "{}", name),
+            SignatureType::Channel(chan_use, direction, t) => {
+                write!(f, "chan(")?;
+                if *chan_use != SignatureChannelUse::Infinity {
+                    write!(f, "{}, ", chan_use)?;
+                }
+                write!(f, "{:?}, {})", direction, t)
+            }
+            SignatureType::Function(i, o) => write!(f, "{} → {}", i, o),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SignatureStack {
+    Bottom,
+    Stack(String, Vec<SignatureType>),
+}
+
+impl fmt::Display for SignatureStack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureStack::Bottom => write!(f, "⊥"),
+            SignatureStack::Stack(name, types) => {
+                write!(f, "{}", name)?;
+                for t in types {
+                    if let SignatureType::Function(_, _) = t {
+                        write!(f, " × ({})", t)?;
+                    } else {
+                        write!(f, " × {}", t)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SignatureChannelUse {
+    Infinity,
+    Shared,
+    Constant(usize),
+    Variable(String, usize),
+}
+
+impl fmt::Display for SignatureChannelUse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureChannelUse::Infinity => write!(f, "∞"),
+            SignatureChannelUse::Shared => write!(f, "shared"),
+            SignatureChannelUse::Constant(k) => write!(f, "{}", k),
+            SignatureChannelUse::Variable(name, 0) => write!(f, "{}", name),
+            SignatureChannelUse::Variable(name, k) => write!(f, "{}+{}", name, k),
+        }
     }
 }
 
@@ -37,6 +194,7 @@ pub struct Term {
     pub is_run: bool,
     pub is_function: bool,
     pub label: Option<String>,
+    pub span: Span,
 }
 
 impl fmt::Display for Term {
@@ -55,6 +213,7 @@ impl fmt::Display for Term {
 pub struct Expression {
     pub expression: ExpressionType,
     pub e_type: Option<Type>,
+    pub span: Span,
 }
 
 impl fmt::Display for Expression {
@@ -67,7 +226,10 @@ impl fmt::Display for Expression {
 pub enum ExpressionType {
     // Numbers are effectively functions, but I don't want 2^16 functions in the standard library
     Number(u16),
-    Alternation(Vec<AlternationArm>),
+    SignedNumber(i16),
+    /// Runs the first arm whose guard is ready. When set, arms that become ready together are
+    /// chosen in the order that they are written.
+    Alternation(Vec<AlternationArm>, bool),
     AnonymousTerm(Box<Term>),
     NamedTermApp(String, Option<u16>),
     NamedTermRef(String, Option<u16>),
@@ -76,6 +238,24 @@ pub enum ExpressionType {
     Forever(Box<Term>),
     Repeat(u16, Box<Term>),
     Offset(u16),
+    /// Binds the values on top of the stack to names for the duration of the term
+    Let(Vec<String>, Box<Term>),
+    /// A use of a name bound by an enclosing let. The type checker sets the offset of the value.
+    Local(String, Option<u16>),
+    /// Pops an integer and runs the arm for that value, or the default arm if there isn't one
+    Case(Vec<CaseArm>, Box<Term>),
+    /// Runs each term in its own process on its own part of the stack, then waits for them all.
+    /// The last term takes the values nearest the top of the stack.
+    Parallel(Vec<Term>),
+    /// Runs n copies of the term in parallel, each of which is passed its index on top of its
+    /// part of the stack unless it takes nothing
+    ReplicatedParallel(u16, Box<Term>),
+    /// Receives once from each of the n channels beneath the words the term keeps, in the order
+    /// that they are ready, running the term with each value and the index of its channel
+    ReplicatedAlternation(u16, Box<Term>),
+    /// Stands for code that hasn't been written yet. The type checker accepts it anywhere and
+    /// reports the stack at that point, but the program can't be compiled until it is filled.
+    Hole,
 }
 
 impl fmt::Display for ExpressionType {
@@ -83,7 +263,11 @@ impl fmt::Display for ExpressionType {
         use ExpressionType::*;
         match self {
             Number(n) => write!(f, "{}", n),
-            Alternation(arms) => {
+            SignedNumber(n) => write!(f, "{}", n),
+            Alternation(arms, prioritised) => {
+                if *prioritised {
+                    write!(f, "pri ")?;
+                }
                 write!(f, "[")?;
                 if !arms.is_empty() {
                     write!(f, " {}", arms[0])?;
@@ -113,20 +297,83 @@ impl fmt::Display for ExpressionType {
             Forever(body) => write!(f, "repeat {}", body),
             Repeat(k, body) => write!(f, "repeat{} {}", subscripted(*k), body),
             Offset(x) => write!(f, "@{}", x),
+            Let(names, body) => write!(f, "let {} in {}", names.join(" "), body),
+            Local(name, _) => write!(f, "{}", name),
+            Case(arms, default) => {
+                write!(f, "case [")?;
+                for arm in arms {
+                    write!(f, " {} |", arm)?;
+                }
+                write!(f, " else -> {} ]", default)
+            }
+            Parallel(branches) => {
+                write!(f, "par ( {}", branches[0])?;
+                for branch in &branches[1..] {
+                    write!(f, " | {}", branch)?;
+                }
+                write!(f, " )")
+            }
+            ReplicatedParallel(n, body) => write!(f, "par{} ({})", subscripted(*n), body),
+            ReplicatedAlternation(n, body) => write!(f, "alt{} ({})", subscripted(*n), body),
+            Hole => write!(f, "_?"),
         }
     }
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct AlternationArm {
-    pub offset: u16,
+    /// The arm is only considered if this pushes true
+    pub condition: Option<Box<Term>>,
+    pub guard: Guard,
     pub term: Box<Term>,
     pub a_type: Option<Type>,
 }
 
+impl AlternationArm {
+    pub fn is_timer(&self) -> bool {
+        matches!(self.guard, Guard::Timer(_))
+    }
+}
+
 impl fmt::Display for AlternationArm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "@{} -> {}", self.offset, self.term)
+        if let Some(condition) = &self.condition {
+            write!(f, "({}) & ", condition)?;
+        }
+        write!(f, "{} -> {}", self.guard, self.term)
+    }
+}
+
+/// The condition under which an arm of an alternation is chosen
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Guard {
+    /// A value can be received from the channel at this offset
+    Channel(u16),
+    /// This many cycles have passed since the alternation started
+    Timer(u16),
+    /// No other guard is ready, so the alternation doesn't wait
+    Else,
+}
+
+impl fmt::Display for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Guard::Channel(offset) => write!(f, "@{}", offset),
+            Guard::Timer(cycles) => write!(f, "after {}", cycles),
+            Guard::Else => write!(f, "else"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct CaseArm {
+    pub value: u16,
+    pub term: Box<Term>,
+}
+
+impl fmt::Display for CaseArm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.value, self.term)
     }
 }
 
@@ -152,16 +399,24 @@ pub trait AstVisitor<T> {
     fn visit_expression(&mut self, expr: &Expression) -> Result<(), T> {
         use ExpressionType::*;
         match &expr.expression {
-            Alternation(arms) => self.visit_alternation(arms),
+            Alternation(arms, _) => self.visit_alternation(arms),
             AnonymousTerm(term) => self.visit_anonymous_term(term),
             If(c, t, f) => self.visit_if(c, t, f),
             While(c, b) => self.visit_while(c, b),
             Forever(b) => self.visit_forever(b),
             Repeat(k, b) => self.visit_repeat(*k, b),
             Number(n) => self.visit_number(*n),
+            SignedNumber(n) => self.visit_signed_number(*n),
             Offset(o) => self.visit_offset(*o),
             NamedTermApp(n, k) => self.visit_named_term_app(n, *k),
             NamedTermRef(n, k) => self.visit_named_term_ref(n, *k),
+            Let(names, body) => self.visit_let(names, body),
+            Local(n, _) => self.visit_local(n),
+            Case(arms, default) => self.visit_case(arms, default),
+            Parallel(branches) => self.visit_parallel(branches),
+            ReplicatedParallel(n, b) => self.visit_replicated_parallel(*n, b),
+            ReplicatedAlternation(n, b) => self.visit_replicated_alternation(*n, b),
+            Hole => self.visit_hole(),
         }
     }
 
@@ -182,6 +437,28 @@ pub trait AstVisitor<T> {
         self.visit_term(&f)
     }
 
+    fn visit_case(&mut self, arms: &[CaseArm], default: &Term) -> Result<(), T> {
+        for arm in arms {
+            self.visit_term(&arm.term)?;
+        }
+        self.visit_term(default)
+    }
+
+    fn visit_parallel(&mut self, branches: &[Term]) -> Result<(), T> {
+        for branch in branches {
+            self.visit_term(branch)?;
+        }
+        Ok(())
+    }
+
+    fn visit_replicated_parallel(&mut self, _n: u16, b: &Term) -> Result<(), T> {
+        self.visit_term(b)
+    }
+
+    fn visit_replicated_alternation(&mut self, _n: u16, b: &Term) -> Result<(), T> {
+        self.visit_term(b)
+    }
+
     fn visit_while(&mut self, c: &Term, b: &Term) -> Result<(), T> {
         self.visit_term(&c)?;
         self.visit_term(&b)
@@ -195,10 +472,26 @@ pub trait AstVisitor<T> {
         self.visit_term(b)
     }
 
+    fn visit_let(&mut self, _names: &[String], body: &Term) -> Result<(), T> {
+        self.visit_term(body)
+    }
+
+    fn visit_local(&mut self, _name: &str) -> Result<(), T> {
+        Ok(())
+    }
+
     fn visit_number(&mut self, _n: u16) -> Result<(), T> {
         Ok(())
     }
 
+    fn visit_hole(&mut self) -> Result<(), T> {
+        Ok(())
+    }
+
+    fn visit_signed_number(&mut self, _n: i16) -> Result<(), T> {
+        Ok(())
+    }
+
     fn visit_offset(&mut self, _n: u16) -> Result<(), T> {
         Ok(())
     }
@@ -211,6 +504,9 @@ pub trait AstVisitor<T> {
     }
 
     fn visit_arm(&mut self, arm: &AlternationArm) -> Result<(), T> {
+        if let Some(condition) = &arm.condition {
+            self.visit_term(condition)?;
+        }
         self.visit_term(&arm.term)
     }
 }
@@ -237,16 +533,24 @@ pub trait MutAstVisitor<T> {
     fn visit_expression(&mut self, expr: &mut Expression) -> Result<(), T> {
         use ExpressionType::*;
         match &mut expr.expression {
-            Alternation(arms) => self.visit_alternation(arms),
+            Alternation(arms, _) => self.visit_alternation(arms),
             AnonymousTerm(term) => self.visit_anonymous_term(term.deref_mut()),
             If(c, t, f) => self.visit_if(c.deref_mut(), t.deref_mut(), f.deref_mut()),
             While(c, b) => self.visit_while(c.deref_mut(), b.deref_mut()),
             Forever(b) => self.visit_forever(b.deref_mut()),
             Repeat(k, b) => self.visit_repeat(*k, b),
             Number(n) => self.visit_number(*n),
+            SignedNumber(n) => self.visit_signed_number(*n),
             Offset(o) => self.visit_offset(*o),
-            NamedTermApp(n, k) => self.visit_named_term_app(n, *k),
-            NamedTermRef(n, k) => self.visit_named_term_ref(n, *k),
+            NamedTermApp(n, k) => self.visit_named_term_app(n, k),
+            NamedTermRef(n, k) => self.visit_named_term_ref(n, k),
+            Let(names, body) => self.visit_let(names, body.deref_mut()),
+            Local(n, o) => self.visit_local(n, o),
+            Case(arms, default) => self.visit_case(arms, default.deref_mut()),
+            Parallel(branches) => self.visit_parallel(branches),
+            ReplicatedParallel(n, b) => self.visit_replicated_parallel(*n, b),
+            ReplicatedAlternation(n, b) => self.visit_replicated_alternation(*n, b),
+            Hole => self.visit_hole(),
         }
     }
 
@@ -272,6 +576,28 @@ pub trait MutAstVisitor<T> {
         self.visit_term(f)
     }
 
+    fn visit_case(&mut self, arms: &mut Vec<CaseArm>, default: &mut Term) -> Result<(), T> {
+        for arm in arms {
+            self.visit_term(&mut arm.term)?;
+        }
+        self.visit_term(default)
+    }
+
+    fn visit_parallel(&mut self, branches: &mut Vec<Term>) -> Result<(), T> {
+        for branch in branches {
+            self.visit_term(branch)?;
+        }
+        Ok(())
+    }
+
+    fn visit_replicated_parallel(&mut self, _n: u16, b: &mut Term) -> Result<(), T> {
+        self.visit_term(b)
+    }
+
+    fn visit_replicated_alternation(&mut self, _n: u16, b: &mut Term) -> Result<(), T> {
+        self.visit_term(b)
+    }
+
     fn visit_while(&mut self, c: &mut Term, b: &mut Term) -> Result<(), T> {
         self.visit_term(c)?;
         self.visit_term(b)
@@ -285,22 +611,41 @@ pub trait MutAstVisitor<T> {
         self.visit_term(b)
     }
 
+    fn visit_let(&mut self, _names: &mut Vec<String>, body: &mut Term) -> Result<(), T> {
+        self.visit_term(body)
+    }
+
+    fn visit_local(&mut self, _name: &mut String, _offset: &mut Option<u16>) -> Result<(), T> {
+        Ok(())
+    }
+
     fn visit_number(&mut self, _n: u16) -> Result<(), T> {
         Ok(())
     }
 
+    fn visit_hole(&mut self) -> Result<(), T> {
+        Ok(())
+    }
+
+    fn visit_signed_number(&mut self, _n: i16) -> Result<(), T> {
+        Ok(())
+    }
+
     fn visit_offset(&mut self, _n: u16) -> Result<(), T> {
         Ok(())
     }
 
-    fn visit_named_term_app(&mut self, _n: &str, _k: Option<u16>) -> Result<(), T> {
+    fn visit_named_term_app(&mut self, _n: &mut String, _k: &mut Option<u16>) -> Result<(), T> {
         Ok(())
     }
-    fn visit_named_term_ref(&mut self, _n: &str, _k: Option<u16>) -> Result<(), T> {
+    fn visit_named_term_ref(&mut self, _n: &mut String, _k: &mut Option<u16>) -> Result<(), T> {
         Ok(())
     }
 
     fn visit_arm(&mut self, arm: &mut AlternationArm) -> Result<(), T> {
+        if let Some(condition) = &mut arm.condition {
+            self.visit_term(condition)?;
+        }
         self.visit_term(&mut arm.term)
     }
 }
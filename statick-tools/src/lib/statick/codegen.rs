@@ -1,13 +1,19 @@
 use super::ast::*;
+use super::lexer::Span;
+use super::types::{self, Type};
 use crate::{Condition, FunctionOp, Instruction, Op, ProcessOp, StackOp};
 
 use std::cell::{RefCell, RefMut};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
 
+mod library;
 mod optimizer;
 
+use library::LibraryRoutine;
+
 #[derive(Debug)]
 pub enum CodegenError {}
 pub type CodegenResult<T> = Result<T, CodegenError>;
@@ -18,29 +24,38 @@ impl fmt::Display for CodegenError {
     }
 }
 
-pub fn codegen(program: Program) -> CodegenResult<String> {
-    Ok(CodeGenerator::flatten(&codegen_blocks(program)?))
+/// When `auto_yield` is set every loop yields at the end of each iteration
+pub fn codegen(program: Program, auto_yield: bool) -> CodegenResult<String> {
+    Ok(CodeGenerator::flatten(&codegen_blocks(
+        program, auto_yield,
+    )?))
 }
 
 // This function is just used for testing
-fn codegen_blocks(program: Program) -> CodegenResult<Vec<Block>> {
-    CodeGenerator::new(program).codegen()
+fn codegen_blocks(program: Program, auto_yield: bool) -> CodegenResult<Vec<Block>> {
+    CodeGenerator::new(program, auto_yield).codegen()
 }
 
+/// Cases with fewer arms than this are always compiled to a chain of comparisons
+const MIN_JUMP_TABLE_ARMS: usize = 3;
+
 #[derive(Default)]
 struct CodeGenerator {
     declarations: HashMap<String, RefCell<Declaration>>,
     label_counter: RefCell<LabelCounter>,
+    library_routines: RefCell<BTreeSet<LibraryRoutine>>,
+    auto_yield: bool,
 }
 
 impl CodeGenerator {
-    fn new(program: Program) -> CodeGenerator {
+    fn new(program: Program, auto_yield: bool) -> CodeGenerator {
         let mut declarations = HashMap::new();
         for decl in program.declarations {
             declarations.insert(decl.name.to_string(), RefCell::new(decl));
         }
         CodeGenerator {
             declarations,
+            auto_yield,
             ..CodeGenerator::default()
         }
     }
@@ -81,6 +96,7 @@ impl CodeGenerator {
                     let e = Expression {
                         expression: e,
                         e_type: None,
+                        span: Span::default(),
                     };
                     let t = Term {
                         expressions: vec![e],
@@ -88,7 +104,11 @@ impl CodeGenerator {
                     };
                     let d = Declaration {
                         name: new_name.to_string(),
+                        span: Span::default(),
                         term: Box::new(t),
+                        module: None,
+                        signature: None,
+                        template: None,
                     };
                     self.new_declarations
                         .insert(new_name.to_string(), RefCell::new(d));
@@ -166,8 +186,29 @@ impl CodeGenerator {
             fn visit_anonymous_term(&mut self, term: &Term) -> CodegenResult<()> {
                 let (new_blocks, _) = (self.gen.assemble_term(term, true, &None, &None, &None))?;
                 self.blocks.extend(new_blocks);
+                self.visit_term(term)
+            }
+
+            fn visit_parallel(&mut self, branches: &[Term]) -> CodegenResult<()> {
+                // Each branch is the function of a spawned process
+                for branch in branches {
+                    self.visit_anonymous_term(branch)?;
+                }
                 Ok(())
             }
+
+            fn visit_replicated_parallel(&mut self, _n: u16, body: &Term) -> CodegenResult<()> {
+                // Every copy runs the same function
+                self.visit_anonymous_term(body)
+            }
+
+            fn visit_arm(&mut self, arm: &AlternationArm) -> CodegenResult<()> {
+                // Conditions are called, as they are needed both to enable and disable a guard
+                if let Some(condition) = &arm.condition {
+                    self.visit_anonymous_term(condition)?;
+                }
+                self.visit_term(&arm.term)
+            }
         }
 
         let mut vis = Visitor {
@@ -178,6 +219,10 @@ impl CodeGenerator {
             vis.visit_declaration(&decl.borrow())?;
         }
         all_blocks.extend(vis.blocks);
+
+        for routine in self.library_routines.borrow().iter() {
+            all_blocks.extend(routine.blocks());
+        }
         Ok(all_blocks)
     }
 
@@ -256,10 +301,36 @@ impl CodeGenerator {
             Number(n) => {
                 block.push(Token::N(*n));
             }
+            SignedNumber(n) => {
+                block.push(Token::N(*n as u16));
+            }
             Offset(n) => {
                 block.push(Token::N(*n));
                 block.push(Token::I(ReadLocal));
             }
+            Local(name, offset) => match offset {
+                Some(n) => CodeGenerator::read_local(&mut block, *n),
+                None => panic!("{} wasn't given an offset by the type checker", name),
+            },
+            Hole => panic!("Holes must be filled before code is generated"),
+            Let(names, body) => {
+                let cleanup_label = self.fresh_label();
+                let (mut blocks, _) =
+                    self.assemble_term(body, false, &Some(cleanup_label.clone()), &None, &None)?;
+                let mut cleanup_block = Block {
+                    label: Some(cleanup_label),
+                    ..Block::default()
+                };
+                if let Some(Type::Function(_, o)) = &body.t_type {
+                    // A body that never returns doesn't need its bindings removing
+                    if o.get_base_stack() != types::Stack::Bottom {
+                        CodeGenerator::remove_bindings(&mut cleanup_block, names.len(), o.height());
+                    }
+                }
+                CodeGenerator::extend_with_exit(&mut cleanup_block, as_function, exit_label);
+                blocks.push(cleanup_block);
+                return Ok((blocks, false));
+            }
             NamedTermRef(name, k) => {
                 block.push(Token::L(format!(
                     "f_{}",
@@ -277,6 +348,11 @@ impl CodeGenerator {
                 match name.as_ref() {
                     "true" => block.push(Token::N(1)),
                     "false" => block.push(Token::N(0)),
+                    // A call in tail position can reuse the caller's return address
+                    "apply" if as_function => {
+                        block.push(Token::I(Jump(Always)));
+                        return Ok((vec![block], false));
+                    }
                     "apply" => block.push(Token::I(Function(Call))),
                     "swap" => block.push(Token::I(Stack(Swap))),
                     "dup" => block.push(Token::I(Stack(Dup))),
@@ -287,10 +363,33 @@ impl CodeGenerator {
                         block.push(Token::I(Process(CreateChannel)));
                         block.push(Token::I(Stack(Dup)));
                     }
+                    // Both ends hold a reference to a shared channel
+                    "sharedchan" => {
+                        block.push(Token::I(Process(CreateChannel)));
+                        block.push(Token::I(Stack(Dup)));
+                        block.push(Token::I(Stack(Dup)));
+                        block.push(Token::I(Process(ShareChannel)));
+                    }
+                    "share" => {
+                        block.push(Token::I(Stack(Dup)));
+                        block.push(Token::I(Stack(Dup)));
+                        block.push(Token::I(Process(ShareChannel)));
+                    }
+                    "release" => block.push(Token::I(Process(DestroyChannel))),
+                    "chanbuf" => {
+                        block.push(Token::N(k));
+                        block.push(Token::I(Process(CreateBufferedChannel)));
+                        block.push(Token::I(Stack(Dup)));
+                    }
                     "proc" => {
                         block.push(Token::N(k));
                         block.push(Token::I(Process(Start)));
                     }
+                    "spawn" => {
+                        let m = CodeGenerator::spawn_result_count(expr);
+                        self.spawn(&mut block, k, m);
+                    }
+                    "join" => CodeGenerator::join(&mut block, k),
                     "?" => {
                         if k > 0 {
                             block.push(Token::N(k));
@@ -330,9 +429,54 @@ impl CodeGenerator {
                     "or" => block.push(Token::I(ArithmeticOrLogic(LogicalOr))),
                     "+" => block.push(Token::I(ArithmeticOrLogic(Add))),
                     "-" => block.push(Token::I(ArithmeticOrLogic(Sub))),
+                    "xor" => block.push(Token::I(ArithmeticOrLogic(LogicalXor))),
+                    "bitand" => block.push(Token::I(ArithmeticOrLogic(LogicalAnd))),
+                    "bitor" => block.push(Token::I(ArithmeticOrLogic(LogicalOr))),
+                    "bitnot" => block.push(Token::I(ArithmeticOrLogic(LogicalNot))),
+                    "asl" => block.push(Token::I(ArithmeticOrLogic(ArithmeticShiftLeft))),
+                    "asr" => block.push(Token::I(ArithmeticOrLogic(ArithmeticShiftRight))),
+                    "lsl" => block.push(Token::I(ArithmeticOrLogic(LogicalShiftLeft))),
+                    "lsr" => block.push(Token::I(ArithmeticOrLogic(LogicalShiftRight))),
+                    "*" => {
+                        block.push(Token::L(self.use_library_routine(LibraryRoutine::Multiply)));
+                        block.push(Token::I(Function(Call)));
+                    }
+                    "/" | "%" => {
+                        block.push(Token::L(self.use_library_routine(LibraryRoutine::DivMod)));
+                        block.push(Token::I(Function(Call)));
+                        // The quotient is beneath the remainder
+                        if name == "/" {
+                            block.push(Token::I(Stack(Drop)));
+                        } else {
+                            block.push(Token::I(Stack(Swap)));
+                            block.push(Token::I(Stack(Drop)));
+                        }
+                    }
+                    "s/" | "s%" => {
+                        // The signed routine divides the magnitudes with the unsigned one
+                        self.use_library_routine(LibraryRoutine::DivMod);
+                        block.push(Token::L(
+                            self.use_library_routine(LibraryRoutine::SignedDivMod),
+                        ));
+                        block.push(Token::I(Function(Call)));
+                        if name == "s/" {
+                            block.push(Token::I(Stack(Drop)));
+                        } else {
+                            block.push(Token::I(Stack(Swap)));
+                            block.push(Token::I(Stack(Drop)));
+                        }
+                    }
                     "." => {}
+                    "yield" => block.push(Token::I(Process(Yield))),
                     "toInt" => block.push(Token::I(Stack(Dup))),
-                    "==" | "!=" | "<=" | ">=" | "<" | ">" => {
+                    // Signed and unsigned integers share a representation
+                    "toSint" | "fromSint" => {}
+                    "neg" => {
+                        block.push(Token::I(ArithmeticOrLogic(LogicalNot)));
+                        block.push(Token::N(1));
+                        block.push(Token::I(ArithmeticOrLogic(Add)));
+                    }
+                    "==" | "!=" | "<=" | ">=" | "<" | ">" | "s<=" | "s>=" | "s<" | "s>" => {
                         let cond = match name.as_ref() {
                             "==" => ZeroEqual,
                             "!=" => NotZeroNotEqual,
@@ -340,6 +484,10 @@ impl CodeGenerator {
                             "<=" => UnsignedLessOrEqual,
                             "<" => UnsignedLess,
                             ">" => UnsignedGreater,
+                            "s>=" => SignedGreaterOrEqual,
+                            "s<=" => SignedLessOrEqual,
+                            "s<" => SignedLess,
+                            "s>" => SignedGreater,
                             _ => panic!("Unknown condition function {}", name),
                         };
                         let mut cmp_block = Block::default();
@@ -354,7 +502,21 @@ impl CodeGenerator {
                             return Ok((vec![cmp_block], true));
                         } else {
                             let true_label = self.fresh_label();
-                            cmp_block.push(Token::I(ArithmeticOrLogic(Compare)));
+                            cmp_block.push(Token::L(true_label.to_string()));
+                            cmp_block.push(Token::I(Jump(cond)));
+
+                            // When the comparison isn't the last expression both branches must
+                            // rejoin before the rest of the term
+                            let join_label = if as_function || exit_label.is_some() {
+                                None
+                            } else {
+                                Some(self.fresh_label())
+                            };
+                            let exit_label = if join_label.is_some() {
+                                &join_label
+                            } else {
+                                exit_label
+                            };
 
                             cmp_block.push(Token::N(0));
                             CodeGenerator::extend_with_exit(
@@ -370,17 +532,32 @@ impl CodeGenerator {
                                 as_function,
                                 exit_label,
                             );
-                            return Ok((vec![cmp_block, true_block], false));
+                            let mut blocks = vec![cmp_block, true_block];
+                            if let Some(join_label) = join_label {
+                                blocks.push(Block {
+                                    label: Some(join_label),
+                                    ..Block::default()
+                                });
+                            }
+                            return Ok((blocks, false));
                         }
                     }
+                    _ if as_function => {
+                        block.push(Token::L(format!("f_{}", name)));
+                        block.push(Token::I(Jump(Always)));
+                        return Ok((vec![block], false));
+                    }
                     _ => {
                         block.push(Token::L(format!("f_{}", name)));
                         block.push(Token::I(Function(Call)));
                     }
                 }
             }
-            Forever(block) => {
-                return self.assemble_term(block, false, &block.label, &None, &None);
+            Forever(body) => {
+                let (back_edge, yield_block) = self.back_edge(&body.label);
+                let (mut blocks, _) = self.assemble_term(body, false, &back_edge, &None, &None)?;
+                blocks.extend(yield_block);
+                return Ok((blocks, false));
             }
             Repeat(n, body) => {
                 let mut blocks = vec![];
@@ -395,6 +572,9 @@ impl CodeGenerator {
 
                 let mut check_block = Block::default();
                 check_block.label = Some(check_label);
+                if self.auto_yield {
+                    check_block.push(Token::I(Process(Yield)));
+                }
                 check_block.push(Token::N(1));
                 check_block.push(Token::I(ArithmeticOrLogic(Add)));
                 check_block.push(Token::I(Stack(Dup)));
@@ -447,6 +627,42 @@ impl CodeGenerator {
 
                 return Ok((blocks, false));
             }
+            Case(arms, default) => {
+                let needs_fresh_exit_label = exit_label.is_none() && !as_function;
+                let exit_label = if needs_fresh_exit_label {
+                    Some(self.fresh_label())
+                } else {
+                    exit_label.clone()
+                };
+
+                let (mut blocks, arm_entries) = if CodeGenerator::is_dense(arms) {
+                    self.assemble_jump_table(arms, default)
+                } else {
+                    self.assemble_compare_chain(arms)
+                };
+
+                // The default arm is always placed immediately after the dispatch
+                let (default_blocks, _) =
+                    self.assemble_term(default, as_function, &exit_label, &None, &None)?;
+                blocks.extend(default_blocks);
+                for (arm, entry) in arms.iter().zip(arm_entries) {
+                    if let Some(entry) = entry {
+                        blocks.push(entry);
+                    }
+                    let (arm_blocks, _) =
+                        self.assemble_term(&arm.term, as_function, &exit_label, &None, &None)?;
+                    blocks.extend(arm_blocks);
+                }
+
+                if needs_fresh_exit_label {
+                    blocks.push(Block {
+                        label: exit_label,
+                        ..Block::default()
+                    });
+                }
+
+                return Ok((blocks, false));
+            }
             While(condition, body) => {
                 // Compiles so that only a single conditional branch is required each iteration
                 let mut blocks = vec![];
@@ -456,9 +672,10 @@ impl CodeGenerator {
                 blocks.push(init_jump);
 
                 let check_label = Some(self.fresh_label());
-                let (condition_blocks, _) =
-                    self.assemble_term(body, false, &condition.label, &None, &None)?;
-                blocks.extend(condition_blocks);
+                let (back_edge, yield_block) = self.back_edge(&condition.label);
+                let (body_blocks, _) = self.assemble_term(body, false, &back_edge, &None, &None)?;
+                blocks.extend(body_blocks);
+                blocks.extend(yield_block);
                 let (check_blocks, no_need_for_check_block) =
                     self.assemble_term(condition, false, &check_label, &body.label, &None)?;
                 blocks.extend(check_blocks);
@@ -478,28 +695,71 @@ impl CodeGenerator {
                 blocks.push(end_block);
                 return Ok((blocks, false));
             }
-            Alternation(arms) => {
+            Alternation(arms, prioritised) => {
+                let mut blocks = vec![];
                 let mut alt_initialisation = Block::default();
                 alt_initialisation.push(Token::I(Process(AlternationStart)));
 
                 for arm in arms {
-                    alt_initialisation.push(Token::N(arm.offset));
-                    alt_initialisation.push(Token::I(ReadLocal));
-                    alt_initialisation.push(Token::I(Process(EnableChannel)));
+                    let skip_label = self.assemble_condition(arm, &mut alt_initialisation);
+                    match arm.guard {
+                        Guard::Channel(offset) => {
+                            alt_initialisation.push(Token::N(offset));
+                            alt_initialisation.push(Token::I(ReadLocal));
+                            alt_initialisation.push(Token::I(Process(EnableChannel)));
+                        }
+                        Guard::Timer(cycles) => {
+                            alt_initialisation.push(Token::N(cycles));
+                            alt_initialisation.push(Token::I(Process(EnableTimer)));
+                        }
+                        Guard::Else => {}
+                    }
+                    if let Some(skip_label) = skip_label {
+                        blocks.push(alt_initialisation);
+                        alt_initialisation = Block::default();
+                        alt_initialisation.label = Some(skip_label);
+                    }
                 }
 
-                alt_initialisation.push(Token::I(Process(AlternationWait)));
+                // With a default arm the guards are only polled, and if none of them are ready the
+                // alternation falls through to the default arm, which is placed immediately after
+                let default_arm = arms.iter().find(|arm| arm.guard == Guard::Else);
+                if default_arm.is_none() {
+                    alt_initialisation.push(Token::I(Process(AlternationWait)));
+                }
 
-                for arm in arms {
-                    alt_initialisation.push(Token::L(arm.term.label.clone().unwrap()));
-                    alt_initialisation.push(Token::N(arm.offset + 1));
-                    alt_initialisation.push(Token::I(ReadLocal));
-                    alt_initialisation.push(Token::I(Process(DisableChannel)));
+                // A disabled guard that is ready jumps straight to its arm. Unless the alternation
+                // is prioritised, channels are disabled first so that a message that arrived in
+                // time is preferred to a timeout.
+                let mut disable_order: Vec<&AlternationArm> = arms.iter().collect();
+                if !prioritised {
+                    disable_order.sort_by_key(|arm| arm.is_timer());
+                }
+                for arm in disable_order {
+                    let skip_label = self.assemble_condition(arm, &mut alt_initialisation);
+                    match arm.guard {
+                        Guard::Channel(offset) => {
+                            alt_initialisation.push(Token::N(offset));
+                            alt_initialisation.push(Token::I(ReadLocal));
+                            alt_initialisation.push(Token::L(arm.term.label.clone().unwrap()));
+                            alt_initialisation.push(Token::I(Process(DisableChannel)));
+                        }
+                        Guard::Timer(cycles) => {
+                            alt_initialisation.push(Token::N(cycles));
+                            alt_initialisation.push(Token::L(arm.term.label.clone().unwrap()));
+                            alt_initialisation.push(Token::I(Process(DisableTimer)));
+                        }
+                        Guard::Else => {}
+                    }
+                    if let Some(skip_label) = skip_label {
+                        blocks.push(alt_initialisation);
+                        alt_initialisation = Block::default();
+                        alt_initialisation.label = Some(skip_label);
+                    }
                 }
 
                 alt_initialisation.push(Token::I(Process(AlternationEnd)));
-
-                let mut blocks = vec![alt_initialisation];
+                blocks.push(alt_initialisation);
 
                 let needs_fresh_exit_label = exit_label.is_none() && !as_function;
                 let exit_label = if needs_fresh_exit_label {
@@ -508,21 +768,326 @@ impl CodeGenerator {
                     exit_label.clone()
                 };
 
-                for arm in arms {
+                let other_arms = arms.iter().filter(|arm| arm.guard != Guard::Else);
+                for arm in default_arm.into_iter().chain(other_arms) {
                     let (new_blocks, _) =
                         self.assemble_term(&arm.term, as_function, &exit_label, &None, &None)?;
                     blocks.extend(new_blocks);
                 }
 
+                if needs_fresh_exit_label {
+                    blocks.push(Block {
+                        label: exit_label,
+                        ..Block::default()
+                    });
+                }
+
                 return Ok((blocks, false));
             }
             AnonymousTerm(t) => block.push(Token::L(t.label.clone().unwrap())),
+            Parallel(branches) => {
+                let branches: Vec<ParallelBranch> = branches
+                    .iter()
+                    .map(|branch| match &branch.t_type {
+                        Some(Type::Function(i, o)) => ParallelBranch {
+                            label: branch.label.clone().unwrap(),
+                            words: i.height() as u16,
+                            results: o.height() as u16,
+                            index: None,
+                        },
+                        _ => panic!("par branch wasn't given a type by the type checker"),
+                    })
+                    .collect();
+                self.assemble_parallel(&mut block, &branches);
+            }
+            ReplicatedParallel(n, body) => {
+                let (words, results, takes_index) = match &body.t_type {
+                    // A copy that takes nothing isn't given its index
+                    Some(Type::Function(i, o)) if i.height() == 0 => (0, o.height() as u16, false),
+                    Some(Type::Function(i, o)) => (i.height() as u16 - 1, o.height() as u16, true),
+                    _ => panic!("par body wasn't given a type by the type checker"),
+                };
+                let branches: Vec<ParallelBranch> = (0..*n)
+                    .map(|index| ParallelBranch {
+                        label: body.label.clone().unwrap(),
+                        words,
+                        results,
+                        index: if takes_index { Some(index) } else { None },
+                    })
+                    .collect();
+                self.assemble_parallel(&mut block, &branches);
+            }
+            ReplicatedAlternation(n, body) => {
+                // A mask of the channels already received from is kept beneath the words that the
+                // body keeps, with the channel with index i a further n - 1 - i words down
+                let n = *n;
+                let kept = match &body.t_type {
+                    Some(Type::Function(_, o)) => o.height() as u16,
+                    _ => panic!("alt body wasn't given a type by the type checker"),
+                };
+                let channel_offset = |i: u16| kept + n - i;
+                let mut blocks = vec![];
+                let mut start_block = Block::default();
+                start_block.push(Token::N(0));
+                if kept > 0 {
+                    CodeGenerator::bury_top(&mut start_block, kept);
+                }
+                blocks.push(start_block);
+
+                // Only the channels that haven't been received from yet are enabled
+                let round_label = self.fresh_label();
+                let mut alt_initialisation = Block {
+                    label: Some(round_label.to_string()),
+                    ..Block::default()
+                };
+                alt_initialisation.push(Token::I(Process(AlternationStart)));
+                for i in 0..n {
+                    let skip_label = self.skip_if_received(&mut alt_initialisation, kept, i);
+                    CodeGenerator::read_local(&mut alt_initialisation, channel_offset(i));
+                    alt_initialisation.push(Token::I(Process(EnableChannel)));
+                    blocks.push(alt_initialisation);
+                    alt_initialisation = Block {
+                        label: Some(skip_label),
+                        ..Block::default()
+                    };
+                }
+                alt_initialisation.push(Token::I(Process(AlternationWait)));
+
+                // Each channel jumps to an entry that marks it as received from and pushes its
+                // index before running the body
+                let entry_labels: Vec<String> = (0..n).map(|_| self.fresh_label()).collect();
+                for (i, entry_label) in entry_labels.iter().enumerate() {
+                    let i = i as u16;
+                    let skip_label = self.skip_if_received(&mut alt_initialisation, kept, i);
+                    CodeGenerator::read_local(&mut alt_initialisation, channel_offset(i));
+                    alt_initialisation.push(Token::L(entry_label.to_string()));
+                    alt_initialisation.push(Token::I(Process(DisableChannel)));
+                    blocks.push(alt_initialisation);
+                    alt_initialisation = Block {
+                        label: Some(skip_label),
+                        ..Block::default()
+                    };
+                }
+                alt_initialisation.push(Token::I(Process(AlternationEnd)));
+                blocks.push(alt_initialisation);
+
+                for (i, entry_label) in entry_labels.into_iter().enumerate() {
+                    let mut entry = Block {
+                        label: Some(entry_label),
+                        ..Block::default()
+                    };
+                    // The received value is on top of the mask's words
+                    CodeGenerator::read_local(&mut entry, kept + 1);
+                    entry.push(Token::N(1 << i));
+                    entry.push(Token::I(ArithmeticOrLogic(LogicalOr)));
+                    CodeGenerator::write_local(&mut entry, kept + 1);
+                    entry.push(Token::N(i as u16));
+                    // The last entry falls through to the body
+                    if i as u16 != n - 1 {
+                        entry.push(Token::L(body.label.clone().unwrap()));
+                        entry.push(Token::I(Jump(Always)));
+                    }
+                    blocks.push(entry);
+                }
+
+                let check_label = self.fresh_label();
+                let (body_blocks, _) =
+                    self.assemble_term(body, false, &Some(check_label.to_string()), &None, &None)?;
+                blocks.extend(body_blocks);
+
+                let mut check_block = Block {
+                    label: Some(check_label),
+                    ..Block::default()
+                };
+                if self.auto_yield {
+                    check_block.push(Token::I(Process(Yield)));
+                }
+                CodeGenerator::read_local(&mut check_block, kept);
+                check_block.push(Token::N(((1u32 << n) - 1) as u16));
+                check_block.push(Token::I(ArithmeticOrLogic(Compare)));
+                check_block.push(Token::L(round_label));
+                check_block.push(Token::I(Jump(NotZeroNotEqual)));
+                CodeGenerator::dig(&mut check_block, kept);
+                check_block.push(Token::I(Stack(Drop)));
+                CodeGenerator::extend_with_exit(&mut check_block, as_function, exit_label);
+                blocks.push(check_block);
+                return Ok((blocks, false));
+            }
         }
         CodeGenerator::extend_with_exit(&mut block, as_function, exit_label);
 
         Ok((vec![block], false))
     }
 
+    /**
+     * Calls the condition of an arm, if it has one, and branches to the returned label if it is
+     * false. The caller must start a new block with that label once it has enabled or disabled
+     * the guard.
+     */
+    fn assemble_condition(&self, arm: &AlternationArm, block: &mut Block) -> Option<String> {
+        use Condition::*;
+        use FunctionOp::*;
+        use Instruction::*;
+        use Op::*;
+
+        let condition = arm.condition.as_ref()?;
+        let skip_label = self.fresh_label();
+        block.push(Token::L(condition.label.clone().unwrap()));
+        block.push(Token::I(Function(Call)));
+        block.push(Token::N(0));
+        block.push(Token::I(ArithmeticOrLogic(Compare)));
+        block.push(Token::L(skip_label.to_string()));
+        block.push(Token::I(Jump(ZeroEqual)));
+        Some(skip_label)
+    }
+
+    /**
+     * Branches to the returned label if the mask `offset` words down has the bit for channel `i`
+     * of an alt_n set. The caller must start a new block with that label once it has enabled or
+     * disabled the channel.
+     */
+    fn skip_if_received(&self, block: &mut Block, offset: u16, i: u16) -> String {
+        use Condition::*;
+        use Instruction::*;
+        use Op::*;
+
+        let skip_label = self.fresh_label();
+        CodeGenerator::read_local(block, offset);
+        block.push(Token::N(1 << i));
+        block.push(Token::I(ArithmeticOrLogic(Test)));
+        block.push(Token::L(skip_label.to_string()));
+        block.push(Token::I(Jump(NotZeroNotEqual)));
+        skip_label
+    }
+
+    /**
+     * Returns the label that the body of a loop should exit to in order to start the next
+     * iteration at `target`. When yields are inserted on back-edges this is a new block, which is
+     * also returned and must be placed after the body.
+     */
+    fn back_edge(&self, target: &Option<String>) -> (Option<String>, Option<Block>) {
+        if self.auto_yield {
+            let label = self.fresh_label();
+            let mut block = Block {
+                label: Some(label.to_string()),
+                ..Block::default()
+            };
+            block.push(Token::I(Instruction::Process(ProcessOp::Yield)));
+            CodeGenerator::extend_with_exit(&mut block, false, target);
+            (Some(label), Some(block))
+        } else {
+            (target.clone(), None)
+        }
+    }
+
+    /// Whether the arms of a case are close enough together to be worth a jump table
+    fn is_dense(arms: &[CaseArm]) -> bool {
+        let min = arms.iter().map(|arm| arm.value).min();
+        let max = arms.iter().map(|arm| arm.value).max();
+        match (min, max) {
+            (Some(min), Some(max)) => {
+                arms.len() >= MIN_JUMP_TABLE_ARMS && ((max - min) as usize) < 2 * arms.len()
+            }
+            _ => false,
+        }
+    }
+
+    /**
+     * Dispatches on the value on top of the stack by pushing a label for every value in the range
+     * of the arms, selecting one with a read from the stack, and then jumping to it. Values outside
+     * of the range fall through to the default arm, which must be placed immediately afterwards.
+     * The arms are jumped to directly, so none of them need a block placing before them.
+     */
+    fn assemble_jump_table(
+        &self,
+        arms: &[CaseArm],
+        default: &Term,
+    ) -> (Vec<Block>, Vec<Option<Block>>) {
+        use Condition::*;
+        use Instruction::*;
+        use Op::*;
+        use StackOp::*;
+
+        let min = arms.iter().map(|arm| arm.value).min().unwrap();
+        let max = arms.iter().map(|arm| arm.value).max().unwrap();
+        let span = max - min + 1;
+        let out_of_range_label = self.fresh_label();
+
+        let mut dispatch = Block::default();
+        if min > 0 {
+            dispatch.push(Token::N(min));
+            dispatch.push(Token::I(ArithmeticOrLogic(Sub)));
+        }
+        // Negative values wrap around, so a single unsigned comparison checks both bounds
+        dispatch.push(Token::I(Stack(Dup)));
+        dispatch.push(Token::N(span));
+        dispatch.push(Token::I(ArithmeticOrLogic(Compare)));
+        dispatch.push(Token::L(out_of_range_label.to_string()));
+        dispatch.push(Token::I(Jump(UnsignedGreaterOrEqual)));
+
+        // The table is pushed so that the label for the smallest value is on top of the stack
+        for value in (min..=max).rev() {
+            let term = match arms.iter().find(|arm| arm.value == value) {
+                Some(arm) => &arm.term,
+                None => default,
+            };
+            dispatch.push(Token::L(term.label.clone().unwrap()));
+        }
+        dispatch.push(Token::N(span));
+        dispatch.push(Token::I(ReadLocal));
+        dispatch.push(Token::I(ReadLocal));
+        // Overwrite the value with the selected label and discard the rest of the table
+        dispatch.push(Token::N(span));
+        dispatch.push(Token::I(WriteLocal));
+        for _ in 0..span {
+            dispatch.push(Token::I(Stack(Drop)));
+        }
+        dispatch.push(Token::I(Jump(Always)));
+
+        let mut out_of_range = Block {
+            label: Some(out_of_range_label),
+            ..Block::default()
+        };
+        out_of_range.push(Token::I(Stack(Drop)));
+
+        (
+            vec![dispatch, out_of_range],
+            arms.iter().map(|_| None).collect(),
+        )
+    }
+
+    /**
+     * Dispatches on the value on top of the stack by comparing it against each arm in turn. The
+     * value is dropped before falling through to the default arm, which must be placed
+     * immediately afterwards, and by a block placed before each of the other arms.
+     */
+    fn assemble_compare_chain(&self, arms: &[CaseArm]) -> (Vec<Block>, Vec<Option<Block>>) {
+        use Condition::*;
+        use Instruction::*;
+        use Op::*;
+        use StackOp::*;
+
+        let mut dispatch = Block::default();
+        let mut entries = vec![];
+        for arm in arms {
+            let entry_label = self.fresh_label();
+            dispatch.push(Token::I(Stack(Dup)));
+            dispatch.push(Token::N(arm.value));
+            dispatch.push(Token::I(ArithmeticOrLogic(Compare)));
+            dispatch.push(Token::L(entry_label.to_string()));
+            dispatch.push(Token::I(Jump(ZeroEqual)));
+
+            let mut entry = Block {
+                label: Some(entry_label),
+                ..Block::default()
+            };
+            entry.push(Token::I(Stack(Drop)));
+            entries.push(Some(entry));
+        }
+        dispatch.push(Token::I(Stack(Drop)));
+        (vec![dispatch], entries)
+    }
+
     fn extend_with_exit(block: &mut Block, as_function: bool, exit_label: &Option<String>) {
         use Condition::*;
         use FunctionOp::*;
@@ -535,10 +1100,167 @@ impl CodeGenerator {
         }
     }
 
+    /// Pushes the value `offset` words beneath the top of the stack
+    fn read_local(block: &mut Block, offset: u16) {
+        match u8::try_from(offset) {
+            Ok(k) if Instruction::fits_immediate(k) => {
+                block.push(Token::I(Instruction::ReadLocalOffset(k)))
+            }
+            _ => {
+                block.push(Token::N(offset));
+                block.push(Token::I(Instruction::ReadLocal));
+            }
+        }
+    }
+
+    /// Pops the top of the stack and writes it `offset` words beneath the new top
+    fn write_local(block: &mut Block, offset: u16) {
+        match u8::try_from(offset) {
+            Ok(k) if Instruction::fits_immediate(k) => {
+                block.push(Token::I(Instruction::WriteLocalOffset(k)))
+            }
+            _ => {
+                block.push(Token::N(offset));
+                block.push(Token::I(Instruction::WriteLocal));
+            }
+        }
+    }
+
+    /// Removes the `bindings` values of a let from beneath the `results` values pushed by its body
+    fn remove_bindings(block: &mut Block, bindings: usize, results: usize) {
+        use Instruction::*;
+        use StackOp::*;
+        // Move each result down in to its final position, starting with the deepest
+        for i in 0..results {
+            CodeGenerator::read_local(block, (results - 1 - i) as u16);
+            CodeGenerator::write_local(block, (results + bindings - 1 - i) as u16);
+        }
+        for _ in 0..bindings {
+            block.push(Token::I(Stack(Drop)));
+        }
+    }
+
+    /**
+     * Starts each branch in its own process, with the first branch taking the deepest words, then
+     * waits for them all so that their results are left in the same order.
+     */
+    fn assemble_parallel(&self, block: &mut Block, branches: &[ParallelBranch]) {
+        // Start the branches from the top of the stack down, keeping the handles of those already
+        // started above the words of the next
+        for (handles, branch) in branches.iter().rev().enumerate() {
+            let handles = handles as u16;
+            if branch.words > 0 {
+                for _ in 0..handles {
+                    CodeGenerator::bury_top(block, branch.words + handles - 1);
+                }
+            }
+            let mut words = branch.words;
+            if let Some(index) = branch.index {
+                block.push(Token::N(index));
+                words += 1;
+            }
+            block.push(Token::L(branch.label.to_string()));
+            self.spawn(block, words, branch.results);
+        }
+        // The first branch's handle is now on top, so its results are pushed first
+        let mut results = 0;
+        for branch in branches {
+            CodeGenerator::dig(block, results);
+            CodeGenerator::join(block, branch.results);
+            results += branch.results;
+        }
+    }
+
+    /**
+     * Starts the function on top of the stack in a new process with the `k` words beneath it. The
+     * process sends its `m` results back over a fresh channel, which is left as the handle.
+     */
+    fn spawn(&self, block: &mut Block, k: u16, m: u16) {
+        use Instruction::*;
+        use ProcessOp::*;
+        use StackOp::*;
+        // The child gets a copy of the handle beneath its function
+        block.push(Token::I(Process(CreateChannel)));
+        block.push(Token::I(Stack(Dup)));
+        CodeGenerator::bury_top(block, k + 2);
+        block.push(Token::L(
+            self.use_library_routine(LibraryRoutine::Spawn(k, m)),
+        ));
+        block.push(Token::N(k + 2));
+        block.push(Token::I(Process(Start)));
+    }
+
+    /// Replaces the handle on top of the stack with the `m` results of its process
+    fn join(block: &mut Block, m: u16) {
+        use Instruction::*;
+        use ProcessOp::*;
+        use StackOp::*;
+        for _ in 0..m {
+            block.push(Token::I(Process(Receive)));
+            block.push(Token::I(Stack(Swap)));
+        }
+        // The process finally sends a word to say that it has finished
+        block.push(Token::I(Process(Receive)));
+        block.push(Token::I(Stack(Drop)));
+        block.push(Token::I(Process(DestroyChannel)));
+    }
+
+    /// Moves the top of the stack beneath the `n` values below it
+    fn bury_top(block: &mut Block, n: u16) {
+        use Instruction::*;
+        block.push(Token::N(0));
+        block.push(Token::I(ReadLocal));
+        // Shift each value up by one, overwriting the original top first
+        for i in 1..=n {
+            block.push(Token::N(i + 1));
+            block.push(Token::I(ReadLocal));
+            block.push(Token::N(i));
+            block.push(Token::I(WriteLocal));
+        }
+        block.push(Token::N(n));
+        block.push(Token::I(WriteLocal));
+    }
+
+    /// Moves the value beneath the top `n` values to the top of the stack
+    fn dig(block: &mut Block, n: u16) {
+        use Instruction::*;
+        if n == 0 {
+            return;
+        }
+        block.push(Token::N(n));
+        block.push(Token::I(ReadLocal));
+        // Shift each value down by one, overwriting the original first
+        for i in (0..n).rev() {
+            block.push(Token::N(i + 1));
+            block.push(Token::I(ReadLocal));
+            block.push(Token::N(i + 2));
+            block.push(Token::I(WriteLocal));
+        }
+        block.push(Token::N(0));
+        block.push(Token::I(WriteLocal));
+    }
+
+    /// The number of results of the function on top of the stack before a spawn
+    fn spawn_result_count(expr: &Expression) -> u16 {
+        if let Some(Type::Function(i, _)) = &expr.e_type {
+            if let types::Stack::Stack(_, f) = i.deref() {
+                if let Type::Function(_, o) = f.deref() {
+                    return o.height() as u16;
+                }
+            }
+        }
+        panic!("spawn wasn't given a function by the type checker");
+    }
+
     fn fresh_label(&self) -> String {
         self.label_counter.borrow_mut().next()
     }
 
+    fn use_library_routine(&self, routine: LibraryRoutine) -> String {
+        self.library_routines.borrow_mut().insert(routine);
+        routine.label()
+    }
+
     fn collapse_adjacent_blocks(&self, blocks: Vec<Block>) -> CodegenResult<Vec<Block>> {
         // TODO: Make this generic
         #[derive(Debug)]
@@ -660,7 +1382,17 @@ impl CodeGenerator {
         for (i, block) in collapsed_blocks.iter().enumerate() {
             let mut new_block = Block::default();
             new_block.comment = block.comment.clone();
-            new_block.label = Some(block_sets.find(block.label.as_ref().unwrap()));
+            // A block that immediately jumps elsewhere now shares that block's label, so it is
+            // only reached by falling through to it
+            let is_alias = match block.tokens.as_slice() {
+                [Token::L(l), Token::I(Instruction::Jump(Condition::Always)), ..] => {
+                    l != block.label.as_ref().unwrap()
+                }
+                _ => false,
+            };
+            if !is_alias {
+                new_block.label = Some(block_sets.find(block.label.as_ref().unwrap()));
+            }
             for (j, tok) in block.tokens.iter().enumerate() {
                 if j < max_lens[i] {
                     if let Token::L(l) = tok {
@@ -681,6 +1413,15 @@ impl CodeGenerator {
     }
 }
 
+/// A process started by a par, which runs the function at `label` on `words` words
+struct ParallelBranch {
+    label: String,
+    words: u16,
+    results: u16,
+    /// Pushed above the words of a copy started by a replicated par
+    index: Option<u16>,
+}
+
 #[derive(Default)]
 struct LabelCounter {
     counter: usize,
@@ -0,0 +1,332 @@
+use super::*;
+
+/**
+ * Stannel has no multiply or divide instructions, so these are implemented as shift-and-add (or
+ * shift-and-subtract) routines. A routine is only included in the output if the program uses it.
+ */
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum LibraryRoutine {
+    Multiply,
+    DivMod,
+    /// Truncating signed division, which relies on `DivMod` also being included
+    SignedDivMod,
+    /// The entry point of a spawned process that takes some words and returns some results
+    Spawn(u16, u16),
+}
+
+impl LibraryRoutine {
+    pub fn label(self) -> String {
+        match self {
+            LibraryRoutine::Multiply => "lib_mul".to_string(),
+            LibraryRoutine::DivMod => "lib_divmod".to_string(),
+            LibraryRoutine::SignedDivMod => "lib_sdivmod".to_string(),
+            LibraryRoutine::Spawn(k, m) => format!("lib_spawn_{}_{}", k, m),
+        }
+    }
+
+    pub fn blocks(self) -> Vec<Block> {
+        match self {
+            LibraryRoutine::Multiply => multiply(),
+            LibraryRoutine::DivMod => div_mod(),
+            LibraryRoutine::SignedDivMod => signed_div_mod(),
+            LibraryRoutine::Spawn(k, m) => spawn(k, m),
+        }
+    }
+}
+
+fn block(label: String, tokens: Vec<Token>) -> Block {
+    Block {
+        comment: None,
+        label: Some(label),
+        tokens,
+    }
+}
+
+/// S × a × b → S × (a * b), modulo 2^16
+fn multiply() -> Vec<Block> {
+    use Condition::*;
+    use FunctionOp::*;
+    use Instruction::*;
+    use Op::*;
+    use StackOp::*;
+    use Token::*;
+
+    let start = LibraryRoutine::Multiply.label();
+    let check = format!("{}_check", start);
+    let shift = format!("{}_shift", start);
+    let done = format!("{}_done", start);
+
+    let mut start_block = block(
+        start,
+        vec![
+            // The result is kept beneath the operands: r a b
+            N(0),
+            I(Stack(Rot)),
+        ],
+    );
+    start_block.comment = Some("* :: ∀ S . S × int × int → S × int".to_string());
+    vec![
+        start_block,
+        block(
+            check.to_string(),
+            vec![
+                I(Stack(Dup)),
+                N(0),
+                I(ArithmeticOrLogic(Compare)),
+                L(done.to_string()),
+                I(Jump(ZeroEqual)),
+                // Only add a to the result if the lowest bit of b is set
+                I(Stack(Dup)),
+                N(1),
+                I(ArithmeticOrLogic(Test)),
+                L(shift.to_string()),
+                I(Jump(ZeroEqual)),
+                N(1),
+                I(ReadLocal),
+                N(3),
+                I(ReadLocal),
+                I(ArithmeticOrLogic(Add)),
+                N(2),
+                I(WriteLocal),
+            ],
+        ),
+        block(
+            shift,
+            vec![
+                I(Stack(Swap)),
+                N(1),
+                I(ArithmeticOrLogic(LogicalShiftLeft)),
+                I(Stack(Swap)),
+                N(1),
+                I(ArithmeticOrLogic(LogicalShiftRight)),
+                L(check),
+                I(Jump(Always)),
+            ],
+        ),
+        block(
+            done,
+            vec![I(Stack(Drop)), I(Stack(Drop)), I(Function(Return))],
+        ),
+    ]
+}
+
+/// S × n × d → S × (n / d) × (n % d). Dividing by zero results in 0xFFFF and n.
+fn div_mod() -> Vec<Block> {
+    use Condition::*;
+    use FunctionOp::*;
+    use Instruction::*;
+    use Op::*;
+    use StackOp::*;
+    use Token::*;
+
+    let start = LibraryRoutine::DivMod.label();
+    let step = format!("{}_step", start);
+    let next = format!("{}_next", start);
+
+    let mut start_block = block(
+        start,
+        vec![
+            // The quotient, remainder and the number of bits left to consider: n d q r i
+            N(0),
+            N(0),
+            N(16),
+        ],
+    );
+    start_block.comment = Some("divmod :: ∀ S . S × int × int → S × int × int".to_string());
+    vec![
+        start_block,
+        block(
+            step.to_string(),
+            vec![
+                // r = (r << 1) | (n >> 15)
+                N(1),
+                I(ReadLocal),
+                N(1),
+                I(ArithmeticOrLogic(LogicalShiftLeft)),
+                N(5),
+                I(ReadLocal),
+                N(15),
+                I(ArithmeticOrLogic(LogicalShiftRight)),
+                I(ArithmeticOrLogic(LogicalOr)),
+                N(1),
+                I(WriteLocal),
+                // n = n << 1
+                N(4),
+                I(ReadLocal),
+                N(1),
+                I(ArithmeticOrLogic(LogicalShiftLeft)),
+                N(4),
+                I(WriteLocal),
+                // q = q << 1
+                N(2),
+                I(ReadLocal),
+                N(1),
+                I(ArithmeticOrLogic(LogicalShiftLeft)),
+                N(2),
+                I(WriteLocal),
+                // if r >= d then r = r - d and q = q | 1
+                N(1),
+                I(ReadLocal),
+                N(4),
+                I(ReadLocal),
+                I(ArithmeticOrLogic(Compare)),
+                L(next.to_string()),
+                I(Jump(UnsignedLess)),
+                N(1),
+                I(ReadLocal),
+                N(4),
+                I(ReadLocal),
+                I(ArithmeticOrLogic(Sub)),
+                N(1),
+                I(WriteLocal),
+                N(2),
+                I(ReadLocal),
+                N(1),
+                I(ArithmeticOrLogic(LogicalOr)),
+                N(2),
+                I(WriteLocal),
+            ],
+        ),
+        block(
+            next,
+            vec![
+                N(1),
+                I(ArithmeticOrLogic(Sub)),
+                L(step),
+                I(Jump(NotZeroNotEqual)),
+                I(Stack(Drop)),
+                // n d q r → q r
+                I(Stack(Tuck)),
+                I(Stack(Drop)),
+                I(Stack(Tuck)),
+                I(Stack(Drop)),
+                I(Function(Return)),
+            ],
+        ),
+    ]
+}
+
+/// S × n × d → S × (n / d) × (n % d), rounding the quotient towards zero so that the remainder
+/// has the sign of n. Each value is negated when its mask m is all ones, as (x xor m) - m.
+fn signed_div_mod() -> Vec<Block> {
+    use FunctionOp::*;
+    use Instruction::*;
+    use Op::*;
+    use StackOp::*;
+    use Token::*;
+
+    let mut start_block = block(
+        LibraryRoutine::SignedDivMod.label(),
+        vec![
+            // The sign mask of the remainder and quotient: n d mr mq
+            N(1),
+            I(ReadLocal),
+            N(15),
+            I(ArithmeticOrLogic(ArithmeticShiftRight)),
+            N(0),
+            I(ReadLocal),
+            N(2),
+            I(ReadLocal),
+            N(15),
+            I(ArithmeticOrLogic(ArithmeticShiftRight)),
+            I(ArithmeticOrLogic(LogicalXor)),
+            // n = |n|, using mr
+            N(3),
+            I(ReadLocal),
+            N(2),
+            I(ReadLocal),
+            I(ArithmeticOrLogic(LogicalXor)),
+            N(2),
+            I(ReadLocal),
+            I(ArithmeticOrLogic(Sub)),
+            N(3),
+            I(WriteLocal),
+            // d = |d|
+            N(2),
+            I(ReadLocal),
+            N(15),
+            I(ArithmeticOrLogic(ArithmeticShiftRight)),
+            N(3),
+            I(ReadLocal),
+            N(1),
+            I(ReadLocal),
+            I(ArithmeticOrLogic(LogicalXor)),
+            I(Stack(Swap)),
+            I(ArithmeticOrLogic(Sub)),
+            N(2),
+            I(WriteLocal),
+            // n d mr mq q r
+            N(3),
+            I(ReadLocal),
+            N(3),
+            I(ReadLocal),
+            L(LibraryRoutine::DivMod.label()),
+            I(Function(Call)),
+            // Give the remainder the sign of n
+            N(3),
+            I(ReadLocal),
+            I(ArithmeticOrLogic(LogicalXor)),
+            N(3),
+            I(ReadLocal),
+            I(ArithmeticOrLogic(Sub)),
+            // Negate the quotient if exactly one of n and d was negative
+            I(Stack(Swap)),
+            N(2),
+            I(ReadLocal),
+            I(ArithmeticOrLogic(LogicalXor)),
+            N(2),
+            I(ReadLocal),
+            I(ArithmeticOrLogic(Sub)),
+            I(Stack(Swap)),
+            // n d mr mq q r → q r
+            N(1),
+            I(ReadLocal),
+            N(5),
+            I(WriteLocal),
+            N(0),
+            I(ReadLocal),
+            N(4),
+            I(WriteLocal),
+            I(Stack(Drop)),
+            I(Stack(Drop)),
+            I(Stack(Drop)),
+            I(Stack(Drop)),
+            I(Function(Return)),
+        ],
+    );
+    start_block.comment = Some("sdivmod :: ∀ S . S × sint × sint → S × sint × sint".to_string());
+    vec![start_block]
+}
+
+/// Runs the function beneath the handle with the `k` words beneath it, sends its `m` results back
+/// over the handle in order, and then sends a final word so that join knows it has finished.
+fn spawn(k: u16, m: u16) -> Vec<Block> {
+    use FunctionOp::*;
+    use Instruction::*;
+    use ProcessOp::*;
+    use StackOp::*;
+    use Token::*;
+
+    let label = LibraryRoutine::Spawn(k, m).label();
+    let mut start_block = block(label, vec![]);
+    start_block.comment = Some(format!("spawn with {} word(s) and {} result(s)", k, m));
+    CodeGenerator::bury_top(&mut start_block, k + 1);
+    start_block.push(I(Function(Call)));
+    // The handle is now beneath the results, with the first result the deepest
+    for i in 0..m {
+        start_block.push(N(m));
+        start_block.push(I(ReadLocal));
+        start_block.push(N(m - i));
+        start_block.push(I(ReadLocal));
+        start_block.push(I(Process(Send)));
+        start_block.push(I(Stack(Drop)));
+    }
+    for _ in 0..m {
+        start_block.push(I(Stack(Drop)));
+    }
+    start_block.push(N(0));
+    start_block.push(I(Process(Send)));
+    start_block.push(I(Stack(Drop)));
+    start_block.push(I(Process(End)));
+    vec![start_block]
+}
@@ -36,45 +36,29 @@ pub fn peephole(tokens: &[Token]) -> Vec<Token> {
                 }
                 _ => {}
             },
+            I(ArithmeticOrLogic(LogicalNot)) => {
+                if let Some(N(n)) = new_toks.back() {
+                    let n = !*n;
+                    new_toks.pop_back();
+                    new_toks.push_back(N(n));
+                    did_opt = true;
+                }
+            }
             I(ArithmeticOrLogic(o)) => match new_toks.pop_back() {
                 Some(N(n1)) => match new_toks.pop_back() {
-                    Some(N(n2)) => match o {
-                        Add => {
-                            new_toks.push_back(N(n1 + n2));
-                            did_opt = true
-                        }
-                        Sub => {
-                            new_toks.push_back(N(n2 - n1));
+                    Some(N(n2)) => match fold(*o, n2, n1) {
+                        Some(n) => {
+                            new_toks.push_back(N(n));
                             did_opt = true
                         }
-                        LogicalAnd => {
-                            new_toks.push_back(N(n1 & n2));
-                            did_opt = true
-                        }
-                        LogicalOr => {
-                            new_toks.push_back(N(n1 | n2));
-                            did_opt = true
-                        }
-                        LogicalXor => {
-                            new_toks.push_back(N(n1 ^ n2));
-                            did_opt = true
-                        }
-                        LogicalNot => {
-                            new_toks.push_back(N(n2));
-                            new_toks.push_back(N(!n1));
-                        }
-                        _ => {
+                        None => {
                             new_toks.push_back(N(n2));
                             new_toks.push_back(N(n1));
                         }
                     },
                     Some(s) => {
                         new_toks.push_back(s);
-                        if let LogicalNot = o {
-                            new_toks.push_back(N(!n1));
-                        } else {
-                            new_toks.push_back(N(n1));
-                        }
+                        new_toks.push_back(N(n1));
                     }
                     None => new_toks.push_back(N(n1)),
                 },
@@ -92,3 +76,21 @@ pub fn peephole(tokens: &[Token]) -> Vec<Token> {
 
     Vec::from(new_toks)
 }
+
+/// Evaluates a binary operation on constants in the same way as the processor
+fn fold(op: Op, a: u16, b: u16) -> Option<u16> {
+    use Op::*;
+    match op {
+        Add => Some(a.wrapping_add(b)),
+        Sub => Some(a.wrapping_sub(b)),
+        LogicalAnd => Some(a & b),
+        LogicalOr => Some(a | b),
+        LogicalXor => Some(a ^ b),
+        LogicalShiftLeft => a.checked_shl(b.into()),
+        LogicalShiftRight => a.checked_shr(b.into()),
+        ArithmeticShiftLeft => (a as i16).checked_shl(b.into()).map(|n| n as u16),
+        ArithmeticShiftRight => (a as i16).checked_shr(b.into()).map(|n| n as u16),
+        // Not is unary, and the others only affect the flags
+        LogicalNot | Test | Compare => None,
+    }
+}
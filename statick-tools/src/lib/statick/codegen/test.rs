@@ -18,7 +18,7 @@ fn parse_and_check(src: &str) -> Program {
 }
 
 fn compile_blocks(src: &str) -> CodegenResult<Vec<Block>> {
-    let blocks = codegen_blocks(parse_and_check(src))?;
+    let blocks = codegen_blocks(parse_and_check(src), false)?;
     println!("{}", CodeGenerator::flatten(&blocks));
     Ok(blocks)
 }
@@ -56,3 +56,80 @@ fn if_returns() -> CodegenResult<()> {
     assert_eq!(blocks[2].tokens[blocks[2].tokens.len()-1], I(Function(Return)));
     Ok(())
 }
+
+fn uses_jump_table(blocks: &[Block]) -> bool {
+    blocks
+        .iter()
+        .any(|b| b.tokens.contains(&I(Jump(UnsignedGreaterOrEqual))))
+}
+
+#[test]
+fn dense_case_uses_jump_table() -> CodegenResult<()> {
+    let blocks = compile_blocks("main = 1 case [ 0 -> 5 | 1 -> 6 | 3 -> 7 | else -> 8 ]")?;
+    assert!(uses_jump_table(&blocks));
+    Ok(())
+}
+
+#[test]
+fn sparse_case_uses_comparisons() -> CodegenResult<()> {
+    let blocks = compile_blocks("main = 1 case [ 0 -> 5 | 10 -> 6 | 30 -> 7 | else -> 8 ]")?;
+    assert!(!uses_jump_table(&blocks));
+    let blocks = compile_blocks("main = 1 case [ 0 -> 5 | 1 -> 6 | else -> 8 ]")?;
+    assert!(!uses_jump_table(&blocks));
+    Ok(())
+}
+
+#[test]
+fn tail_call_is_a_jump() -> CodegenResult<()> {
+    let blocks = compile_blocks("main = 0 go go = if (dup 10 <) then (1 + go) else ()")?;
+    let tail_call = [L("f_go".to_string()), I(Jump(Always))];
+    assert!(blocks.iter().any(|b| b.tokens.ends_with(&tail_call)));
+    // Including the call from main to go
+    assert!(!blocks.iter().any(|b| b.tokens.contains(&I(Function(Call)))));
+    Ok(())
+}
+
+#[test]
+fn function_that_only_jumps_doesnt_duplicate_a_label() -> CodegenResult<()> {
+    let blocks = compile_blocks("main = 1 helper\nhelper = other\nother = 2 +")?;
+    let labels: Vec<&String> = blocks.iter().filter_map(|b| b.label.as_ref()).collect();
+    let unique: HashSet<&String> = labels.iter().cloned().collect();
+    assert_eq!(labels.len(), unique.len());
+    Ok(())
+}
+
+fn count_yields(blocks: &[Block]) -> usize {
+    blocks
+        .iter()
+        .map(|b| {
+            b.tokens
+                .iter()
+                .filter(|t| **t == I(Process(ProcessOp::Yield)))
+                .count()
+        })
+        .sum()
+}
+
+#[test]
+fn auto_yield_on_back_edges() -> CodegenResult<()> {
+    let src = "main = 0 while (dup 3 <) do (1 +) repeat_2 (swap 1 + swap) repeat (.)";
+    assert_eq!(
+        count_yields(&codegen_blocks(parse_and_check(src), false)?),
+        0
+    );
+    assert_eq!(
+        count_yields(&codegen_blocks(parse_and_check(src), true)?),
+        3
+    );
+    Ok(())
+}
+
+#[test]
+fn let_reads_and_removes_bindings_with_offsets() -> CodegenResult<()> {
+    let blocks = compile_blocks("main = 1 2 let a b in (a b +) drop")?;
+    let tokens: Vec<&Token> = blocks.iter().flat_map(|b| b.tokens.iter()).collect();
+    assert!(tokens.contains(&&I(ReadLocalOffset(1))));
+    assert!(tokens.contains(&&I(WriteLocalOffset(2))));
+    assert!(!tokens.contains(&&I(ReadLocal)));
+    Ok(())
+}
@@ -1,22 +1,42 @@
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use super::ast::{Import, Program};
 use super::codegen::{codegen, CodegenError};
-use super::lexer::{lex, LexerError};
-use super::parser::{parse, ParserError};
-use super::types::{type_check, TypeError};
+use super::diagnostic::{Diagnostic, SourceFiles};
+use super::lexer::{lex, lex_file, LexerError, Span, Token};
+use super::parser::{parse_recovering, ParserError};
+use super::types::{type_check, Hole, TypeError};
 
 #[derive(Debug)]
 pub enum CompileError {
     FileOpen,
     FileRead,
+    ModuleNotFound(Import, PathBuf),
+    ModuleConflict(String, PathBuf, PathBuf),
+    Module(String, Box<CompileError>),
     Lexer(LexerError),
     Parser(ParserError),
     Type(TypeError),
     Codegen(CodegenError),
+    /// The program type checked, but code can't be generated until its holes are filled
+    Holes(Vec<Hole>),
+    /// Every error found before compilation stopped, in the order they were found
+    Multiple(Vec<CompileError>),
+}
+
+impl CompileError {
+    fn from_errors(mut errors: Vec<CompileError>) -> CompileError {
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            CompileError::Multiple(errors)
+        }
+    }
 }
 
 impl From<LexerError> for CompileError {
@@ -48,37 +68,168 @@ impl fmt::Display for CompileError {
         match self {
             CompileError::FileOpen => write!(f, "could not open file"),
             CompileError::FileRead => write!(f, "could not read file"),
+            CompileError::ModuleNotFound(import, path) => write!(
+                f,
+                "could not find module {} (imported on line {}) at {}",
+                import.module,
+                import.source.line_number,
+                path.display()
+            ),
+            CompileError::ModuleConflict(module, first, second) => write!(
+                f,
+                "module {} refers to both {} and {}",
+                module,
+                first.display(),
+                second.display()
+            ),
+            CompileError::Module(module, e) => write!(f, "In module {}: {}", module, e),
             CompileError::Lexer(e) => write!(f, "Lexer: {}", e),
             CompileError::Parser(e) => write!(f, "Parser: {}", e),
             CompileError::Type(e) => write!(f, "Type: {}", e),
             CompileError::Codegen(e) => write!(f, "Codegen: {}", e),
+            CompileError::Holes(holes) if holes.len() == 1 => {
+                write!(
+                    f,
+                    "the program has a hole to fill before it can be compiled"
+                )
+            }
+            CompileError::Holes(holes) => write!(
+                f,
+                "the program has {} holes to fill before it can be compiled",
+                holes.len()
+            ),
+            CompileError::Multiple(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
 impl Error for CompileError {}
 
-pub fn compile<P>(path: P, output_types: bool) -> Result<String, CompileError>
+impl CompileError {
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            CompileError::ModuleNotFound(import, _) => {
+                let span = Span {
+                    start: import.source,
+                    end: import.source,
+                };
+                vec![Diagnostic::new(self.to_string(), Some(span))]
+            }
+            // Spans record which file they are in, so the module doesn't need to be named
+            CompileError::Module(_, e) => e.diagnostics(),
+            CompileError::Lexer(e) => e.diagnostics(),
+            CompileError::Parser(e) => vec![e.diagnostic()],
+            CompileError::Type(e) => vec![e.diagnostic()],
+            CompileError::Holes(holes) => holes
+                .iter()
+                .map(Hole::diagnostic)
+                .chain(std::iter::once(Diagnostic::new(self.to_string(), None)))
+                .collect(),
+            CompileError::Multiple(errors) => errors.iter().flat_map(|e| e.diagnostics()).collect(),
+            _ => vec![Diagnostic::new(self.to_string(), None)],
+        }
+    }
+
+    /// Describes the error, showing the code that caused it from the files that were compiled
+    pub fn render(&self, files: &SourceFiles) -> String {
+        self.diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.render(files))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompileOptions {
+    /// Print the type of each definition to standard out
+    pub output_types: bool,
+    /// Yield at the end of every loop iteration, so that processes that never block can't starve
+    /// other processes of a core
+    pub auto_yield: bool,
+}
+
+pub fn compile<P>(path: P, options: CompileOptions) -> Result<String, CompileError>
 where
     P: AsRef<Path>,
 {
-    let mut file = match File::open(path) {
-        Ok(f) => f,
-        Err(_) => return Err(CompileError::FileOpen),
-    };
-    let mut contents = String::new();
-    if file.read_to_string(&mut contents).is_err() {
-        return Err(CompileError::FileRead);
-    }
-    compile_str(&contents, output_types)
+    compile_with_sources(path, options, &mut SourceFiles::default())
 }
 
-pub fn compile_str(src: &str, output_types: bool) -> Result<String, CompileError> {
+/// Compiles a program, keeping the contents of every file read so that errors can be rendered
+pub fn compile_with_sources<P>(
+    path: P,
+    options: CompileOptions,
+    files: &mut SourceFiles,
+) -> Result<String, CompileError>
+where
+    P: AsRef<Path>,
+{
+    let mut loader = ModuleLoader::new(files);
+    match loader.load(path.as_ref()) {
+        Ok(program) => compile_program(program, loader.errors, options),
+        Err(e) => {
+            loader.errors.push(e);
+            Err(CompileError::from_errors(loader.errors))
+        }
+    }
+}
+
+/**
+ * Compiles a program that isn't stored in a file. Any modules that it imports are found in
+ * `directory`, as they would be next to a file.
+ */
+pub fn compile_str<P>(
+    src: &str,
+    directory: P,
+    options: CompileOptions,
+) -> Result<String, CompileError>
+where
+    P: AsRef<Path>,
+{
+    // Registered so that imported modules are given the file ids after the root's
+    let mut files = SourceFiles::default();
+    files.add(PathBuf::from("<input>"), src.to_string());
     let tokens = lex(src)?;
-    let mut program = parse(&tokens)?;
-    type_check(&mut program)?;
+    let mut loader = ModuleLoader::new(&mut files);
+    let program = loader.parse_tokens(&tokens, None);
+    match loader.load_imports(program, directory.as_ref()) {
+        Ok(program) => compile_program(program, loader.errors, options),
+        Err(e) => {
+            loader.errors.push(e);
+            Err(CompileError::from_errors(loader.errors))
+        }
+    }
+}
 
-    if output_types {
+/**
+ * Type checks and generates code for a program. Declarations with syntax errors have already been
+ * left out of the program, so the rest of it is still checked to find as many errors as possible.
+ */
+fn compile_program(
+    mut program: Program,
+    mut errors: Vec<CompileError>,
+    options: CompileOptions,
+) -> Result<String, CompileError> {
+    let result = type_check(&mut program);
+    if !program.holes.is_empty() {
+        errors.push(CompileError::Holes(std::mem::take(&mut program.holes)));
+    }
+    if let Err(e) = result {
+        errors.push(e.into());
+    }
+    if !errors.is_empty() {
+        return Err(CompileError::from_errors(errors));
+    }
+
+    if options.output_types {
         for decl in &program.declarations {
             println!("{} :: {}", decl.name, decl.term.t_type.as_ref().unwrap());
         }
@@ -86,9 +237,116 @@ pub fn compile_str(src: &str, output_types: bool) -> Result<String, CompileError
 
     // Can't directly return this because the error might need to be converted, an the Result type
     // doesn't do that automatically.
-    let res = codegen(program)?;
+    let res = codegen(program, options.auto_yield)?;
     Ok(res)
 }
 
+/**
+ * Reads a program and every module that it (transitively) imports into a single Program. A module
+ * called `name` is read from `name.st` in the same directory as the file that imports it. Each
+ * file is lexed with its own file id, which is the id that `files` gives it.
+ */
+struct ModuleLoader<'a> {
+    files: &'a mut SourceFiles,
+    modules: HashMap<String, PathBuf>,
+    /// Syntax errors, which don't stop the rest of the program from being loaded
+    errors: Vec<CompileError>,
+}
+
+impl<'a> ModuleLoader<'a> {
+    fn new(files: &'a mut SourceFiles) -> Self {
+        ModuleLoader {
+            files,
+            modules: HashMap::new(),
+            errors: vec![],
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Result<Program, CompileError> {
+        let program = self.parse_file(path, None)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        self.load_imports(program, directory)
+    }
+
+    fn load_imports(
+        &mut self,
+        mut program: Program,
+        directory: &Path,
+    ) -> Result<Program, CompileError> {
+        let mut pending = VecDeque::new();
+        for import in program.imports.get(&None).into_iter().flatten() {
+            pending.push_back((import.clone(), directory.to_path_buf()));
+        }
+
+        while let Some((import, directory)) = pending.pop_front() {
+            let path = directory.join(format!("{}.st", import.module));
+            let path = match fs::canonicalize(&path) {
+                Ok(path) => path,
+                Err(_) => return Err(CompileError::ModuleNotFound(import, path)),
+            };
+            if let Some(existing) = self.modules.get(&import.module) {
+                if *existing != path {
+                    return Err(CompileError::ModuleConflict(
+                        import.module,
+                        existing.clone(),
+                        path,
+                    ));
+                }
+                continue;
+            }
+            self.modules.insert(import.module.to_string(), path.clone());
+
+            let mut module = self
+                .parse_file(&path, Some(&import.module))
+                .map_err(|e| CompileError::Module(import.module.to_string(), Box::new(e)))?;
+            let module_name = Some(import.module.to_string());
+            let imports = module.imports.remove(&None).unwrap_or_default();
+            let module_directory = path.parent().unwrap_or_else(|| Path::new("."));
+            for import in &imports {
+                pending.push_back((import.clone(), module_directory.to_path_buf()));
+            }
+            for decl in &mut module.declarations {
+                decl.module = module_name.clone();
+            }
+            for constant in &mut module.constants {
+                constant.module = module_name.clone();
+            }
+            program.declarations.extend(module.declarations);
+            program.constants.extend(module.constants);
+            program.unparsed.extend(module.unparsed);
+            program.imports.insert(module_name, imports);
+        }
+
+        Ok(program)
+    }
+
+    fn parse_file(&mut self, path: &Path, module: Option<&str>) -> Result<Program, CompileError> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(_) => return Err(CompileError::FileOpen),
+        };
+        let mut contents = String::new();
+        if file.read_to_string(&mut contents).is_err() {
+            return Err(CompileError::FileRead);
+        }
+        let file_id = self.files.add(path.to_path_buf(), contents);
+        let tokens = lex_file(self.files.contents(file_id), file_id)?;
+        Ok(self.parse_tokens(&tokens, module))
+    }
+
+    /// Parses the tokens of a file, keeping its syntax errors to report along with any others
+    fn parse_tokens(&mut self, tokens: &[Token], module: Option<&str>) -> Program {
+        let (program, errors) = parse_recovering(tokens);
+        for e in errors {
+            let e = CompileError::Parser(e);
+            self.errors.push(match module {
+                Some(module) => CompileError::Module(module.to_string(), Box::new(e)),
+                None => e,
+            });
+        }
+        program
+    }
+}
+
 #[cfg(test)]
 mod test;
@@ -1,9 +1,12 @@
-use super::{compile_str, CompileError};
+use super::super::diagnostic::SourceFiles;
+use super::super::types::TypeError;
+use super::{compile, compile_str, compile_with_sources, CompileError, CompileOptions};
 use crate::assembler::{assemble, lex_str};
 use crate::Processor;
 use std::error::Error;
 use std::fmt;
 use std::fs;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 enum CompilerTestError {
@@ -45,7 +48,31 @@ impl Error for CompilerTestError {}
 type CompilerTestResult = Result<(), CompilerTestError>;
 
 fn compile_expect(name: &str, statick_src: &str, stacks: Vec<Vec<u16>>) -> CompilerTestResult {
-    let program = compile_str(statick_src, false)?;
+    let program = compile_str(statick_src, ".", CompileOptions::default())?;
+    run_expect(name, &program, stacks)
+}
+
+/// Writes each (file name, source) pair to a fresh directory and compiles the first file
+fn compile_files(name: &str, files: &[(&str, &str)]) -> Result<String, CompilerTestError> {
+    let directory = std::env::temp_dir().join(format!("statick_{}", name));
+    fs::create_dir_all(&directory)?;
+    for (file_name, src) in files {
+        fs::write(directory.join(file_name), src)?;
+    }
+    let path: PathBuf = directory.join(files[0].0);
+    Ok(compile(path, CompileOptions::default())?)
+}
+
+fn compile_files_expect(
+    name: &str,
+    files: &[(&str, &str)],
+    stacks: Vec<Vec<u16>>,
+) -> CompilerTestResult {
+    let program = compile_files(name, files)?;
+    run_expect(name, &program, stacks)
+}
+
+fn run_expect(name: &str, program: &str, stacks: Vec<Vec<u16>>) -> CompilerTestResult {
     println!("{}", program);
 
     let path = format!(
@@ -62,10 +89,10 @@ fn compile_expect(name: &str, statick_src: &str, stacks: Vec<Vec<u16>>) -> Compi
                 .join(" ")
         );
     }
-    src_to_write += &program;
+    src_to_write += program;
     fs::write(path, src_to_write)?;
 
-    let is = assemble(lex_str(&program)?)?;
+    let is = assemble(lex_str(program)?)?;
     let mut processor = Processor::default();
     processor.set_instructions(&is)?;
     processor.run(true)?;
@@ -183,6 +210,550 @@ fn basic_if() -> CompilerTestResult {
     Ok(())
 }
 
+#[test]
+fn numeric_literals() -> CompilerTestResult {
+    compile_expect(
+        "literals",
+        "main = 0xFF 0b1010 'A' 1_000 -0x1 fromSint",
+        vec![vec![65535, 1000, 65, 10, 255]],
+    )
+}
+
+#[test]
+fn letter_between_quotes_is_a_character_even_if_a_primed_name_exists() -> CompilerTestResult {
+    compile_expect(
+        "char_not_quote",
+        "f' = 1\nmain = 'f' ' f' apply",
+        vec![vec![1, 102]],
+    )
+}
+
+#[test]
+fn signed_comparisons() -> CompilerTestResult {
+    compile_expect(
+        "signed_if1",
+        "main = if (-3 2 toSint s<) then (13) else (12)",
+        vec![vec![13]],
+    )?;
+    compile_expect(
+        "signed_if2",
+        "main = if (-3 2 toSint s>) then (13) else (12)",
+        vec![vec![12]],
+    )?;
+    compile_expect(
+        "signed_if3",
+        "main = if (-3 -3 s<=) then (13) else (12)",
+        vec![vec![13]],
+    )?;
+    compile_expect(
+        "signed_if4",
+        "main = if (-32768 32767 toSint s>=) then (13) else (12)",
+        vec![vec![12]],
+    )?;
+    compile_expect(
+        "signed_if5",
+        "main = if (-3 fromSint 2 <) then (13) else (12)",
+        vec![vec![12]],
+    )?;
+    compile_expect(
+        "signed_cmp",
+        "main = -1 0 toSint s< -1 0 toSint s> 32767 toSint -32768 s>",
+        vec![vec![1, 0, 1]],
+    )
+}
+
+#[test]
+fn comparison_result_in_middle_of_term() -> CompilerTestResult {
+    compile_expect("cmp_middle1", "main = 1 2 < 7", vec![vec![7, 1]])?;
+    compile_expect("cmp_middle2", "main = 2 1 < 7", vec![vec![7, 0]])
+}
+
+#[test]
+fn signed_arithmetic() -> CompilerTestResult {
+    compile_expect(
+        "signed_add",
+        "main = -5 3 toSint + fromSint",
+        vec![vec![65534]],
+    )?;
+    compile_expect(
+        "signed_neg",
+        "main = 5 toSint neg -7 neg",
+        vec![vec![7, 65531]],
+    )?;
+    compile_expect(
+        "signed_mul",
+        "main = -4 -5 * -4 5 toSint *",
+        vec![vec![65516, 20]],
+    )?;
+    compile_expect(
+        "signed_div",
+        "main = -7 2 toSint s/ 7 toSint -2 s/ -7 -2 s/ 7 toSint 2 toSint s/",
+        vec![vec![3, 3, 65533, 65533]],
+    )?;
+    compile_expect(
+        "signed_mod",
+        "main = -7 2 toSint s% 7 toSint -2 s% -7 -2 s% -32768 3 toSint s%",
+        vec![vec![65534, 65535, 1, 65535]],
+    )?;
+    compile_expect(
+        "signed_asr",
+        "main = -8 2 asr fromSint 0x8000 15 asr",
+        vec![vec![65535, 65534]],
+    )
+}
+
+#[test]
+fn let_bindings() -> CompilerTestResult {
+    compile_expect(
+        "let1",
+        "main = 1 2 let fst snd in (fst snd - snd fst -)",
+        vec![vec![1, 65535]],
+    )?;
+    compile_expect("let2", "main = 5 let val in (1 2 val + +)", vec![vec![8]])?;
+    compile_expect(
+        "let3",
+        "main = 7 let fst in (fst fst fst)",
+        vec![vec![7, 7, 7]],
+    )?;
+    compile_expect("let4", "main = 9 8 7 let fst snd in () ", vec![vec![9]])?;
+    compile_expect(
+        "let5",
+        "main = 1 let val in (2 let val in (val 10 +) val)",
+        vec![vec![1, 12]],
+    )?;
+    compile_expect(
+        "let6",
+        "main = go go = 3 4 let fst snd in (snd fst)",
+        vec![vec![3, 4]],
+    )
+}
+
+#[test]
+fn let_bindings_in_nested_terms() -> CompilerTestResult {
+    compile_expect(
+        "let_if",
+        "main = 3 let val in (if (val 2 >) then (val 10 *) else (0))",
+        vec![vec![30]],
+    )?;
+    compile_expect(
+        "let_while",
+        "main = 3 let num in (0 while (dup num <) do (1 +))",
+        vec![vec![3]],
+    )?;
+    compile_expect(
+        "let_repeat",
+        "main = 4 let num in (0 repeat_3 (swap num + swap))",
+        vec![vec![12]],
+    )?;
+    compile_expect(
+        "let_chan",
+        "main = chan_1 (42 ! drop) proc_1 let rx in (rx ? swap del)",
+        vec![vec![42]],
+    )
+}
+
+#[test]
+fn outer_bindings_in_nested_lets() -> CompilerTestResult {
+    compile_expect(
+        "let_nested",
+        "main = 1 2 let a in (3 let b in (a b))",
+        vec![vec![3, 2, 1]],
+    )?;
+    compile_expect(
+        "let_nested_same_value",
+        "main = 1 let x in (x let y in (x y))",
+        vec![vec![1, 1]],
+    )
+}
+
+#[test]
+fn dense_case() -> CompilerTestResult {
+    compile_expect(
+        "case_dense",
+        "main = 0 go 1 go 2 go 3 go 7 go
+        go = case [ 0 -> 10 | 1 -> 11 | 3 -> 13 | else -> 99 ]",
+        vec![vec![99, 13, 99, 11, 10]],
+    )?;
+    compile_expect(
+        "case_dense_offset",
+        "main = 1 go 2 go 4 go 5 go
+        go = case [ 2 -> 20 | 3 -> 30 | 4 -> 40 | else -> 0 ]",
+        vec![vec![0, 40, 20, 0]],
+    )
+}
+
+#[test]
+fn sparse_case() -> CompilerTestResult {
+    compile_expect(
+        "case_sparse",
+        "main = 1000 go 10 go 5 go
+        go = case [ 10 -> 1 | 1000 -> 2 | else -> 3 ]",
+        vec![vec![3, 1, 2]],
+    )?;
+    compile_expect(
+        "case_default_only",
+        "main = 4 5 case [ else -> 6 ]",
+        vec![vec![6, 4]],
+    )
+}
+
+#[test]
+fn case_in_middle_of_term() -> CompilerTestResult {
+    compile_expect(
+        "case_middle_dense",
+        "main = 1 case [ 0 -> 5 | 1 -> 6 | 2 -> 7 | else -> 8 ] 100 +",
+        vec![vec![106]],
+    )?;
+    compile_expect(
+        "case_middle_sparse",
+        "main = 7 case [ 0 -> 5 | 7 -> 6 | else -> 8 ] 100 +",
+        vec![vec![106]],
+    )
+}
+
+#[test]
+fn explicit_yield() -> CompilerTestResult {
+    compile_expect("yield", "main = 1 yield 2 +", vec![vec![3]])
+}
+
+#[test]
+fn auto_yield_prevents_starvation() -> CompilerTestResult {
+    let options = CompileOptions {
+        auto_yield: true,
+        ..CompileOptions::default()
+    };
+    let program = compile_str(
+        "main = chan_1 (repeat (.)) proc_0 (42 ! drop) proc_1 ? swap del",
+        ".",
+        options,
+    )?;
+    let is = assemble(lex_str(&program)?)?;
+    // The spinning process is started first and never halts, so with a single core main only
+    // finishes because the spinning process yields
+    let mut processor = Processor::new(1, 8);
+    processor.set_instructions(&is)?;
+    for _ in 0..500 {
+        processor.tick(false)?;
+    }
+    assert_eq!(processor.final_stack(0), &vec![42]);
+    Ok(())
+}
+
+#[test]
+fn alternation_receives() -> CompilerTestResult {
+    compile_expect(
+        "alt_receive",
+        "main = chan_1 (5 ! drop) proc_1 [ @0 -> swap del ] 1 +",
+        vec![vec![6]],
+    )
+}
+
+#[test]
+fn alternation_times_out() -> CompilerTestResult {
+    // Both arms must leave the channel in the same state, so the timeout arm falls back to a
+    // blocking receive
+    compile_expect(
+        "alt_timeout",
+        "main = chan_1 (0 while (dup 50 <) do (1 +) drop 5 ! drop) proc_1
+        [ @0 -> swap del | after 10 -> ? swap del 100 + ]",
+        vec![vec![105]],
+    )?;
+    compile_expect(
+        "alt_no_timeout",
+        "main = chan_1 (5 ! drop) proc_1 [ after 1000 -> ? swap del 100 + | @0 -> swap del ]",
+        vec![vec![5]],
+    )
+}
+
+#[test]
+fn repeated_timeouts() -> CompilerTestResult {
+    compile_expect(
+        "alt_repeated_timeouts",
+        "main = 0 repeat_3 ([ after 5 -> swap 1 + swap | after 20 -> ])",
+        vec![vec![3]],
+    )
+}
+
+#[test]
+fn guarded_alternation() -> CompilerTestResult {
+    let senders = "
+    sender1 = 7 ! drop
+    sender2 = 8 ! drop";
+    compile_expect(
+        "alt_guard_false",
+        &format!(
+            "main = false chan_1 'sender1 proc_1 chan_1 'sender2 proc_1
+            [ (@2) & @0 -> ?_2 - | @1 -> ?_1 - ] rot del del swap drop{}",
+            senders
+        ),
+        vec![vec![7_u16.wrapping_sub(8)]],
+    )?;
+    compile_expect(
+        "alt_guard_true",
+        &format!(
+            "main = true chan_1 'sender1 proc_1 chan_1 'sender2 proc_1
+            0 while (dup 50 <) do (1 +) drop
+            [ (@2) & @0 -> ?_2 - | @1 -> ?_1 - ] rot del del swap drop{}",
+            senders
+        ),
+        vec![vec![1]],
+    )?;
+    compile_expect(
+        "alt_guard_timer",
+        "main = [ (false) & after 0 -> 1 | after 10 -> 2 ]",
+        vec![vec![2]],
+    )
+}
+
+#[test]
+fn prioritised_alternation() -> CompilerTestResult {
+    // Both senders are ready by the time the alternation starts, so the first arm is chosen
+    let senders = "
+    sender1 = 7 ! drop
+    sender2 = 8 ! drop";
+    compile_expect(
+        "alt_pri_first",
+        &format!(
+            "main = chan_1 'sender1 proc_1 chan_1 'sender2 proc_1
+            0 while (dup 50 <) do (1 +) drop
+            pri [ @1 -> ?_1 - | @0 -> ?_2 - ] rot del del{}",
+            senders
+        ),
+        vec![vec![7_u16.wrapping_sub(8)]],
+    )?;
+    compile_expect(
+        "alt_pri_second",
+        &format!(
+            "main = chan_1 'sender1 proc_1 chan_1 'sender2 proc_1
+            0 while (dup 50 <) do (1 +) drop
+            pri [ @0 -> ?_2 - | @1 -> ?_1 - ] rot del del{}",
+            senders
+        ),
+        vec![vec![1]],
+    )?;
+    // A timer that has expired is chosen ahead of a later channel
+    compile_expect(
+        "alt_pri_timer",
+        "main = chan_1 (5 ! drop) proc_1
+        0 while (dup 50 <) do (1 +) drop
+        pri [ after 0 -> ? swap del 100 + | @0 -> swap del ]",
+        vec![vec![105]],
+    )
+}
+
+#[test]
+fn polling_alternation() -> CompilerTestResult {
+    // The sender isn't ready when the channel is polled, so it must be received from afterwards
+    compile_expect(
+        "alt_poll_not_ready",
+        "main = chan_1 (0 while (dup 50 <) do (1 +) drop 5 ! drop) proc_1
+        [ @0 -> swap del | else -> ? swap del 100 + ]",
+        vec![vec![105]],
+    )?;
+    compile_expect(
+        "alt_poll_ready",
+        "main = chan_1 (5 ! drop) proc_1
+        0 while (dup 50 <) do (1 +) drop
+        [ @0 -> swap del | else -> ? swap del 100 + ]",
+        vec![vec![5]],
+    )?;
+    compile_expect("alt_poll_nothing", "main = [ else -> 3 ]", vec![vec![3]])
+}
+
+#[test]
+fn buffered_channel() -> CompilerTestResult {
+    // Sending to an unbuffered channel with no receiver would deadlock
+    compile_expect(
+        "chanbuf_self",
+        "main = chanbuf_3 1 ! 2 ! 3 ! drop ? swap ? swap ? swap del",
+        vec![vec![3, 2, 1]],
+    )?;
+    compile_expect(
+        "chanbuf_pipeline",
+        "main = chanbuf_2 'producer proc_1 ? swap ? swap ? swap ? swap del + + +
+        producer = 1 ! 2 ! 3 ! 4 ! drop",
+        vec![vec![10]],
+    )
+}
+
+#[test]
+fn shared_channel() -> CompilerTestResult {
+    compile_expect(
+        "shared_channel",
+        "main = sharedchan share 'client proc_1 share 'client proc_1 'client proc_1
+        ? swap ? tuck + swap ? tuck + swap release
+        client = 7 ! release",
+        vec![vec![21]],
+    )
+}
+
+#[test]
+fn released_shared_channels_are_freed() -> CompilerTestResult {
+    compile_expect(
+        "shared_release",
+        "main = 0 while (dup 200 <) do (sharedchan release release 1 +)",
+        vec![vec![200]],
+    )?;
+    // The receiver is released first, so the channel is only freed once the last sender is done
+    compile_expect(
+        "shared_release_receiver_first",
+        "main = 0 while (dup 200 <) do (sharedchan swap release share (release) proc release 1 +)",
+        vec![vec![200]],
+    )
+}
+
+#[test]
+fn inferred_proc_word_count() -> CompilerTestResult {
+    compile_expect(
+        "proc_inferred",
+        "main = chan_1 7 'sender proc ? swap del
+        sender = 3 + ! drop",
+        vec![vec![10]],
+    )
+}
+
+#[test]
+fn spawn_and_join() -> CompilerTestResult {
+    compile_expect(
+        "spawn_and_join",
+        "main = 3 4 'sums spawn 10 swap join
+        sums = + dup 1 +",
+        vec![vec![8, 7, 10]],
+    )
+}
+
+#[test]
+fn join_waits_for_process_without_results() -> CompilerTestResult {
+    compile_expect(
+        "join_without_results",
+        "main = (1 drop) spawn join 5",
+        vec![vec![5]],
+    )
+}
+
+#[test]
+fn parallel_branches_split_the_stack() -> CompilerTestResult {
+    compile_expect(
+        "parallel_split",
+        "main = 1 2 3 par ( 10 + | 20 + | 30 + )",
+        vec![vec![33, 22, 11]],
+    )
+}
+
+#[test]
+fn parallel_branches_communicate() -> CompilerTestResult {
+    compile_expect(
+        "parallel_communicate",
+        "main = chan_1 par ( ? swap del | 5 ! drop )",
+        vec![vec![5]],
+    )
+}
+
+#[test]
+fn parallel_branches_push_results_in_order() -> CompilerTestResult {
+    compile_expect(
+        "parallel_results",
+        "main = 7 par ( 1 2 | | drop 3 )",
+        vec![vec![3, 2, 1]],
+    )
+}
+
+#[test]
+fn replicated_par_passes_each_copy_its_index() -> CompilerTestResult {
+    compile_expect("par_index", "main = par_3 (1 +)", vec![vec![3, 2, 1]])?;
+    compile_expect("par_words", "main = 4 5 par_2 (+)", vec![vec![6, 4]])?;
+    compile_expect("par_unused_index", "main = par_2 (7)", vec![vec![7, 7]])
+}
+
+#[test]
+fn replicated_par_over_channels() -> CompilerTestResult {
+    compile_expect(
+        "par_channels",
+        "main = chanbuf_1 chanbuf_1 swap rot par_2 (1 + 10 * ! drop) ? swap del swap ? swap del",
+        vec![vec![10, 20]],
+    )
+}
+
+#[test]
+fn replicated_alt_receives_once_from_each_channel() -> CompilerTestResult {
+    compile_expect(
+        "alt_each",
+        "main = chan_1 chan_1 swap rot (6 ! drop) proc (5 ! drop) proc 0 alt_2 (10 * + +) rot del del",
+        vec![vec![21]],
+    )?;
+    compile_expect(
+        "alt_shared",
+        "main = sharedchan sharedchan swap rot (7 ! release) proc (9 ! release) proc 0 alt_2 (drop +) rot release release",
+        vec![vec![16]],
+    )
+}
+
+#[test]
+fn replicated_alt_over_channels_used_more_than_once() -> CompilerTestResult {
+    compile_expect(
+        "alt_twice",
+        "main = chan_2 chan_2 swap rot (6 ! 8 ! drop) proc (5 ! 7 ! drop) proc 0 alt_2 (drop +) alt_2 (drop +) rot del del",
+        vec![vec![26]],
+    )
+}
+
+#[test]
+fn constants_in_subscripts() -> CompilerTestResult {
+    compile_expect(
+        "constants",
+        "const SIZE = 3
+        const SENT = SIZE * 2 + 1
+        main = chan_SIZE (repeat_SIZE (SENT !_(SIZE-1)) drop) proc_1 0 repeat_SIZE (?_2 rot rot + swap) swap del",
+        vec![vec![21]],
+    )
+}
+
+#[test]
+fn parameterised_declarations_are_instantiated_per_use() -> CompilerTestResult {
+    compile_expect(
+        "template_channels",
+        "sendAll_count = repeat_count (5 !_2) drop
+        sumAll_count = 0 repeat_count (?_2 rot rot + swap) swap del
+        main = chan_3 'sendAll_3 proc_1 sumAll_3 chan_2 'sendAll_2 proc_1 sumAll_2 +",
+        vec![vec![25]],
+    )?;
+    compile_expect(
+        "template_nested",
+        "main = 1 addTwice_3
+        addTwice_count = add_count add_(count*2)
+        add_count = count +",
+        vec![vec![10]],
+    )
+}
+
+#[test]
+fn imported_parameterised_declaration() -> CompilerTestResult {
+    compile_files_expect(
+        "imported_template",
+        &[
+            (
+                "main.st",
+                "import chans\nmain = chan_2 'sendAll_2 proc_1 sumAll_2",
+            ),
+            (
+                "chans.st",
+                "sendAll_count = repeat_count (count !_2) drop
+                sumAll_count = 0 repeat_count (?_2 rot rot + swap) swap del",
+            ),
+        ],
+        vec![vec![4]],
+    )
+}
+
+#[test]
+fn nested_quotation() -> CompilerTestResult {
+    compile_expect(
+        "nested_quotation",
+        "main = ((1) apply) apply",
+        vec![vec![1]],
+    )
+}
+
 #[test]
 fn basic_while() -> CompilerTestResult {
     compile_expect("while1", "main = while (false) do () 42", vec![vec![42]])?;
@@ -249,6 +820,32 @@ fn really_basic_rec() -> CompilerTestResult {
     Ok(())
 }
 
+#[test]
+fn tail_recursion_doesnt_grow_call_stack() -> CompilerTestResult {
+    compile_expect(
+        "tail_rec",
+        "main = 0 count
+        count = if (dup 1000 <) then (1 + count) else ()",
+        vec![vec![1000]],
+    )
+}
+
+#[test]
+fn mutual_tail_recursion() -> CompilerTestResult {
+    compile_expect(
+        "mutual_tail_rec",
+        "main = 1001 even
+        even = if (dup 0 ==) then (drop true) else (1 - odd)
+        odd = if (dup 0 ==) then (drop false) else (1 - even)",
+        vec![vec![0]],
+    )
+}
+
+#[test]
+fn tail_apply() -> CompilerTestResult {
+    compile_expect("tail_apply", "main = 2 (3 +) go go = apply", vec![vec![5]])
+}
+
 #[test]
 fn fib() -> CompilerTestResult {
     compile_expect(
@@ -496,3 +1093,227 @@ fn repeat_with_channels() -> CompilerTestResult {
         vec![vec![], vec![]],
     )
 }
+
+#[test]
+fn bitwise_operations() -> CompilerTestResult {
+    compile_expect(
+        "bitwise",
+        "main = 12 10 xor 12 10 bitand 12 10 bitor 1 bitnot",
+        vec![vec![65534, 14, 8, 6]],
+    )?;
+    compile_expect(
+        "shifts",
+        "main = 3 2 lsl 48 4 lsr 65528 1 asr 3 1 asl",
+        vec![vec![6, 65532, 3, 12]],
+    )
+}
+
+#[test]
+fn multiplication() -> CompilerTestResult {
+    compile_expect("multiply1", "main = 6 7 *", vec![vec![42]])?;
+    compile_expect("multiply2", "main = 0 7 * 7 0 *", vec![vec![0, 0]])?;
+    compile_expect("multiply3", "main = 300 300 *", vec![vec![24464]])?;
+    compile_expect("multiply4", "main = 2 'dup apply * 5 *", vec![vec![20]])
+}
+
+#[test]
+fn division_and_modulo() -> CompilerTestResult {
+    compile_expect("divide1", "main = 42 5 /", vec![vec![8]])?;
+    compile_expect("modulo1", "main = 42 5 %", vec![vec![2]])?;
+    compile_expect(
+        "divmod1",
+        "main = 65535 16 / 65535 16 % 7 9 / 7 9 %",
+        vec![vec![7, 0, 15, 4095]],
+    )?;
+    compile_expect("divmod2", "main = 6 7 * 6 /", vec![vec![7]])
+}
+
+#[test]
+fn library_routines_only_emitted_when_used() -> Result<(), CompileError> {
+    let program = compile_str("main = 1 2 +", ".", CompileOptions::default())?;
+    assert!(!program.contains("lib_mul"));
+    assert!(!program.contains("lib_divmod"));
+    let program = compile_str("main = 2 3 *", ".", CompileOptions::default())?;
+    assert!(program.contains("lib_mul:"));
+    assert!(!program.contains("lib_divmod"));
+    Ok(())
+}
+
+#[test]
+fn imported_definition() -> CompilerTestResult {
+    compile_files_expect(
+        "imported_definition",
+        &[
+            ("main.st", "import arith\nmain = 3 double"),
+            ("arith.st", "double = dup +"),
+        ],
+        vec![vec![6]],
+    )
+}
+
+#[test]
+fn imported_names_dont_collide() -> CompilerTestResult {
+    compile_files_expect(
+        "imported_names_dont_collide",
+        &[
+            (
+                "main.st",
+                "import counter\nmain = helper counterValue\nhelper = 1",
+            ),
+            ("counter.st", "counterValue = helper\nhelper = 2"),
+        ],
+        vec![vec![2, 1]],
+    )
+}
+
+#[test]
+fn transitive_imports() -> CompilerTestResult {
+    compile_files_expect(
+        "transitive_imports",
+        &[
+            ("main.st", "import relay\nmain = 4 relayed"),
+            ("relay.st", "import arith\nrelayed = double 1 +"),
+            ("arith.st", "import relay\ndouble = dup +"),
+        ],
+        vec![vec![9]],
+    )
+}
+
+#[test]
+fn string_imports_from_the_given_directory() -> CompilerTestResult {
+    let directory = std::env::temp_dir().join("statick_string_imports");
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join("arith.st"), "double = dup +")?;
+    let src = "import arith\nmain = 5 double";
+    let program = compile_str(src, &directory, CompileOptions::default())?;
+    run_expect("string_imports", &program, vec![vec![10]])
+}
+
+#[test]
+fn missing_module() {
+    match compile_files("missing_module", &[("main.st", "import nowhere\nmain = .")]) {
+        Err(CompilerTestError::CompilerFailure(CompileError::ModuleNotFound(import, _))) => {
+            assert_eq!(import.module, "nowhere");
+        }
+        _ => panic!("Expected the module not to be found"),
+    }
+}
+
+#[test]
+fn ambiguous_imported_name() {
+    let result = compile_files(
+        "ambiguous_imported_name",
+        &[
+            ("main.st", "import first\nimport second\nmain = value"),
+            ("first.st", "value = 1"),
+            ("second.st", "value = 2"),
+        ],
+    );
+    match result {
+        Err(CompilerTestError::CompilerFailure(CompileError::Type(e))) => match e.into_cause() {
+            TypeError::AmbiguousName(name, _) => assert_eq!(name, "value"),
+            e => panic!("Expected value to be ambiguous, not {}", e),
+        },
+        _ => panic!("Expected value to be ambiguous"),
+    }
+}
+
+#[test]
+fn errors_are_rendered_with_the_code_that_caused_them() -> Result<(), CompilerTestError> {
+    let directory = std::env::temp_dir().join("statick_errors_are_rendered");
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join("main.st"), "import arith\nmain = 1 double")?;
+    fs::write(directory.join("arith.st"), "double =\n    dup true +")?;
+    let mut files = SourceFiles::default();
+    let path = directory.join("main.st");
+    match compile_with_sources(path, CompileOptions::default(), &mut files) {
+        Ok(_) => panic!("Expected a type error"),
+        Err(e) => {
+            let rendered = e.render(&files);
+            assert!(rendered.contains("arith.st:2:14\n"), "{}", rendered);
+            assert!(
+                rendered.contains("2 |     dup true +\n  |              ^\n"),
+                "{}",
+                rendered
+            );
+            assert!(rendered.contains("note: this leaves the stack as"));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn every_syntax_error_is_reported_with_type_errors_in_the_rest() {
+    let src = "main = 1 broken
+        broken = (1
+        other = 2 (
+        fine = 1 true +";
+    match compile_str(src, ".", CompileOptions::default()) {
+        Err(CompileError::Multiple(errors)) => {
+            assert_eq!(errors.len(), 3, "{:?}", errors);
+            assert!(matches!(errors[0], CompileError::Parser(_)));
+            assert!(matches!(errors[1], CompileError::Parser(_)));
+            match &errors[2] {
+                CompileError::Type(TypeError::Located(_, span, _)) => {
+                    assert_eq!(span.start.line_number, 4)
+                }
+                e => panic!("Expected the type error in fine, not {}", e),
+            }
+        }
+        r => panic!("Expected every error to be reported, got {:?}", r),
+    }
+    // Nothing that depends on a declaration with a syntax error is checked, including main
+    match compile_str("main = (\nfine = 1", ".", CompileOptions::default()) {
+        Err(CompileError::Parser(_)) => {}
+        r => panic!("Expected only the syntax error, got {:?}", r),
+    }
+}
+
+#[test]
+fn holes_are_reported_instead_of_compiled() {
+    match compile_str("main = 1 _? drop", ".", CompileOptions::default()) {
+        Err(e @ CompileError::Holes(_)) => {
+            let rendered = e.render(&SourceFiles::default());
+            assert!(
+                rendered.starts_with("info: the stack here is S × int"),
+                "{}",
+                rendered
+            );
+            assert!(rendered
+                .ends_with("error: the program has a hole to fill before it can be compiled\n"));
+        }
+        r => panic!("Expected the hole to be reported, got {:?}", r),
+    }
+}
+
+#[test]
+fn mutually_recursive_state_machine() -> CompilerTestResult {
+    compile_expect(
+        "mutually_recursive_state_machine",
+        "main = 5 even 4 even
+        even = if (@0 0 ==) then (drop true) else (1 - odd)
+        odd = if (@0 0 ==) then (drop false) else (1 - even)",
+        vec![vec![1, 0]],
+    )
+}
+
+#[test]
+fn recursive_group_entered_through_a_chain() -> CompilerTestResult {
+    compile_expect(
+        "recursive_group_entered_through_a_chain",
+        "main = 3 a
+        a = b
+        b = c
+        c = if (dup 0 ==) then (drop) else (1 - a)",
+        vec![vec![]],
+    )
+}
+
+#[test]
+fn single_letter_names() -> CompilerTestResult {
+    compile_expect(
+        "single_letter_names",
+        "x = 1\nf' = x x +\nmain = f' {- twice -} x",
+        vec![vec![1, 2]],
+    )
+}
@@ -0,0 +1,206 @@
+use super::lexer::{FileId, Span};
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Points at code that helps to explain an error, such as an earlier definition of a name
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Note {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Note {
+    pub fn new(span: Span, message: String) -> Note {
+        Note { span, message }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    /// Information that was asked for, such as the stack at a hole
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+/**
+ * An error as it is shown to the user: a message, the code that caused it, and any notes. Errors
+ * that aren't caused by a particular piece of code, such as a missing main, have no span.
+ */
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            span,
+            notes: vec![],
+        }
+    }
+
+    /**
+     * Prints the message followed by the line that the span starts on, with the span underlined.
+     * A span that continues on to later lines is underlined to the end of its first line.
+     */
+    pub fn render(&self, files: &SourceFiles) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}: {}", self.severity, self.message).unwrap();
+        if let Some(span) = &self.span {
+            render_span(&mut out, files, span);
+        }
+        for note in &self.notes {
+            writeln!(out, "note: {}", note.message).unwrap();
+            render_span(&mut out, files, &note.span);
+        }
+        out
+    }
+}
+
+fn render_span(out: &mut String, files: &SourceFiles, span: &Span) {
+    let start = span.start;
+    let line = match files.line(start.file, start.line_number) {
+        Some(line) => line,
+        None => {
+            writeln!(out, " --> line {}", start.line_number).unwrap();
+            return;
+        }
+    };
+    // Offsets count bytes, but the caret has to line up with characters
+    let offset = floor_char_boundary(line, start.line_offset);
+    let end = if span.end.line_number == start.line_number {
+        floor_char_boundary(line, span.end.line_offset.max(offset))
+    } else {
+        line.len()
+    };
+    let column = line[..offset].chars().count() + 1;
+    let width = line[offset..end].chars().count().max(1);
+    // Tabs are kept so that the caret lines up however wide the terminal shows them
+    let indent: String = line[..offset]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    let line_number = start.line_number.to_string();
+    let gutter = " ".repeat(line_number.len());
+    writeln!(
+        out,
+        "{}--> {}:{}:{}",
+        gutter,
+        files.path(start.file).display(),
+        start.line_number,
+        column
+    )
+    .unwrap();
+    writeln!(out, "{} |", gutter).unwrap();
+    writeln!(out, "{} | {}", line_number, line).unwrap();
+    writeln!(out, "{} | {}{}", gutter, indent, "^".repeat(width)).unwrap();
+}
+
+fn floor_char_boundary(line: &str, offset: usize) -> usize {
+    let mut offset = offset.min(line.len());
+    while !line.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// The contents of each file read while compiling a program, indexed by the file's id
+#[derive(Debug, Default)]
+pub struct SourceFiles {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl SourceFiles {
+    pub fn add(&mut self, path: PathBuf, contents: String) -> FileId {
+        self.files.push((path, contents));
+        self.files.len() - 1
+    }
+
+    pub fn path(&self, file: FileId) -> &Path {
+        &self.files[file].0
+    }
+
+    pub fn contents(&self, file: FileId) -> &str {
+        &self.files[file].1
+    }
+
+    fn line(&self, file: FileId, line_number: usize) -> Option<&str> {
+        let (_, contents) = self.files.get(file)?;
+        contents.lines().nth(line_number.checked_sub(1)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lexer::Source;
+    use super::*;
+
+    fn span(line_number: usize, start: usize, end: usize) -> Span {
+        let start = Source {
+            file: 0,
+            line_number,
+            line_offset: start,
+        };
+        let end = Source {
+            line_offset: end,
+            ..start
+        };
+        Span { start, end }
+    }
+
+    #[test]
+    fn renders_caret_under_span() {
+        let mut files = SourceFiles::default();
+        files.add(
+            PathBuf::from("main.st"),
+            "main = 1 2 +\nbad = 1 true +".to_string(),
+        );
+        let mut diagnostic = Diagnostic::new("Can't unify int with bool".to_string(), None);
+        diagnostic.span = Some(span(2, 13, 14));
+        diagnostic.notes.push(Note::new(
+            span(2, 6, 12),
+            "this leaves S × int × bool".to_string(),
+        ));
+        assert_eq!(
+            diagnostic.render(&files),
+            "error: Can't unify int with bool
+ --> main.st:2:14
+  |
+2 | bad = 1 true +
+  |              ^
+note: this leaves S × int × bool
+ --> main.st:2:7
+  |
+2 | bad = 1 true +
+  |       ^^^^^^
+"
+        );
+    }
+
+    #[test]
+    fn span_across_lines_is_underlined_to_end_of_first_line() {
+        let mut files = SourceFiles::default();
+        files.add(PathBuf::from("main.st"), "main = (1\n  2)".to_string());
+        let mut multi_line = span(1, 7, 0);
+        multi_line.end.line_number = 2;
+        let diagnostic = Diagnostic::new("Oops".to_string(), Some(multi_line));
+        assert!(diagnostic
+            .render(&files)
+            .ends_with("1 | main = (1\n  |        ^^\n"));
+    }
+}
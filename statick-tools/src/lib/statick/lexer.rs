@@ -1,12 +1,12 @@
-extern crate regex;
-
-use regex::Regex;
+use super::diagnostic::Diagnostic;
+use crate::assembler::{parse_literal, Literal, LiteralError};
 use std::error::Error;
 use std::fmt;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum TokenKind {
     Number(u16),
+    SignedNumber(i16),
     Assign,
     Identifier(String),
     OpenParen,
@@ -18,6 +18,7 @@ pub enum TokenKind {
     Offset,
     Quote,
     Underscore,
+    Hole,
     If,
     Then,
     Else,
@@ -25,25 +26,78 @@ pub enum TokenKind {
     Do,
     Repeat,
     Period,
+    Import,
+    Let,
+    In,
+    Case,
+    After,
+    Pri,
+    Par,
+    Alt,
+    Const,
+    Ampersand,
+    DoubleColon,
+    Comma,
+    Times,
+    Bottom,
 }
 
-/** Currently I only support programs containing one file. */
-#[derive(Clone, Copy, Debug)]
+/** Identifies the file a token was read from. The root file of a program is always file 0. */
+pub type FileId = usize;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct Source {
+    pub file: FileId,
     pub line_number: usize,
     pub line_offset: usize,
 }
 
+/**
+ * The text from `start` up to (but not including) `end`. The AST records a span for each
+ * expression so that errors found after parsing can point at the code that caused them.
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    pub start: Source,
+    pub end: Source,
+}
+
+impl Span {
+    /// The span from the start of this one to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Token {
     pub kind: TokenKind,
     pub source: Source,
+    /// The number of bytes of the line that the token was read from
+    pub length: usize,
+}
+
+impl Token {
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.source,
+            end: Source {
+                line_offset: self.source.line_offset + self.length,
+                ..self.source
+            },
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ErrorToken {
     pub text: String,
     pub source: Source,
+    /** Set if the token looked like a literal but couldn't be read as one. */
+    pub literal_error: Option<LiteralError>,
 }
 
 #[derive(Debug)]
@@ -55,12 +109,22 @@ impl fmt::Display for LexerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if !self.error_tokens.is_empty() {
             if self.error_tokens.len() == 1 {
-                writeln!(f, "Found one unknown token:")?;
+                writeln!(f, "Found one invalid token:")?;
             } else {
-                writeln!(f, "Found {} unknown tokens:", self.error_tokens.len())?;
+                writeln!(f, "Found {} invalid tokens:", self.error_tokens.len())?;
             }
             for token in &self.error_tokens {
-                writeln!(f, "Line {}: {}", token.source.line_number, token.text)?;
+                write!(
+                    f,
+                    "Line {}, column {}: {}",
+                    token.source.line_number,
+                    token.source.line_offset + 1,
+                    token.text
+                )?;
+                match token.literal_error {
+                    Some(e) => writeln!(f, " ({})", e)?,
+                    None => writeln!(f)?,
+                }
             }
         }
         Ok(())
@@ -69,108 +133,312 @@ impl fmt::Display for LexerError {
 
 impl Error for LexerError {}
 
-pub fn lex(src: &str) -> Result<Vec<Token>, LexerError> {
-    let mut tokens = Vec::new();
-    let mut error_tokens = Vec::new();
-
-    let number_regex = Regex::new(r"^[0-9]+").unwrap();
-    let special_char_regex =
-        Regex::new(r"^(\(|\)|\[|\]|\||'|@|\->|\-\-|_|\+|\-|<=|>=|>|<|==|!=|\?|!|=|\.)").unwrap();
-    let identifier_regex = Regex::new(r"^[A-Za-z][A-Za-z0-9]+").unwrap();
-    let whitespace_regex = Regex::new(r"^[\s]+").unwrap();
-
-    for (i, line) in src.lines().enumerate() {
-        let line_number = i + 1;
-        let mut line_offset = 0;
-        // Consume leading whitespace
-        if let Some(m) = whitespace_regex.find(&line[line_offset..]) {
-            line_offset += m.end();
-        }
-        while line_offset != line.len() {
-            let slice = &line[line_offset..];
-            if let Some(m) = special_char_regex.find(slice) {
-                let matching_str = &slice[m.start()..m.end()];
-                let kind = match matching_str {
-                    "=" => TokenKind::Assign,
-                    "(" => TokenKind::OpenParen,
-                    ")" => TokenKind::CloseParen,
-                    "[" => TokenKind::OpenSquare,
-                    "]" => TokenKind::CloseSquare,
-                    "|" => TokenKind::VerticalBar,
-                    "->" => TokenKind::Arrow,
-                    "'" => TokenKind::Quote,
-                    "@" => TokenKind::Offset,
-                    "_" => TokenKind::Underscore,
-                    "+" => TokenKind::Identifier("+".to_string()),
-                    "-" => TokenKind::Identifier("-".to_string()),
-                    "<" => TokenKind::Identifier("<".to_string()),
-                    ">" => TokenKind::Identifier(">".to_string()),
-                    "<=" => TokenKind::Identifier("<=".to_string()),
-                    ">=" => TokenKind::Identifier(">=".to_string()),
-                    "==" => TokenKind::Identifier("==".to_string()),
-                    "!=" => TokenKind::Identifier("!=".to_string()),
-                    "?" => TokenKind::Identifier("?".to_string()),
-                    "!" => TokenKind::Identifier("!".to_string()),
-                    "." => TokenKind::Period,
-                    "--" => {
-                        // The rest of this line is a comment
-                        break;
-                    }
-                    _ => {
-                        panic!("Regular expression and match statements don't match");
-                    }
-                };
-                let source = Source {
-                    line_number,
-                    line_offset,
+impl LexerError {
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.error_tokens
+            .iter()
+            .map(|token| {
+                let message = match token.literal_error {
+                    Some(e) => format!("Invalid token {} ({})", token.text, e),
+                    None => format!("Invalid token {}", token.text),
                 };
-                let token = Token { source, kind };
-                tokens.push(token);
-                line_offset += m.end();
-            } else if let Some(m) = identifier_regex.find(slice) {
-                let matching_str = &slice[m.start()..m.end()];
-                let kind = match matching_str {
-                    "if" => TokenKind::If,
-                    "then" => TokenKind::Then,
-                    "else" => TokenKind::Else,
-                    "while" => TokenKind::While,
-                    "do" => TokenKind::Do,
-                    "repeat" => TokenKind::Repeat,
-                    _ => TokenKind::Identifier(matching_str.to_string()),
+                let end = Source {
+                    line_offset: token.source.line_offset + token.text.len(),
+                    ..token.source
                 };
-                let source = Source {
-                    line_number,
-                    line_offset,
+                let span = Span {
+                    start: token.source,
+                    end,
                 };
-                let token = Token { source, kind };
-                tokens.push(token);
-                line_offset += m.end();
-            } else if let Some(m) = number_regex.find(slice) {
-                let matching_str = &slice[m.start()..m.end()];
-                let source = Source {
-                    line_number,
-                    line_offset,
-                };
-                if let Ok(n) = matching_str.parse::<u16>() {
-                    let kind = TokenKind::Number(n);
-                    let token = Token { source, kind };
-                    tokens.push(token);
-                } else {
-                    let text = matching_str.to_string();
-                    error_tokens.push(ErrorToken { text, source });
+                Diagnostic::new(message, Some(span))
+            })
+            .collect()
+    }
+}
+
+pub fn lex(src: &str) -> Result<Vec<Token>, LexerError> {
+    lex_file(src, 0)
+}
+
+pub fn lex_file(src: &str, file: FileId) -> Result<Vec<Token>, LexerError> {
+    let mut lexer = Lexer {
+        src,
+        file,
+        position: 0,
+        line_number: 1,
+        line_start: 0,
+        tokens: Vec::new(),
+        error_tokens: Vec::new(),
+    };
+    lexer.lex();
+    if lexer.error_tokens.is_empty() {
+        Ok(lexer.tokens)
+    } else {
+        Err(LexerError {
+            error_tokens: lexer.error_tokens,
+        })
+    }
+}
+
+/// Operators and punctuation, with each one listed before any that it starts with
+const SYMBOLS: [(&str, Option<TokenKind>); 38] = [
+    ("s<=", None),
+    ("s>=", None),
+    ("::", Some(TokenKind::DoubleColon)),
+    ("->", Some(TokenKind::Arrow)),
+    ("-}", None),
+    ("<=", None),
+    (">=", None),
+    ("==", None),
+    ("!=", None),
+    ("s<", None),
+    ("s>", None),
+    ("s/", None),
+    ("s%", None),
+    ("(", Some(TokenKind::OpenParen)),
+    (")", Some(TokenKind::CloseParen)),
+    ("[", Some(TokenKind::OpenSquare)),
+    ("]", Some(TokenKind::CloseSquare)),
+    ("|", Some(TokenKind::VerticalBar)),
+    ("'", Some(TokenKind::Quote)),
+    ("@", Some(TokenKind::Offset)),
+    ("_?", Some(TokenKind::Hole)),
+    ("_", Some(TokenKind::Underscore)),
+    ("=", Some(TokenKind::Assign)),
+    (".", Some(TokenKind::Period)),
+    (",", Some(TokenKind::Comma)),
+    ("&", Some(TokenKind::Ampersand)),
+    ("×", Some(TokenKind::Times)),
+    ("→", Some(TokenKind::Arrow)),
+    ("⊥", Some(TokenKind::Bottom)),
+    ("+", None),
+    ("-", None),
+    ("*", None),
+    ("/", None),
+    ("%", None),
+    ("<", None),
+    (">", None),
+    ("?", None),
+    ("!", None),
+];
+
+fn keyword(name: &str) -> Option<TokenKind> {
+    match name {
+        "if" => Some(TokenKind::If),
+        "then" => Some(TokenKind::Then),
+        "else" => Some(TokenKind::Else),
+        "while" => Some(TokenKind::While),
+        "do" => Some(TokenKind::Do),
+        "repeat" => Some(TokenKind::Repeat),
+        "import" => Some(TokenKind::Import),
+        "let" => Some(TokenKind::Let),
+        "in" => Some(TokenKind::In),
+        "case" => Some(TokenKind::Case),
+        "after" => Some(TokenKind::After),
+        "pri" => Some(TokenKind::Pri),
+        "par" => Some(TokenKind::Par),
+        "alt" => Some(TokenKind::Alt),
+        "const" => Some(TokenKind::Const),
+        _ => None,
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\''
+}
+
+/**
+ * Reads the source in a single pass. Identifiers start with a letter and continue with letters,
+ * digits and primes, e.g. `x'`. An underscore directly after a name is its own token, as it may
+ * start a subscript like `chan_N`; the parser decides whether `max_depth` is one name or not.
+ * A letter between quotes is always a character, so quoting `f'` needs a space, as in `' f'`.
+ */
+struct Lexer<'a> {
+    src: &'a str,
+    file: FileId,
+    /// The byte offset of the next character
+    position: usize,
+    line_number: usize,
+    /// The byte offset of the start of the current line
+    line_start: usize,
+    tokens: Vec<Token>,
+    error_tokens: Vec<ErrorToken>,
+}
+
+impl<'a> Lexer<'a> {
+    fn lex(&mut self) {
+        while let Some(c) = self.peek(0) {
+            let rest = &self.src[self.position..];
+            if c == '\n' {
+                self.newline();
+            } else if c.is_whitespace() {
+                self.position += c.len_utf8();
+            } else if rest.starts_with("--") {
+                // The rest of this line is a comment
+                self.position += rest.find('\n').unwrap_or(rest.len());
+            } else if rest.starts_with("{-") {
+                self.block_comment();
+            } else if let Some(length) = self.literal_length() {
+                let source = self.source();
+                match literal_token(&rest[..length], source) {
+                    Ok(token) => self.tokens.push(token),
+                    Err(error_token) => self.error_tokens.push(error_token),
+                }
+                self.position += length;
+            } else if let Some(length) = self.identifier_length() {
+                let name = &rest[..length];
+                let kind = keyword(name).unwrap_or_else(|| TokenKind::Identifier(name.to_string()));
+                self.push(kind, length);
+            } else if let Some((symbol, kind)) = SYMBOLS.iter().find(|(s, _)| rest.starts_with(s)) {
+                match (kind, *symbol) {
+                    (Some(kind), _) => self.push(kind.clone(), symbol.len()),
+                    // The end of a block comment that was never started
+                    (None, "-}") => self.error(symbol.len()),
+                    (None, _) => self.push(TokenKind::Identifier(symbol.to_string()), symbol.len()),
                 }
-                line_offset += m.end();
-            } else if let Some(m) = whitespace_regex.find(&line[line_offset..]) {
-                line_offset += m.end();
             } else {
-                panic!("Error at {}", slice);
+                self.error(c.len_utf8());
             }
         }
     }
-    if error_tokens.is_empty() {
-        Ok(tokens)
-    } else {
-        Err(LexerError { error_tokens })
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.src[self.position..].chars().nth(n)
+    }
+
+    fn source(&self) -> Source {
+        Source {
+            file: self.file,
+            line_number: self.line_number,
+            line_offset: self.position - self.line_start,
+        }
+    }
+
+    fn newline(&mut self) {
+        self.position += 1;
+        self.line_number += 1;
+        self.line_start = self.position;
+    }
+
+    fn push(&mut self, kind: TokenKind, length: usize) {
+        let source = self.source();
+        self.tokens.push(Token {
+            kind,
+            source,
+            length,
+        });
+        self.position += length;
+    }
+
+    fn error(&mut self, length: usize) {
+        let source = self.source();
+        let text = self.src[self.position..self.position + length].to_string();
+        self.error_tokens.push(ErrorToken {
+            text,
+            source,
+            literal_error: None,
+        });
+        self.position += length;
+    }
+
+    /// Skips a `{- ... -}` comment, which may contain further block comments
+    fn block_comment(&mut self) {
+        let source = self.source();
+        let start = self.position;
+        let mut depth = 0;
+        while let Some(c) = self.peek(0) {
+            let rest = &self.src[self.position..];
+            if rest.starts_with("{-") {
+                depth += 1;
+                self.position += 2;
+            } else if rest.starts_with("-}") {
+                depth -= 1;
+                self.position += 2;
+                if depth == 0 {
+                    return;
+                }
+            } else if c == '\n' {
+                self.newline();
+            } else {
+                self.position += c.len_utf8();
+            }
+        }
+        // Report the opening of the comment, rather than everything after it
+        self.error_tokens.push(ErrorToken {
+            text: self.src[start..start + 2].to_string(),
+            source,
+            literal_error: None,
+        });
+    }
+
+    /**
+     * Literals are read loosely so that malformed ones are reported rather than split up. A minus
+     * sign is only part of a literal if it doesn't follow a name or number, so `3-2` is still a
+     * subtraction.
+     */
+    fn literal_length(&self) -> Option<usize> {
+        let rest = &self.src[self.position..];
+        let follows_word =
+            matches!(self.src[..self.position].chars().last(), Some(c) if c.is_alphanumeric());
+        let (sign, unsigned) = match rest.strip_prefix('-') {
+            Some(unsigned) if !follows_word => (1, unsigned),
+            Some(_) => return None,
+            None => (0, rest),
+        };
+        let mut chars = unsigned.chars();
+        let length = match (chars.next(), chars.next(), chars.next(), chars.next()) {
+            (Some(c), _, _, _) if c.is_ascii_digit() => unsigned
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(unsigned.len()),
+            (Some('\''), Some('\\'), Some(c), Some('\'')) if c != '\n' => 3 + c.len_utf8(),
+            (Some('\''), Some(c), Some('\''), _) if c != '\\' && c != '\'' && c != '\n' => {
+                2 + c.len_utf8()
+            }
+            _ => return None,
+        };
+        Some(sign + length)
+    }
+
+    fn identifier_length(&self) -> Option<usize> {
+        let rest = &self.src[self.position..];
+        let mut chars = rest.chars();
+        let first = chars.next()?;
+        let starts_word =
+            !matches!(self.src[..self.position].chars().last(), Some(c) if is_identifier_char(c));
+        let starts_identifier = match (first, chars.next()) {
+            // s< and friends are operators
+            ('s', Some('<')) | ('s', Some('>')) | ('s', Some('/')) | ('s', Some('%')) => false,
+            (c, _) if c.is_alphabetic() => true,
+            ('_', Some(c)) => starts_word && c.is_alphabetic(),
+            _ => false,
+        };
+        if !starts_identifier {
+            return None;
+        }
+        let length = rest[first.len_utf8()..]
+            .find(|c: char| !is_identifier_char(c))
+            .map_or(rest.len(), |length| first.len_utf8() + length);
+        Some(length)
+    }
+}
+
+fn literal_token(text: &str, source: Source) -> Result<Token, ErrorToken> {
+    match parse_literal(text) {
+        Ok(Literal::Unsigned(n)) => Ok(Token {
+            kind: TokenKind::Number(n),
+            source,
+            length: text.len(),
+        }),
+        Ok(Literal::Signed(n)) => Ok(Token {
+            kind: TokenKind::SignedNumber(n),
+            source,
+            length: text.len(),
+        }),
+        Err(e) => Err(ErrorToken {
+            text: text.to_string(),
+            source,
+            literal_error: Some(e),
+        }),
     }
 }
 
@@ -265,5 +533,189 @@ mod tests {
         Ok(())
     }
 
-    // TODO: Write tests that expose specific error cases in the lexer
+    #[test]
+    fn records_file_of_token() -> Result<(), LexerError> {
+        let result = lex_file("import relay\nmain = forward", 3)?;
+        assert_eq!(result[0].kind, TokenKind::Import);
+        assert_eq!(result[1].kind, TokenKind::Identifier("relay".to_string()));
+        for token in &result {
+            assert_eq!(token.source.file, 3);
+        }
+        assert_eq!(lex("main")?[0].source.file, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_operators() -> Result<(), LexerError> {
+        let result = lex("1 2 * 3 / 4 % xor")?;
+        assert_eq!(result[2].kind, TokenKind::Identifier("*".to_string()));
+        assert_eq!(result[4].kind, TokenKind::Identifier("/".to_string()));
+        assert_eq!(result[6].kind, TokenKind::Identifier("%".to_string()));
+        assert_eq!(result[7].kind, TokenKind::Identifier("xor".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn signature_tokens() -> Result<(), LexerError> {
+        let result = lex("sender :: Rest × chan(1, Tx, elem) → ⊥")?;
+        assert_eq!(result[0].kind, TokenKind::Identifier("sender".to_string()));
+        assert_eq!(result[1].kind, TokenKind::DoubleColon);
+        assert_eq!(result[2].kind, TokenKind::Identifier("Rest".to_string()));
+        assert_eq!(result[3].kind, TokenKind::Times);
+        assert_eq!(result[4].kind, TokenKind::Identifier("chan".to_string()));
+        assert_eq!(result[5].kind, TokenKind::OpenParen);
+        assert_eq!(result[6].kind, TokenKind::Number(1));
+        assert_eq!(result[7].kind, TokenKind::Comma);
+        assert_eq!(result[8].kind, TokenKind::Identifier("Tx".to_string()));
+        assert_eq!(result[10].kind, TokenKind::Identifier("elem".to_string()));
+        assert_eq!(result[11].kind, TokenKind::CloseParen);
+        assert_eq!(result[12].kind, TokenKind::Arrow);
+        assert_eq!(result[13].kind, TokenKind::Bottom);
+        Ok(())
+    }
+
+    #[test]
+    fn identifiers_may_be_short_unicode_or_primed() -> Result<(), LexerError> {
+        let result = lex("x = größe λ x' f'' _unused chan_N")?;
+        let kinds: Vec<TokenKind> = result.into_iter().map(|t| t.kind).collect();
+        let identifier = |name: &str| TokenKind::Identifier(name.to_string());
+        assert_eq!(
+            kinds,
+            vec![
+                identifier("x"),
+                TokenKind::Assign,
+                identifier("größe"),
+                identifier("λ"),
+                identifier("x'"),
+                identifier("f''"),
+                identifier("_unused"),
+                identifier("chan"),
+                TokenKind::Underscore,
+                identifier("N"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn letter_between_quotes_is_always_a_character() -> Result<(), LexerError> {
+        let result = lex("main = 'f' ' f' apply\nf' = 1")?;
+        let kinds: Vec<TokenKind> = result.iter().map(|t| t.kind.clone()).collect();
+        let identifier = |name: &str| TokenKind::Identifier(name.to_string());
+        assert_eq!(
+            kinds,
+            vec![
+                identifier("main"),
+                TokenKind::Assign,
+                TokenKind::Number(u16::from(b'f')),
+                TokenKind::Quote,
+                identifier("f'"),
+                identifier("apply"),
+                identifier("f'"),
+                TokenKind::Assign,
+                TokenKind::Number(1),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn block_comments_nest() -> Result<(), LexerError> {
+        let result = lex("main {- outer {- inner -}\nstill -} = {--} 1")?;
+        let kinds: Vec<TokenKind> = result.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Identifier("main".to_string()),
+                TokenKind::Assign,
+                TokenKind::Number(1),
+            ]
+        );
+        assert_eq!(result[1].source.line_number, 2);
+        assert_eq!(result[1].source.line_offset, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn unrecognised_characters_are_errors() {
+        let error = lex("main = 1 # 2\n{- never closed").unwrap_err();
+        let texts: Vec<&str> = error.error_tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["#", "{-"]);
+        assert_eq!(error.error_tokens[1].source.line_number, 2);
+        let error = lex("} -} ~").unwrap_err();
+        let texts: Vec<&str> = error.error_tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["}", "-}", "~"]);
+    }
+
+    #[test]
+    fn negative_literals() {
+        let result = lex("-5 3-2 (-32768) xs -1 s< - 1 s/ s%").unwrap();
+        let kinds: Vec<TokenKind> = result.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::SignedNumber(-5),
+                TokenKind::Number(3),
+                TokenKind::Identifier("-".to_string()),
+                TokenKind::Number(2),
+                TokenKind::OpenParen,
+                TokenKind::SignedNumber(-32768),
+                TokenKind::CloseParen,
+                TokenKind::Identifier("xs".to_string()),
+                TokenKind::SignedNumber(-1),
+                TokenKind::Identifier("s<".to_string()),
+                TokenKind::Identifier("-".to_string()),
+                TokenKind::Number(1),
+                TokenKind::Identifier("s/".to_string()),
+                TokenKind::Identifier("s%".to_string()),
+            ]
+        );
+        assert!(lex("-32769").is_err());
+    }
+
+    #[test]
+    fn numeric_literals() -> Result<(), LexerError> {
+        let result = lex(r"0x1F 0b1010 'a' 1_000 -0x10 '\n' ' ' 'fn 'go")?;
+        let kinds: Vec<TokenKind> = result.into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Number(31),
+                TokenKind::Number(10),
+                TokenKind::Number(97),
+                TokenKind::Number(1000),
+                TokenKind::SignedNumber(-16),
+                TokenKind::Number(10),
+                TokenKind::Number(32),
+                TokenKind::Quote,
+                TokenKind::Identifier("fn".to_string()),
+                TokenKind::Quote,
+                TokenKind::Identifier("go".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_range_literals_are_errors() {
+        let error = lex("main =\n  1 0x10000 0b2").unwrap_err();
+        assert_eq!(error.error_tokens.len(), 2);
+        assert_eq!(error.error_tokens[0].text, "0x10000");
+        assert_eq!(error.error_tokens[0].source.line_number, 2);
+        assert_eq!(error.error_tokens[0].source.line_offset, 4);
+        assert_eq!(
+            error.error_tokens[0].literal_error,
+            Some(LiteralError::OutOfRange)
+        );
+        assert_eq!(
+            error.error_tokens[1].literal_error,
+            Some(LiteralError::Malformed)
+        );
+        assert_eq!(
+            format!("{}", error),
+            "Found 2 invalid tokens:\n\
+             Line 2, column 5: 0x10000 (literal doesn't fit in a 16-bit word)\n\
+             Line 2, column 13: 0b2 (malformed literal)\n"
+        );
+    }
 }
@@ -1,6 +1,9 @@
 use super::ast::*;
-use super::lexer::{Source, Token, TokenKind};
+use super::diagnostic::Diagnostic;
+use super::lexer::{Source, Span, Token, TokenKind};
+use super::types::Direction;
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 
@@ -12,6 +15,14 @@ pub enum ParserError {
     ExpectedToken(TokenKind),
     UnexpectedToken(Token, TokenKind),
     DisallowedToken(Token),
+    /// An open paren whose term ran into the next declaration or the end of the file
+    UnclosedParen(Token),
+    DuplicateSignature(String),
+    SignatureWithoutDeclaration(String),
+    DuplicateCaseArm(u16),
+    DuplicateConstant(String),
+    UnknownConstant(String),
+    ConstantOutOfRange(Source),
 }
 
 impl fmt::Display for ParserError {
@@ -38,76 +49,593 @@ impl fmt::Display for ParserError {
                 "Unexpected {:?} on line {}, expected {:?}",
                 tok.kind, tok.source.line_number, exp
             ),
+            ParserError::UnclosedParen(tok) => write!(
+                f,
+                "The ( on line {} is never closed",
+                tok.source.line_number
+            ),
             ParserError::DisallowedToken(tok) => write!(
                 f,
                 "{:?} at {}, which is not allowed",
                 tok.kind, tok.source.line_number
             ),
+            ParserError::DuplicateSignature(name) => {
+                write!(f, "{} has more than one signature", name)
+            }
+            ParserError::SignatureWithoutDeclaration(name) => {
+                write!(f, "{} has a signature but is never defined", name)
+            }
+            ParserError::DuplicateCaseArm(n) => write!(f, "case has more than one arm for {}", n),
+            ParserError::DuplicateConstant(name) => write!(f, "{} is already a constant", name),
+            ParserError::UnknownConstant(name) => {
+                write!(f, "{} is not a constant defined before this point", name)
+            }
+            ParserError::ConstantOutOfRange(src) => write!(
+                f,
+                "Constant arithmetic on line {} doesn't fit in a word",
+                src.line_number
+            ),
         }
     }
 }
 
 impl Error for ParserError {}
 
+impl ParserError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = match self {
+            ParserError::ExpectedExpressionInIf(src)
+            | ParserError::ExpectedExpressionInWhile(src)
+            | ParserError::ExpectedExpressionInRepeat(src)
+            | ParserError::ConstantOutOfRange(src) => Some(Span {
+                start: *src,
+                end: *src,
+            }),
+            ParserError::UnexpectedToken(tok, _)
+            | ParserError::DisallowedToken(tok)
+            | ParserError::UnclosedParen(tok) => Some(tok.span()),
+            _ => None,
+        };
+        Diagnostic::new(self.to_string(), span)
+    }
+}
+
 pub type ParserResult<T> = Result<T, ParserError>;
 
+/// Parses a file, failing with the first error in it
+#[cfg(test)]
 pub fn parse(tokens: &[Token]) -> ParserResult<Program> {
+    let (program, mut errors) = parse_recovering(tokens);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/**
+ * Parses a file, skipping to the start of the next declaration after each error so that every
+ * error in the file is found. The program has each declaration that could be parsed.
+ */
+pub fn parse_recovering(tokens: &[Token]) -> (Program, Vec<ParserError>) {
     let iter = tokens.iter();
-    let mut parser = Parser { iter };
-    parser.parse_program()
+    let mut parser = Parser {
+        iter,
+        locals: vec![],
+        constants: HashMap::new(),
+        errors: vec![],
+    };
+    parser.collect_constants(tokens);
+    let program = parser.parse_program();
+    (program, parser.errors)
+}
+
+/// Parses the term of a parameterised declaration with its parameter set to `k`
+pub fn parse_template_instance(template: &Template, k: u16) -> ParserResult<Term> {
+    let mut constants = template.constants.clone();
+    constants.insert(template.parameter.to_string(), k);
+    let mut parser = Parser {
+        iter: template.tokens.iter(),
+        locals: vec![],
+        constants,
+        errors: vec![],
+    };
+    parser.parse_term(false)
 }
 
 struct Parser<'a> {
     iter: std::slice::Iter<'a, Token>,
+    /// Names bound by the enclosing lets, innermost last
+    locals: Vec<String>,
+    /// The values of the constants declared in the file
+    constants: HashMap<String, u16>,
+    /// The errors that have been recovered from
+    errors: Vec<ParserError>,
 }
 
 impl<'a> Parser<'a> {
-    fn parse_program(&mut self) -> ParserResult<Program> {
+    /**
+     * Constants are evaluated before anything else so that declarations can use constants declared
+     * later in the file. A constant's own value may only use the constants declared before it.
+     */
+    fn collect_constants(&mut self, tokens: &'a [Token]) {
+        for (i, tok) in tokens.iter().enumerate() {
+            if tok.kind == TokenKind::Const {
+                self.iter = tokens[i..].iter();
+                match self.parse_constant() {
+                    Ok((name, value)) => {
+                        if self.constants.insert(name.to_string(), value).is_some() {
+                            self.errors.push(ParserError::DuplicateConstant(name));
+                        }
+                    }
+                    Err(e) => self.errors.push(e),
+                }
+            }
+        }
+        self.iter = tokens.iter();
+    }
+
+    fn parse_program(&mut self) -> Program {
         let mut program = Program::default();
-        program.declarations = self.parse_declaration_list()?;
-        Ok(program)
+        let imports = self.parse_import_list();
+        program.imports.insert(None, imports);
+        self.parse_declaration_list(&mut program);
+        program
     }
 
-    fn parse_declaration_list(&mut self) -> ParserResult<Vec<Declaration>> {
-        let mut declarations = Vec::new();
-        while let Some(decl) = self.parse_declaration()? {
+    fn parse_import_list(&mut self) -> Vec<Import> {
+        let mut imports = Vec::new();
+        loop {
+            let start = self.iter.as_slice();
+            match self.consume(TokenKind::Import) {
+                Ok(tok) => match self.iter.next() {
+                    Some(module) => match Parser::token_to_identifier(module) {
+                        Ok(module) => {
+                            let source = tok.source;
+                            imports.push(Import { module, source });
+                        }
+                        Err(e) => {
+                            self.errors.push(e);
+                            self.recover(start);
+                        }
+                    },
+                    None => self
+                        .errors
+                        .push(ParserError::ExpectedToken(TokenKind::Identifier(
+                            "".to_string(),
+                        ))),
+                },
+                Err(_) => {
+                    self.iter = start.iter();
+                    break;
+                }
+            }
+        }
+        imports
+    }
+
+    fn parse_declaration_list(&mut self, program: &mut Program) {
+        let mut signatures = HashMap::new();
+        loop {
+            let start = self.iter.as_slice();
+            let parsed = if let Some(Token {
+                kind: TokenKind::Const,
+                ..
+            }) = start.first()
+            {
+                // Already evaluated by collect_constants, which reported any error
+                match self.parse_constant() {
+                    Ok((name, _)) => {
+                        let span = self.span_since(start);
+                        let module = None;
+                        program.constants.push(Constant { name, span, module });
+                    }
+                    Err(_) => self.recover(start),
+                }
+                Ok(true)
+            } else {
+                self.parse_declaration_list_item(&mut program.declarations, &mut signatures)
+            };
+            match parsed {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    self.errors.push(e);
+                    if let Some(name) = Parser::declaration_name(start) {
+                        program.unparsed.insert(name);
+                    }
+                    self.recover(start);
+                }
+            }
+        }
+        // Signatures may appear anywhere in a file, not just before their declaration
+        for decl in &mut program.declarations {
+            decl.signature = signatures.remove(&decl.name);
+        }
+        for name in signatures.keys() {
+            if !program.unparsed.contains(name) {
+                let name = name.to_string();
+                self.errors
+                    .push(ParserError::SignatureWithoutDeclaration(name));
+            }
+        }
+    }
+
+    /// Parses a signature or declaration, returning false at the end of the file
+    fn parse_declaration_list_item(
+        &mut self,
+        declarations: &mut Vec<Declaration>,
+        signatures: &mut HashMap<String, SignatureType>,
+    ) -> ParserResult<bool> {
+        if let Some((name, signature)) = self.parse_signature()? {
+            if signatures.contains_key(&name) {
+                return Err(ParserError::DuplicateSignature(name));
+            }
+            signatures.insert(name, signature);
+        } else if let Some(decl) = self.parse_declaration()? {
             declarations.push(decl);
+        } else {
+            return Ok(false);
         }
-        Ok(declarations)
+        Ok(true)
     }
 
-    fn parse_declaration(&mut self) -> ParserResult<Option<Declaration>> {
+    /**
+     * Skips past the construct that started at `start` and failed to parse, up to the start of
+     * the next import, constant, signature or declaration.
+     */
+    fn recover(&mut self, start: &'a [Token]) {
+        if start.is_empty() {
+            self.iter = start.iter();
+            return;
+        }
+        self.iter = start[1..].iter();
+        while !self.iter.as_slice().is_empty() && !self.at_declaration() {
+            self.iter.next();
+        }
+    }
+
+    fn at_declaration(&self) -> bool {
+        let start = self.iter.as_slice();
+        match start.first().map(|tok| &tok.kind) {
+            Some(TokenKind::Import) | Some(TokenKind::Const) => true,
+            _ => Parser::declaration_name(start).is_some(),
+        }
+    }
+
+    /// The name defined by the declaration or signature starting at `start`, if it is one
+    fn declaration_name(start: &[Token]) -> Option<String> {
+        let length = Parser::name_length(start);
+        match start.get(length).map(|tok| &tok.kind) {
+            Some(TokenKind::Assign) | Some(TokenKind::DoubleColon) if length > 0 => {
+                Some(Parser::joined_name(&start[..length]))
+            }
+            _ => None,
+        }
+    }
+
+    /// How many tokens make up the name at the start of `tokens`, e.g. the three of `max_depth`
+    fn name_length(tokens: &[Token]) -> usize {
+        match tokens.first().map(|tok| &tok.kind) {
+            Some(TokenKind::Identifier(_)) => 1 + Parser::joined_words_length(tokens),
+            _ => 0,
+        }
+    }
+
+    /// How many tokens after the first word in `tokens` join further words to it, as in `_depth`
+    fn joined_words_length(tokens: &[Token]) -> usize {
+        let mut length = 0;
+        while let (Some(underscore), Some(word)) = (tokens.get(length + 1), tokens.get(length + 2))
+        {
+            let joined = underscore.kind == TokenKind::Underscore
+                && matches!(word.kind, TokenKind::Identifier(_))
+                && tokens[length].span().end == underscore.source
+                && underscore.span().end == word.source;
+            if !joined {
+                break;
+            }
+            length += 2;
+        }
+        length
+    }
+
+    /// The name made of the words in `tokens`, which were counted by `name_length`
+    fn joined_name(tokens: &[Token]) -> String {
+        let words: Vec<String> = tokens
+            .iter()
+            .step_by(2)
+            .filter_map(|tok| Parser::token_to_identifier(tok).ok())
+            .collect();
+        words.join("_")
+    }
+
+    /**
+     * Reads the name starting with `first`, which was just consumed, and the words joined to it by
+     * underscores, as in `max_depth`. An underscore before a constant (including a template's
+     * parameter) starts a subscript instead, as in `chan_N`, as does one before a number.
+     */
+    fn parse_name(&mut self, first: &Token) -> ParserResult<String> {
+        let mut name = Parser::token_to_identifier(first)?;
+        let mut previous = first;
+        loop {
+            let mut iter = self.iter.clone();
+            match (iter.next(), iter.next()) {
+                (
+                    Some(underscore),
+                    Some(
+                        word @ Token {
+                            kind: TokenKind::Identifier(id),
+                            ..
+                        },
+                    ),
+                ) if underscore.kind == TokenKind::Underscore
+                    && previous.span().end == underscore.source
+                    && underscore.span().end == word.source
+                    && !self.constants.contains_key(id) =>
+                {
+                    name.push('_');
+                    name.push_str(id);
+                    previous = word;
+                    self.iter = iter;
+                }
+                _ => return Ok(name),
+            }
+        }
+    }
+
+    fn parse_signature(&mut self) -> ParserResult<Option<(String, SignatureType)>> {
+        let backtracking_iter = self.iter.clone();
         let name = match self.iter.next() {
+            Some(
+                tok @ Token {
+                    kind: TokenKind::Identifier(_),
+                    ..
+                },
+            ) => self.parse_name(tok)?,
+            _ => {
+                self.iter = backtracking_iter;
+                return Ok(None);
+            }
+        };
+        if self.consume(TokenKind::DoubleColon).is_err() {
+            self.iter = backtracking_iter;
+            return Ok(None);
+        }
+        let input = self.parse_signature_stack()?;
+        self.consume(TokenKind::Arrow)?;
+        let output = self.parse_signature_stack()?;
+        Ok(Some((name, SignatureType::Function(input, output))))
+    }
+
+    fn parse_signature_stack(&mut self) -> ParserResult<SignatureStack> {
+        let backtracking_iter = self.iter.clone();
+        if self.consume(TokenKind::Bottom).is_ok() {
+            return Ok(SignatureStack::Bottom);
+        }
+        self.iter = backtracking_iter;
+        let name = self.parse_signature_variable()?;
+        let mut types = Vec::new();
+        loop {
+            let backtracking_iter = self.iter.clone();
+            if self.consume(TokenKind::Times).is_err() {
+                self.iter = backtracking_iter;
+                break;
+            }
+            types.push(self.parse_signature_type()?);
+        }
+        Ok(SignatureStack::Stack(name, types))
+    }
+
+    fn parse_signature_type(&mut self) -> ParserResult<SignatureType> {
+        let backtracking_iter = self.iter.clone();
+        if self.consume(TokenKind::OpenParen).is_ok() {
+            let input = self.parse_signature_stack()?;
+            self.consume(TokenKind::Arrow)?;
+            let output = self.parse_signature_stack()?;
+            self.consume(TokenKind::CloseParen)?;
+            return Ok(SignatureType::Function(input, output));
+        }
+        self.iter = backtracking_iter;
+        let name = self.parse_signature_variable()?;
+        match name.as_ref() {
+            "int" => Ok(SignatureType::Integer),
+            "sint" => Ok(SignatureType::SignedInteger),
+            "bool" => Ok(SignatureType::Boolean),
+            "chan" => {
+                self.consume(TokenKind::OpenParen)?;
+                // The number of uses may be omitted for channels that can be used indefinitely
+                let backtracking_iter = self.iter.clone();
+                let chan_use = if self.parse_signature_direction().is_ok() {
+                    self.iter = backtracking_iter;
+                    SignatureChannelUse::Infinity
+                } else {
+                    self.iter = backtracking_iter;
+                    let chan_use = self.parse_signature_channel_use()?;
+                    self.consume(TokenKind::Comma)?;
+                    chan_use
+                };
+                let direction = self.parse_signature_direction()?;
+                self.consume(TokenKind::Comma)?;
+                let t = self.parse_signature_type()?;
+                self.consume(TokenKind::CloseParen)?;
+                Ok(SignatureType::Channel(chan_use, direction, Box::new(t)))
+            }
+            _ => Ok(SignatureType::Generic(name)),
+        }
+    }
+
+    fn parse_signature_channel_use(&mut self) -> ParserResult<SignatureChannelUse> {
+        let backtracking_iter = self.iter.clone();
+        if let Ok(k) = self.consume_number() {
+            return Ok(SignatureChannelUse::Constant(k as usize));
+        }
+        self.iter = backtracking_iter;
+        let name = self.parse_signature_variable()?;
+        if name == "shared" {
+            return Ok(SignatureChannelUse::Shared);
+        }
+        let backtracking_iter = self.iter.clone();
+        if self.consume(TokenKind::Identifier("+".to_string())).is_ok() {
+            let k = self.consume_number()?;
+            Ok(SignatureChannelUse::Variable(name, k as usize))
+        } else {
+            self.iter = backtracking_iter;
+            Ok(SignatureChannelUse::Variable(name, 0))
+        }
+    }
+
+    fn parse_signature_direction(&mut self) -> ParserResult<Direction> {
+        match self.iter.next() {
+            Some(tok) => match &tok.kind {
+                TokenKind::Identifier(id) if id == "Rx" => Ok(Direction::Rx),
+                TokenKind::Identifier(id) if id == "Tx" => Ok(Direction::Tx),
+                _ => Err(ParserError::UnexpectedToken(
+                    tok.clone(),
+                    TokenKind::Identifier("Tx".to_string()),
+                )),
+            },
+            None => Err(ParserError::ExpectedToken(TokenKind::Identifier(
+                "Tx".to_string(),
+            ))),
+        }
+    }
+
+    /// Variables in signatures may be followed by primes, i.e. S'
+    fn parse_signature_variable(&mut self) -> ParserResult<String> {
+        let mut name = match self.iter.next() {
             Some(tok) => Parser::token_to_identifier(tok)?,
-            None => return Ok(None),
+            None => {
+                return Err(ParserError::ExpectedToken(TokenKind::Identifier(
+                    "".to_string(),
+                )))
+            }
+        };
+        loop {
+            let backtracking_iter = self.iter.clone();
+            if self.consume(TokenKind::Quote).is_err() {
+                self.iter = backtracking_iter;
+                break;
+            }
+            name.push('\'');
+        }
+        Ok(name)
+    }
+
+    /**
+     * A declaration whose name ends in `_word` is parameterised by that word if its term uses it,
+     * as in `sendAll_count = repeat_count (...)`. Otherwise the underscore is part of its name.
+     */
+    fn parse_declaration(&mut self) -> ParserResult<Option<Declaration>> {
+        let start = self.iter.as_slice();
+        let length = match (start.first(), Parser::name_length(start)) {
+            (None, _) => return Ok(None),
+            (Some(tok), 0) => return Err(Parser::token_to_identifier(tok).unwrap_err()),
+            (_, length) => length,
         };
+        let span = start[0].span().to(start[length - 1].span());
+        self.iter = start[length..].iter();
         self.consume(TokenKind::Assign)?;
-        let term = Box::new(self.parse_term(true)?);
-        let declaration = Declaration { name, term };
+        let body = self.iter.clone();
+        let parameter = Parser::joined_name(&start[length - 1..length]);
+        let uses_parameter = length > 1
+            && self
+                .skip_template_term()
+                .iter()
+                .any(|tok| tok.kind == TokenKind::Identifier(parameter.to_string()));
+        let (name, term, template) = if uses_parameter {
+            // The term can't be built until the parameter is known, so only its tokens are kept
+            let tokens = body.as_slice()[..body.len() - self.iter.len()].to_vec();
+            let template = Template {
+                parameter,
+                tokens,
+                constants: self.constants.clone(),
+            };
+            let name = Parser::joined_name(&start[..length - 2]);
+            (name, Term::default(), Some(template))
+        } else {
+            self.iter = body;
+            let name = Parser::joined_name(&start[..length]);
+            (name, self.parse_term(true)?, None)
+        };
+        let term = Box::new(term);
+        let module = None;
+        let signature = None;
+        let declaration = Declaration {
+            name,
+            span,
+            term,
+            module,
+            signature,
+            template,
+        };
         Ok(Some(declaration))
     }
 
+    /**
+     * Skips the tokens of a parameterised declaration's term, which ends where a top-level term
+     * would: at the next declaration, or at a bracket that it didn't open.
+     */
+    fn skip_template_term(&mut self) -> &'a [Token] {
+        let body = self.iter.as_slice();
+        let mut depth = 0;
+        while let Some(tok) = self.iter.clone().next() {
+            if depth == 0 && self.at_declaration() {
+                break;
+            }
+            match tok.kind {
+                TokenKind::OpenParen | TokenKind::OpenSquare => depth += 1,
+                TokenKind::CloseParen | TokenKind::CloseSquare if depth == 0 => break,
+                TokenKind::CloseParen | TokenKind::CloseSquare => depth -= 1,
+                _ => {}
+            }
+            self.iter.next();
+        }
+        &body[..body.len() - self.iter.as_slice().len()]
+    }
+
     fn parse_term(&mut self, top_level: bool) -> ParserResult<Term> {
+        let start = self.iter.as_slice();
         let expressions = self.parse_expression_list(top_level)?;
         let t_type = None;
+        let span = self.span_since(start);
         let term = Term {
             expressions,
             t_type,
+            span,
             ..Term::default()
         };
         Ok(term)
     }
 
+    /// The span of the tokens consumed since the parser was at `start`
+    fn span_since(&self, start: &[Token]) -> Span {
+        let consumed = start.len() - self.iter.as_slice().len();
+        match (start.first(), consumed) {
+            (Some(first), 0) => Span {
+                start: first.source,
+                end: first.source,
+            },
+            (Some(first), _) => first.span().to(start[consumed - 1].span()),
+            (None, _) => Span::default(),
+        }
+    }
+
     fn parse_expression_list(&mut self, top_level: bool) -> ParserResult<Vec<Expression>> {
         let mut exprs = Vec::new();
         let mut backtracking_iter = self.iter.clone();
-        while let Some(expr) = self.parse_expression()? {
-            // we have ident = <new expr list> so we should break out
-            if let ExpressionType::NamedTermApp(_, _) = expr.expression {
+        loop {
+            let start = self.iter.as_slice();
+            let expr = match self.parse_expression()? {
+                Some(expr) => expr,
+                None => break,
+            };
+            // we have ident = <new expr list> so we should break out. The identifier may also have
+            // been replaced with a constant, which the type checker reports.
+            if let Some(TokenKind::Identifier(_)) = start.first().map(|tok| &tok.kind) {
                 let mut iter_copy = self.iter.clone();
                 if let Some(tok) = iter_copy.next() {
-                    // I.e. we have a new term, so we should break out
-                    if tok.kind == TokenKind::Assign {
+                    // I.e. we have a new term or signature, so we should break out
+                    if tok.kind == TokenKind::Assign || tok.kind == TokenKind::DoubleColon {
                         if top_level {
                             break;
                         } else {
@@ -126,38 +654,50 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_expression(&mut self) -> ParserResult<Option<Expression>> {
+        let start = self.iter.as_slice();
         let backtracking_iter = self.iter.clone();
         if let Some(token) = self.iter.next() {
             let expression = match &token.kind {
                 TokenKind::Number(n) => ExpressionType::Number(*n),
+                TokenKind::SignedNumber(n) => ExpressionType::SignedNumber(*n),
                 TokenKind::Assign => return Err(ParserError::DisallowedToken(token.clone())),
-                TokenKind::Identifier(id) => {
-                    let name = id.to_string();
+                TokenKind::Identifier(_) => {
+                    let name = self.parse_name(token)?;
                     let backtracking_iter = self.iter.clone();
                     let n = if self.consume(TokenKind::Underscore).is_ok() {
-                        Some(self.consume_number()?)
+                        Some(self.parse_number()?)
                     } else {
                         self.iter = backtracking_iter;
                         None
                     };
-                    ExpressionType::NamedTermApp(name, n)
+                    if n.is_none() && self.locals.contains(&name) {
+                        ExpressionType::Local(name, None)
+                    } else if let (None, Some(value)) = (n, self.constants.get(&name)) {
+                        ExpressionType::Number(*value)
+                    } else {
+                        ExpressionType::NamedTermApp(name, n)
+                    }
                 }
                 TokenKind::Quote => {
-                    if let ExpressionType::NamedTermApp(name, n) =
-                        self.parse_expression()?.unwrap().expression
-                    {
-                        ExpressionType::NamedTermRef(name, n)
-                    } else {
-                        return Err(ParserError::ExpectedToken(TokenKind::Identifier(
-                            "".to_string(),
-                        )));
+                    let quoted = self.iter.clone().next();
+                    match self.parse_expression()?.map(|expr| expr.expression) {
+                        Some(ExpressionType::NamedTermApp(name, n)) => {
+                            ExpressionType::NamedTermRef(name, n)
+                        }
+                        _ => {
+                            let expected = TokenKind::Identifier("".to_string());
+                            return Err(match quoted {
+                                Some(tok) => ParserError::UnexpectedToken(tok.clone(), expected),
+                                None => ParserError::ExpectedToken(expected),
+                            });
+                        }
                     }
                 }
                 TokenKind::OpenParen => {
                     self.iter = backtracking_iter; // Allow the sub-parser to consume (
                     ExpressionType::AnonymousTerm(self.parse_anonymous_term()?)
                 }
-                TokenKind::OpenSquare => {
+                TokenKind::OpenSquare | TokenKind::Pri => {
                     self.iter = backtracking_iter; // Allow the sub-parser to consume [
                     self.parse_alternation()?
                 }
@@ -166,6 +706,7 @@ impl<'a> Parser<'a> {
                     self.parse_offset()?
                 }
                 TokenKind::Period => ExpressionType::NamedTermApp(".".to_string(), None),
+                TokenKind::Hole => ExpressionType::Hole,
                 TokenKind::If => {
                     let condition = self.parse_anonymous_term()?;
                     self.consume(TokenKind::Then)?;
@@ -175,6 +716,51 @@ impl<'a> Parser<'a> {
 
                     ExpressionType::If(condition, true_branch, false_branch)
                 }
+                TokenKind::Let => {
+                    let mut names = vec![];
+                    while let Some(
+                        tok @ Token {
+                            kind: TokenKind::Identifier(_),
+                            ..
+                        },
+                    ) = self.iter.clone().next()
+                    {
+                        self.iter.next();
+                        names.push(self.parse_name(tok)?);
+                    }
+                    if names.is_empty() {
+                        return Err(ParserError::ExpectedToken(TokenKind::Identifier(
+                            "".to_string(),
+                        )));
+                    }
+                    self.consume(TokenKind::In)?;
+                    let scope_size = self.locals.len();
+                    self.locals.extend(names.iter().cloned());
+                    let body = self.parse_anonymous_term();
+                    self.locals.truncate(scope_size);
+                    ExpressionType::Let(names, body?)
+                }
+                TokenKind::Case => {
+                    self.iter = backtracking_iter;
+                    self.parse_case()?
+                }
+                TokenKind::Par => {
+                    let backtracking_iter = self.iter.clone();
+                    if self.consume(TokenKind::Underscore).is_ok() {
+                        let n = self.parse_number()?;
+                        let body = self.parse_anonymous_term()?;
+                        ExpressionType::ReplicatedParallel(n, body)
+                    } else {
+                        self.iter = backtracking_iter;
+                        self.parse_parallel()?
+                    }
+                }
+                TokenKind::Alt => {
+                    self.consume(TokenKind::Underscore)?;
+                    let n = self.parse_number()?;
+                    let body = self.parse_anonymous_term()?;
+                    ExpressionType::ReplicatedAlternation(n, body)
+                }
                 TokenKind::While => {
                     let condition = self.parse_anonymous_term()?;
                     self.consume(TokenKind::Do)?;
@@ -184,7 +770,7 @@ impl<'a> Parser<'a> {
                 TokenKind::Repeat => {
                     let backtracking_iter = self.iter.clone();
                     if self.consume(TokenKind::Underscore).is_ok() {
-                        let n = self.consume_number()?;
+                        let n = self.parse_number()?;
                         let body = self.parse_anonymous_term()?;
                         ExpressionType::Repeat(n, body)
                     } else {
@@ -193,13 +779,20 @@ impl<'a> Parser<'a> {
                         ExpressionType::Forever(body)
                     }
                 }
+                // Ends the term before the next declaration
+                TokenKind::Const => return Ok(None),
                 TokenKind::CloseSquare => return Ok(None),
                 TokenKind::CloseParen => return Ok(None),
                 TokenKind::VerticalBar => return Ok(None),
                 _ => return Err(ParserError::DisallowedToken(token.clone())),
             };
             let e_type = None;
-            let expr = Expression { expression, e_type };
+            let span = self.span_since(start);
+            let expr = Expression {
+                expression,
+                e_type,
+                span,
+            };
             Ok(Some(expr))
         } else {
             Ok(None)
@@ -207,23 +800,53 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_anonymous_term(&mut self) -> ParserResult<Box<Term>> {
-        self.consume(TokenKind::OpenParen)?;
-        let term = self.parse_term(false)?;
-        self.consume(TokenKind::CloseParen)?;
+        let open = self.consume(TokenKind::OpenParen)?;
+        let term = self
+            .parse_term(false)
+            .map_err(|e| Parser::unclosed_paren(&open, e))?;
+        self.consume(TokenKind::CloseParen)
+            .map_err(|e| Parser::unclosed_paren(&open, e))?;
         Ok(Box::new(term))
     }
 
+    /// Reports a term that ran into the next declaration or the end of the file at its open paren,
+    /// which is where the close paren is missing from
+    fn unclosed_paren(open: &Token, e: ParserError) -> ParserError {
+        match &e {
+            ParserError::DisallowedToken(Token {
+                kind: TokenKind::Assign,
+                ..
+            })
+            | ParserError::DisallowedToken(Token {
+                kind: TokenKind::DoubleColon,
+                ..
+            })
+            | ParserError::ExpectedToken(_) => ParserError::UnclosedParen(open.clone()),
+            _ => e,
+        }
+    }
+
     fn parse_offset(&mut self) -> ParserResult<ExpressionType> {
         self.consume(TokenKind::Offset)?;
-        let n = self.consume_number()?;
+        let n = self.parse_number()?;
         Ok(ExpressionType::Offset(n))
     }
 
     fn parse_alternation(&mut self) -> ParserResult<ExpressionType> {
+        let backtracking_iter = self.iter.clone();
+        let prioritised = self.consume(TokenKind::Pri).is_ok();
+        if !prioritised {
+            self.iter = backtracking_iter;
+        }
         self.consume(TokenKind::OpenSquare)?;
         let mut list = Vec::new();
         while let Some(branch) = self.parse_alternation_arm()? {
+            let is_default = branch.guard == Guard::Else;
             list.push(branch);
+            // The default arm must come last
+            if is_default {
+                break;
+            }
             // Peek ahead, and check that the next token is a |
             let backtracking_iter = self.iter.clone();
             if let Some(token) = self.iter.next() {
@@ -236,25 +859,99 @@ impl<'a> Parser<'a> {
             }
         }
         self.consume(TokenKind::CloseSquare)?;
-        Ok(ExpressionType::Alternation(list))
+        Ok(ExpressionType::Alternation(list, prioritised))
     }
 
-    fn parse_alternation_arm(&mut self) -> ParserResult<Option<AlternationArm>> {
-        let backtracking_iter = self.iter.clone();
-        let offset = match self.parse_offset() {
-            Ok(ExpressionType::Offset(offset)) => offset,
-            _ => {
-                // In the event of failure we return to the start to allow another parser to
-                // attempt parsing that construct (e.g. most likely ])
+    /// Parses `case [ 0 -> ... | 1 -> ... | else -> ... ]`, where the default arm must come last
+    fn parse_case(&mut self) -> ParserResult<ExpressionType> {
+        self.consume(TokenKind::Case)?;
+        self.consume(TokenKind::OpenSquare)?;
+        let mut arms: Vec<CaseArm> = Vec::new();
+        loop {
+            let backtracking_iter = self.iter.clone();
+            match self.iter.next().map(|tok| &tok.kind) {
+                // Arms may match constants as well as numbers
+                Some(TokenKind::Number(_)) | Some(TokenKind::Identifier(_)) => {
+                    self.iter = backtracking_iter;
+                    let value = self.parse_number()?;
+                    if arms.iter().any(|arm| arm.value == value) {
+                        return Err(ParserError::DuplicateCaseArm(value));
+                    }
+                    self.consume(TokenKind::Arrow)?;
+                    let term = Box::new(self.parse_term(false)?);
+                    arms.push(CaseArm { value, term });
+                    self.consume(TokenKind::VerticalBar)?;
+                }
+                Some(TokenKind::Else) => {
+                    self.consume(TokenKind::Arrow)?;
+                    let default = Box::new(self.parse_term(false)?);
+                    self.consume(TokenKind::CloseSquare)?;
+                    return Ok(ExpressionType::Case(arms, default));
+                }
+                Some(_) => {
+                    let tok = backtracking_iter.clone().next().unwrap();
+                    return Err(ParserError::UnexpectedToken(tok.clone(), TokenKind::Else));
+                }
+                None => return Err(ParserError::ExpectedToken(TokenKind::Else)),
+            }
+        }
+    }
+
+    /// Parses the branches of `par ( ... | ... )`, once `par` has been consumed
+    fn parse_parallel(&mut self) -> ParserResult<ExpressionType> {
+        let open = self.consume(TokenKind::OpenParen)?;
+        let unclosed = |e| Parser::unclosed_paren(&open, e);
+        let mut branches = vec![self.parse_term(false).map_err(unclosed)?];
+        loop {
+            let backtracking_iter = self.iter.clone();
+            if self.consume(TokenKind::VerticalBar).is_err() {
                 self.iter = backtracking_iter;
-                return Ok(None);
+                break;
+            }
+            branches.push(self.parse_term(false).map_err(unclosed)?);
+        }
+        self.consume(TokenKind::CloseParen).map_err(unclosed)?;
+        Ok(ExpressionType::Parallel(branches))
+    }
+
+    /// Parses `@k -> ...` or `after n -> ...`, either of which may be preceded by `(cond) &`, or
+    /// the default arm `else -> ...`
+    fn parse_alternation_arm(&mut self) -> ParserResult<Option<AlternationArm>> {
+        let condition = if let Some(Token {
+            kind: TokenKind::OpenParen,
+            ..
+        }) = self.iter.clone().next()
+        {
+            let condition = self.parse_anonymous_term()?;
+            self.consume(TokenKind::Ampersand)?;
+            Some(condition)
+        } else {
+            None
+        };
+        // In the event of failure the iterator is left at the start of the arm, to allow another
+        // parser to attempt parsing that construct (e.g. most likely ])
+        let guard = match self.iter.clone().next().map(|tok| &tok.kind) {
+            Some(TokenKind::After) => {
+                self.iter.next();
+                Guard::Timer(self.parse_number()?)
+            }
+            Some(TokenKind::Else) if condition.is_none() => {
+                self.iter.next();
+                Guard::Else
             }
+            Some(TokenKind::Offset) => match self.parse_offset()? {
+                ExpressionType::Offset(offset) => Guard::Channel(offset),
+                _ => unreachable!(),
+            },
+            _ if condition.is_some() => return Err(ParserError::ExpectedToken(TokenKind::Offset)),
+            _ => return Ok(None),
         };
         self.consume(TokenKind::Arrow)?;
         let term = Box::new(self.parse_term(false)?);
         let a_type = None;
         let arm = AlternationArm {
-            offset,
+            condition,
+            guard,
             term,
             a_type,
         };
@@ -287,6 +984,108 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses a number, a constant, or constant arithmetic in parentheses, e.g. `chan_(SIZE+1)`
+    fn parse_number(&mut self) -> ParserResult<u16> {
+        let backtracking_iter = self.iter.clone();
+        if self.consume(TokenKind::OpenParen).is_ok() {
+            let n = self.parse_constant_sum()?;
+            self.consume(TokenKind::CloseParen)?;
+            Ok(n)
+        } else {
+            self.iter = backtracking_iter;
+            self.parse_constant_atom()
+        }
+    }
+
+    /// Parses `const NAME = ...`, returning the name and value
+    fn parse_constant(&mut self) -> ParserResult<(String, u16)> {
+        self.consume(TokenKind::Const)?;
+        let name = match self.iter.next() {
+            Some(tok) => Parser::token_to_identifier(tok)?,
+            None => {
+                return Err(ParserError::ExpectedToken(TokenKind::Identifier(
+                    "".to_string(),
+                )))
+            }
+        };
+        self.consume(TokenKind::Assign)?;
+        let value = self.parse_constant_sum()?;
+        Ok((name, value))
+    }
+
+    fn parse_constant_sum(&mut self) -> ParserResult<u16> {
+        let mut value = self.parse_constant_product()?;
+        loop {
+            let backtracking_iter = self.iter.clone();
+            let (subtract, rhs, source) = match self.iter.next() {
+                Some(Token {
+                    kind: TokenKind::Identifier(op),
+                    source,
+                    ..
+                }) if op == "+" || op == "-" => (op == "-", self.parse_constant_product()?, source),
+                // The lexer reads `4 -1` as 4 and the literal -1, which here is a subtraction
+                Some(Token {
+                    kind: TokenKind::SignedNumber(n),
+                    source,
+                    ..
+                }) => (true, self.parse_constant_factors(n.unsigned_abs())?, source),
+                _ => {
+                    self.iter = backtracking_iter;
+                    return Ok(value);
+                }
+            };
+            let result = if subtract {
+                value.checked_sub(rhs)
+            } else {
+                value.checked_add(rhs)
+            };
+            value = self.constant_result(result, source)?;
+        }
+    }
+
+    fn parse_constant_product(&mut self) -> ParserResult<u16> {
+        let value = self.parse_constant_atom()?;
+        self.parse_constant_factors(value)
+    }
+
+    /// Parses the factors that multiply a product's first atom, `value`
+    fn parse_constant_factors(&mut self, mut value: u16) -> ParserResult<u16> {
+        loop {
+            let backtracking_iter = self.iter.clone();
+            let source = match self.iter.next() {
+                Some(Token {
+                    kind: TokenKind::Identifier(op),
+                    source,
+                    ..
+                }) if op == "*" => source,
+                _ => {
+                    self.iter = backtracking_iter;
+                    return Ok(value);
+                }
+            };
+            let rhs = self.parse_constant_atom()?;
+            value = self.constant_result(value.checked_mul(rhs), source)?;
+        }
+    }
+
+    fn constant_result(&self, result: Option<u16>, source: &Source) -> ParserResult<u16> {
+        result.ok_or(ParserError::ConstantOutOfRange(*source))
+    }
+
+    fn parse_constant_atom(&mut self) -> ParserResult<u16> {
+        match self.iter.clone().next().map(|tok| &tok.kind) {
+            Some(TokenKind::Identifier(name)) => {
+                self.iter.next();
+                match self.constants.get(name) {
+                    Some(value) => Ok(*value),
+                    None => Err(ParserError::UnknownConstant(name.to_string())),
+                }
+            }
+            Some(TokenKind::OpenParen) => self.parse_number(),
+            _ => self.consume_number(),
+        }
+    }
+
     fn token_to_identifier(token: &Token) -> ParserResult<String> {
         match &token.kind {
             TokenKind::Identifier(id) => Ok(id.to_string()),
@@ -380,15 +1179,15 @@ mod tests {
         assert_eq!(alternation.name, "alternation");
         let alternation_exprs = &alternation.term.expressions;
         assert_eq!(alternation_exprs.len(), 1);
-        if let ExpressionType::Alternation(arms) = &alternation_exprs[0].expression {
+        if let ExpressionType::Alternation(arms, _) = &alternation_exprs[0].expression {
             assert_eq!(arms.len(), 2);
-            assert_eq!(arms[0].offset, 0);
+            assert_eq!(arms[0].guard, Guard::Channel(0));
             assert_eq!(arms[0].term.expressions.len(), 1);
             assert_eq!(
                 ExpressionType::NamedTermApp("hello".to_string(), None),
                 arms[0].term.expressions[0].expression
             );
-            assert_eq!(arms[1].offset, 7);
+            assert_eq!(arms[1].guard, Guard::Channel(7));
             assert_eq!(arms[1].term.expressions.len(), 1);
             assert_eq!(
                 ExpressionType::NamedTermApp("other".to_string(), None),
@@ -439,6 +1238,75 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_imports() -> ParserResult<()> {
+        let tokens = lex("import relay\nimport counter\nmain = forward").unwrap();
+        let program = parse(&tokens)?;
+        let imports = &program.imports[&None];
+        assert_eq!(imports.len(), 2);
+        assert_eq!(imports[0].module, "relay");
+        assert_eq!(imports[0].source.line_number, 1);
+        assert_eq!(imports[1].module, "counter");
+        assert_eq!(program.declarations.len(), 1);
+        assert_eq!(program.declarations[0].module, None);
+        Ok(())
+    }
+
+    #[test]
+    fn imports_must_precede_declarations() {
+        let tokens = lex("main = forward\nimport relay").unwrap();
+        assert!(parse(&tokens).is_err());
+    }
+
+    #[test]
+    fn parse_signatures() -> ParserResult<()> {
+        let tokens = lex("
+            main = sender
+            sender :: Rest × chan(uses+1, Tx, int) → Rest × chan(uses, Tx, int)
+            sender = 42 !
+            looper :: Rest' × (Rest' → Rest') → ⊥
+            looper = repeat (dup apply)")
+        .unwrap();
+        let program = parse(&tokens)?;
+        assert_eq!(program.declarations.len(), 3);
+        assert_eq!(program.declarations[0].signature, None);
+        let chan = |k| {
+            SignatureType::Channel(
+                SignatureChannelUse::Variable("uses".to_string(), k),
+                Direction::Tx,
+                Box::new(SignatureType::Integer),
+            )
+        };
+        assert_eq!(
+            program.declarations[1].signature,
+            Some(SignatureType::Function(
+                SignatureStack::Stack("Rest".to_string(), vec![chan(1)]),
+                SignatureStack::Stack("Rest".to_string(), vec![chan(0)])
+            ))
+        );
+        let s = SignatureStack::Stack("Rest'".to_string(), vec![]);
+        assert_eq!(
+            program.declarations[2].signature,
+            Some(SignatureType::Function(
+                SignatureStack::Stack(
+                    "Rest'".to_string(),
+                    vec![SignatureType::Function(s.clone(), s)]
+                ),
+                SignatureStack::Bottom
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn signature_needs_declaration() {
+        let tokens = lex("main = .\nother :: Rest → Rest").unwrap();
+        match parse(&tokens) {
+            Err(ParserError::SignatureWithoutDeclaration(name)) => assert_eq!(name, "other"),
+            _ => panic!("Expected a signature without a declaration"),
+        }
+    }
+
     #[test]
     fn parse_the_empty_alternation() {
         let tokens = lex("main = []").unwrap();
@@ -446,13 +1314,61 @@ mod tests {
         let main = &program.declarations[0];
         let main_exprs = &main.term.expressions;
         assert_eq!(main_exprs.len(), 1);
-        if let ExpressionType::Alternation(arms) = &main_exprs[0].expression {
+        if let ExpressionType::Alternation(arms, _) = &main_exprs[0].expression {
             assert!(arms.is_empty());
         } else {
             panic!("{:?} is not an alternation", main_exprs[0]);
         }
     }
 
+    #[test]
+    fn parse_timer_arm() {
+        let tokens = lex("main = [ @1 -> drop | after 100 -> ]").unwrap();
+        let program = parse(&tokens).unwrap();
+        let main_exprs = &program.declarations[0].term.expressions;
+        if let ExpressionType::Alternation(arms, _) = &main_exprs[0].expression {
+            assert_eq!(arms[0].guard, Guard::Channel(1));
+            assert_eq!(arms[1].guard, Guard::Timer(100));
+            assert!(arms[1].term.expressions.is_empty());
+        } else {
+            panic!("{:?} is not an alternation", main_exprs[0]);
+        }
+    }
+
+    #[test]
+    fn parse_guarded_prioritised_alternation() {
+        let tokens =
+            lex("main = pri [ (@2 0 ==) & @1 -> drop | (true) & after 5 -> | @0 -> ]").unwrap();
+        let program = parse(&tokens).unwrap();
+        let main_exprs = &program.declarations[0].term.expressions;
+        if let ExpressionType::Alternation(arms, prioritised) = &main_exprs[0].expression {
+            assert!(prioritised);
+            assert_eq!(arms[0].condition.as_ref().unwrap().expressions.len(), 3);
+            assert_eq!(arms[0].guard, Guard::Channel(1));
+            assert!(arms[1].condition.is_some());
+            assert_eq!(arms[1].guard, Guard::Timer(5));
+            assert!(arms[2].condition.is_none());
+        } else {
+            panic!("{:?} is not an alternation", main_exprs[0]);
+        }
+        assert!(parse(&lex("main = [ (true) & -> ]").unwrap()).is_err());
+    }
+
+    #[test]
+    fn default_arm_must_be_last() {
+        let tokens = lex("main = [ @0 -> | else -> drop ]").unwrap();
+        let program = parse(&tokens).unwrap();
+        let main_exprs = &program.declarations[0].term.expressions;
+        if let ExpressionType::Alternation(arms, _) = &main_exprs[0].expression {
+            assert_eq!(arms[1].guard, Guard::Else);
+        } else {
+            panic!("{:?} is not an alternation", main_exprs[0]);
+        }
+        assert!(parse(&lex("main = [ else -> | @0 -> ]").unwrap()).is_err());
+        assert!(parse(&lex("main = [ @0 -> | else -> | else -> ]").unwrap()).is_err());
+        assert!(parse(&lex("main = [ (true) & else -> ]").unwrap()).is_err());
+    }
+
     #[test]
     fn parse_while_loop() {
         let tokens = lex("main = while (true) do ()").unwrap();
@@ -492,4 +1408,332 @@ mod tests {
             panic!("not a forever");
         }
     }
+
+    #[test]
+    fn parse_let() -> ParserResult<()> {
+        let tokens = lex("main = 1 2 let fst snd in (fst snd thd) fst").unwrap();
+        let program = parse(&tokens)?;
+        let main_exprs = &program.declarations[0].term.expressions;
+        assert_eq!(main_exprs.len(), 4);
+        if let ExpressionType::Let(names, body) = &main_exprs[2].expression {
+            assert_eq!(names, &vec!["fst".to_string(), "snd".to_string()]);
+            assert_eq!(
+                body.expressions[0].expression,
+                ExpressionType::Local("fst".to_string(), None)
+            );
+            assert_eq!(
+                body.expressions[2].expression,
+                ExpressionType::NamedTermApp("thd".to_string(), None)
+            );
+        } else {
+            panic!("Expected a let");
+        }
+        // The names are only in scope in the body
+        assert_eq!(
+            main_exprs[3].expression,
+            ExpressionType::NamedTermApp("fst".to_string(), None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_case() -> ParserResult<()> {
+        let tokens = lex("main = 1 case [ 0 -> 10 | 1 -> 20 30 + | else -> 40 ]").unwrap();
+        let program = parse(&tokens)?;
+        let main_exprs = &program.declarations[0].term.expressions;
+        assert_eq!(main_exprs.len(), 2);
+        if let ExpressionType::Case(arms, default) = &main_exprs[1].expression {
+            assert_eq!(arms.len(), 2);
+            assert_eq!(arms[0].value, 0);
+            assert_eq!(arms[1].value, 1);
+            assert_eq!(arms[1].term.expressions.len(), 3);
+            assert_eq!(
+                default.expressions[0].expression,
+                ExpressionType::Number(40)
+            );
+        } else {
+            panic!("Expected a case");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_parallel() -> ParserResult<()> {
+        let tokens = lex("main = 1 2 par ( 3 + | dup | ) drop").unwrap();
+        let program = parse(&tokens)?;
+        let main_exprs = &program.declarations[0].term.expressions;
+        assert_eq!(main_exprs.len(), 4);
+        if let ExpressionType::Parallel(branches) = &main_exprs[2].expression {
+            assert_eq!(branches.len(), 3);
+            assert_eq!(branches[0].expressions.len(), 2);
+            assert_eq!(
+                branches[1].expressions[0].expression,
+                ExpressionType::NamedTermApp("dup".to_string(), None)
+            );
+            assert!(branches[2].expressions.is_empty());
+        } else {
+            panic!("Expected a par");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_replicated_par_and_alt() -> ParserResult<()> {
+        let tokens = lex("main = par_4 (drop) alt_2 (+)").unwrap();
+        let program = parse(&tokens)?;
+        let main_exprs = &program.declarations[0].term.expressions;
+        assert_eq!(main_exprs.len(), 2);
+        match &main_exprs[0].expression {
+            ExpressionType::ReplicatedParallel(4, body) => assert_eq!(body.expressions.len(), 1),
+            _ => panic!("Expected a replicated par"),
+        }
+        match &main_exprs[1].expression {
+            ExpressionType::ReplicatedAlternation(2, body) => {
+                assert_eq!(body.expressions.len(), 1)
+            }
+            _ => panic!("Expected a replicated alt"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn constants_can_be_used_before_their_declaration() -> ParserResult<()> {
+        let tokens = lex("
+            main = chan_(SIZE*2-1) SIZE case [ SIZE -> . | else -> . ]
+            const SIZE = 3
+            const MORE = SIZE + 1")
+        .unwrap();
+        let program = parse(&tokens)?;
+        assert_eq!(program.declarations.len(), 1);
+        let main_exprs = &program.declarations[0].term.expressions;
+        assert_eq!(
+            main_exprs[0].expression,
+            ExpressionType::NamedTermApp("chan".to_string(), Some(5))
+        );
+        assert_eq!(main_exprs[1].expression, ExpressionType::Number(3));
+        match &main_exprs[2].expression {
+            ExpressionType::Case(arms, _) => assert_eq!(arms[0].value, 3),
+            _ => panic!("Expected a case"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn constant_subtraction_may_be_spaced_like_a_negative_literal() -> ParserResult<()> {
+        let tokens = lex("
+            const N = 4 -1
+            main = chan_(N -1) chan_(N -2*1)")
+        .unwrap();
+        let program = parse(&tokens)?;
+        let main_exprs = &program.declarations[0].term.expressions;
+        assert_eq!(
+            main_exprs[0].expression,
+            ExpressionType::NamedTermApp("chan".to_string(), Some(2))
+        );
+        assert_eq!(
+            main_exprs[1].expression,
+            ExpressionType::NamedTermApp("chan".to_string(), Some(1))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn constants_must_be_declared_before_other_constants_use_them() {
+        let tokens = lex("const FIRST = SECOND + 1 const SECOND = 1").unwrap();
+        match parse(&tokens) {
+            Err(ParserError::UnknownConstant(name)) => assert_eq!(name, "SECOND"),
+            r => panic!("Expected an unknown constant, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn constants_must_be_distinct() {
+        let tokens = lex("const SIZE = 1 const SIZE = 2").unwrap();
+        match parse(&tokens) {
+            Err(ParserError::DuplicateConstant(name)) => assert_eq!(name, "SIZE"),
+            r => panic!("Expected a duplicate constant, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn constant_arithmetic_must_fit_in_a_word() {
+        for src in &["const SIZE = 0 - 1", "const SIZE = 256 * 256"] {
+            let tokens = lex(src).unwrap();
+            match parse(&tokens) {
+                Err(ParserError::ConstantOutOfRange(_)) => {}
+                r => panic!("Expected an out of range constant, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_parameterised_declaration() -> ParserResult<()> {
+        let tokens = lex("main = 1 add_2\nadd_count = count +\nother = 3").unwrap();
+        let program = parse(&tokens)?;
+        assert_eq!(program.declarations.len(), 3);
+        assert_eq!(program.declarations[0].term.expressions.len(), 2);
+        let add = &program.declarations[1];
+        assert_eq!(add.name, "add");
+        let template = add.template.as_ref().unwrap();
+        assert_eq!(template.parameter, "count");
+        let instance = parse_template_instance(template, 4)?;
+        assert_eq!(
+            instance.expressions[0].expression,
+            ExpressionType::Number(4)
+        );
+        assert_eq!(instance.expressions.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn underscores_join_words_unless_a_constant_follows() -> ParserResult<()> {
+        let tokens = lex("
+            max_depth = 3
+            main = max_depth chan_N let my_val in (my_val) max_ N
+            const N = 2")
+        .unwrap();
+        let program = parse(&tokens)?;
+        assert_eq!(program.declarations[0].name, "max_depth");
+        assert!(program.declarations[0].template.is_none());
+        let main_exprs = &program.declarations[1].term.expressions;
+        let named = |name: &str, k| ExpressionType::NamedTermApp(name.to_string(), k);
+        assert_eq!(main_exprs[0].expression, named("max_depth", None));
+        assert_eq!(main_exprs[1].expression, named("chan", Some(2)));
+        match &main_exprs[2].expression {
+            ExpressionType::Let(names, _) => assert_eq!(names, &vec!["my_val".to_string()]),
+            _ => panic!("Expected a let"),
+        }
+        assert_eq!(main_exprs[3].expression, named("max", Some(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn case_arms_must_be_distinct() {
+        let tokens = lex("main = case [ 1 -> . | 1 -> . | else -> . ]").unwrap();
+        match parse(&tokens) {
+            Err(ParserError::DuplicateCaseArm(1)) => {}
+            _ => panic!("Expected a duplicate arm"),
+        }
+    }
+
+    #[test]
+    fn expressions_record_their_span() {
+        let tokens = lex("main = 1\n  (dup +)").unwrap();
+        let program = parse(&tokens).unwrap();
+        let main = &program.declarations[0];
+        assert_eq!(main.span.start.line_offset, 0);
+        assert_eq!(main.span.end.line_offset, 4);
+        let expr = &main.term.expressions[1];
+        assert_eq!(expr.span.start.line_number, 2);
+        assert_eq!(expr.span.start.line_offset, 2);
+        assert_eq!(expr.span.end.line_offset, 9);
+        assert_eq!(main.term.span.start.line_offset, 7);
+        assert_eq!(main.term.span.end, expr.span.end);
+        if let ExpressionType::AnonymousTerm(term) = &expr.expression {
+            assert_eq!(term.span.start.line_offset, 3);
+            assert_eq!(term.span.end.line_offset, 8);
+        } else {
+            panic!("Expected a quotation");
+        }
+    }
+
+    #[test]
+    fn recovers_at_the_next_declaration() {
+        let tokens = lex("main = 1 ok\nbad = ( 1\nquote = ' )\nok = 2\nsig :: Rest →").unwrap();
+        let (program, errors) = parse_recovering(&tokens);
+        assert_eq!(errors.len(), 3);
+        match &errors[1] {
+            ParserError::UnexpectedToken(tok, _) => assert_eq!(tok.kind, TokenKind::CloseParen),
+            e => panic!("Expected the quote to be reported, not {}", e),
+        }
+        let names: Vec<&str> = program
+            .declarations
+            .iter()
+            .map(|decl| decl.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["main", "ok"]);
+        assert!(program.unparsed.contains("bad"));
+        assert!(program.unparsed.contains("quote"));
+    }
+
+    #[test]
+    fn unclosed_paren_is_reported_where_it_opens() {
+        for src in &["main = 1 (2 +\nok = 2", "main = 1 (2 +"] {
+            let tokens = lex(src).unwrap();
+            let (program, errors) = parse_recovering(&tokens);
+            match &errors[..] {
+                [ParserError::UnclosedParen(tok)] => {
+                    assert_eq!(tok.kind, TokenKind::OpenParen);
+                    assert_eq!((tok.source.line_number, tok.source.line_offset), (1, 9));
+                }
+                e => panic!("Expected an unclosed paren, not {:?}", e),
+            }
+            assert!(program.unparsed.contains("main"));
+        }
+    }
+
+    #[test]
+    fn no_token_sequence_makes_the_parser_panic() {
+        let identifier = |name: &str| TokenKind::Identifier(name.to_string());
+        let kinds = vec![
+            TokenKind::Number(1),
+            TokenKind::SignedNumber(-1),
+            TokenKind::Assign,
+            identifier("x"),
+            identifier("+"),
+            TokenKind::OpenParen,
+            TokenKind::CloseParen,
+            TokenKind::OpenSquare,
+            TokenKind::CloseSquare,
+            TokenKind::VerticalBar,
+            TokenKind::Arrow,
+            TokenKind::Offset,
+            TokenKind::Quote,
+            TokenKind::Underscore,
+            TokenKind::Hole,
+            TokenKind::If,
+            TokenKind::Then,
+            TokenKind::Else,
+            TokenKind::While,
+            TokenKind::Do,
+            TokenKind::Repeat,
+            TokenKind::Period,
+            TokenKind::Import,
+            TokenKind::Let,
+            TokenKind::In,
+            TokenKind::Case,
+            TokenKind::After,
+            TokenKind::Pri,
+            TokenKind::Par,
+            TokenKind::Alt,
+            TokenKind::Const,
+            TokenKind::Ampersand,
+            TokenKind::DoubleColon,
+            TokenKind::Comma,
+            TokenKind::Times,
+            TokenKind::Bottom,
+        ];
+        let token = |kind: &TokenKind| Token {
+            kind: kind.clone(),
+            source: Source::default(),
+            length: 1,
+        };
+        // Every sequence of up to three tokens, after the start of a declaration
+        let prefix = vec![token(&identifier("main")), token(&TokenKind::Assign)];
+        for a in &kinds {
+            for b in &kinds {
+                for c in &kinds {
+                    for length in 1..=3 {
+                        let sequence: Vec<Token> = [a, b, c]
+                            .iter()
+                            .take(length)
+                            .map(|kind| token(kind))
+                            .collect();
+                        parse_recovering(&sequence);
+                        parse_recovering(&[prefix.clone(), sequence].concat());
+                    }
+                }
+            }
+        }
+    }
 }
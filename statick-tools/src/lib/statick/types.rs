@@ -1,14 +1,22 @@
 use super::ast::{
-    AlternationArm, AstVisitor, Declaration, Expression, ExpressionType, MutAstVisitor, Program,
-    Term,
+    qualified_name, AlternationArm, AstVisitor, Declaration, Expression, ExpressionType, Guard,
+    ModuleName, MutAstVisitor, Program, Term,
 };
+use super::diagnostic::Note;
+use super::lexer::Span;
+use super::parser::parse_template_instance;
+use crate::processor::MAX_CHANNEL_CAPACITY;
 
 use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
 
 mod constraint;
 mod constraint_set;
+mod hole;
+mod locals;
 mod mgu;
+mod scc;
+mod signature;
 mod stack;
 mod stack_constraint;
 mod subscript;
@@ -20,6 +28,8 @@ mod unifier;
 
 pub use constraint::{Constraint, TypeConstraints};
 pub use constraint_set::ConstraintSet;
+pub use hole::Hole;
+use signature::{elaborate_signature, only_renames};
 pub use stack::Stack;
 pub use stack_constraint::{StackConstraint, StackConstraints};
 pub use subscript::subscripted;
@@ -29,25 +39,84 @@ pub use type_fmt::TypeFmt;
 pub use type_impl::{ChannelUse, ChannelVariable, ChannelVariableOffset, Direction, Type};
 pub use unifier::{ApplyUnifierStep, Unifier, UnifierStep};
 
+/// How many instances deep a parameterised declaration can be instantiated, as one that
+/// instantiates itself with a different parameter would otherwise never stop
+pub const MAX_INSTANTIATION_DEPTH: usize = 100;
+
+/// The limit on refitting a recursive group's uses to its types. Each pass may only add values
+/// beneath a use, so a group whose types still change after this many passes is rejected.
+pub const MAX_RECURSION_PASSES: usize = 100;
+
+/// An alt_n keeps a word with a bit for each channel it has received from
+pub const MAX_ALTERNATION_CHANNELS: u16 = 16;
+
+/// Checks the program, recording any holes in it even if it has a type error
 pub fn type_check(program: &mut Program) -> TypeCheckResult<()> {
-    TypeChecker::default().check(program)
+    let mut checker = TypeChecker::default();
+    let result = checker.check(program);
+    program.holes = checker.holes;
+    result
+}
+
+/// Visits each expression of a term, pointing errors at the expression that they came from
+fn visit_located_term<V>(visitor: &mut V, term: &mut Term) -> TypeCheckResult<()>
+where
+    V: MutAstVisitor<TypeError>,
+{
+    for expr in &mut term.expressions {
+        let span = expr.span;
+        visitor
+            .visit_expression(expr)
+            .map_err(|e| e.at(span, vec![]))?;
+    }
+    Ok(())
 }
 
 #[derive(Default)]
 struct TypeChecker {
     environment: HashMap<String, Type>,
+    /// The types of declarations that were given a signature
+    signatures: HashMap<String, Type>,
+    /// Names bound by the enclosing lets, innermost last
+    locals: Vec<(String, Type)>,
+    /// Declarations without signatures in the group being inferred
+    group: Vec<String>,
+    /// Uses of the declarations in `group`, which must all fit their types once they are inferred
+    group_uses: Vec<GroupUse>,
+    /// Declarations that had syntax errors, or that use one, so aren't checked
+    unparsed: HashSet<String>,
+    /// Types are narrowed as the terms around each hole are checked
+    holes: Vec<Hole>,
+    /// Declarations that have a hole or use one, so may leave a stack unrelated to their input
+    with_holes: HashSet<String>,
+    /// Whether the group being inferred uses a declaration in `with_holes`
+    group_uses_hole: bool,
     alloc: TypeAllocator,
 }
 
+/// Indices into the program's declarations of declarations that use each other, so are inferred
+/// together
+type Group = Vec<usize>;
+
+/// A use of a declaration in the group being inferred
+struct GroupUse {
+    name: String,
+    span: Span,
+    t: Type,
+}
+
 impl TypeChecker {
     fn check(&mut self, program: &mut Program) -> TypeCheckResult<()> {
         self.elaborate_standard_library()?;
+        self.check_constant_names(program)?;
+        self.qualify_names(program)?;
+        self.remove_unparsed_dependents(program)?;
         self.check_for_duplicate_names(program)?;
         self.annotate_declarations_with_generic_type(program)?;
-        let topo_order = self.sort_definitions_topologically(program)?;
-        self.annotate(program, topo_order)?;
+        let groups = self.sort_definitions_topologically(program)?;
+        self.annotate(program, groups)?;
         self.check_inferred_types_match(program)?;
-        self.check_main_takes_empty_stack()?;
+        self.check_main_takes_empty_stack(program)?;
         Ok(())
     }
 
@@ -99,6 +168,27 @@ impl TypeChecker {
         }
 
         self.add_func(".", vec![], vec![])?;
+        self.add_func("yield", vec![], vec![])?;
+
+        {
+            let t = self.alloc.generic_type();
+            let rx = Type::Channel(ChannelUse::Shared, Direction::Rx, Box::new(t.clone()));
+            let tx = Type::Channel(ChannelUse::Shared, Direction::Tx, Box::new(t));
+            self.add_func("sharedchan", vec![], vec![rx, tx])?;
+        }
+
+        {
+            let t = self.alloc.generic_type();
+            let tx = Type::Channel(ChannelUse::Shared, Direction::Tx, Box::new(t));
+            self.add_func("share", vec![tx.clone()], vec![tx.clone(), tx])?;
+        }
+
+        {
+            let a = self
+                .alloc
+                .generic_type_with_constraints(vec![Constraint::Releasable]);
+            self.add_func("release", vec![a], vec![])?;
+        }
 
         {
             // if :: S * (S -> a bool) * (a -> b) * (a -> b) -> b
@@ -148,30 +238,44 @@ impl TypeChecker {
             self.add_to_environment("forever", forever_t, false)?;
         }
 
-        self.add_func("+", vec![Type::Integer, Type::Integer], vec![Type::Integer])?;
-        self.add_func("-", vec![Type::Integer, Type::Integer], vec![Type::Integer])?;
-        self.add_func(">", vec![Type::Integer, Type::Integer], vec![Type::Boolean])?;
-        self.add_func(
-            ">=",
-            vec![Type::Integer, Type::Integer],
-            vec![Type::Boolean],
-        )?;
-        self.add_func("<", vec![Type::Integer, Type::Integer], vec![Type::Boolean])?;
-        self.add_func(
-            "<=",
-            vec![Type::Integer, Type::Integer],
-            vec![Type::Boolean],
-        )?;
-        self.add_func(
-            "==",
-            vec![Type::Integer, Type::Integer],
-            vec![Type::Boolean],
-        )?;
-        self.add_func(
-            "!=",
-            vec![Type::Integer, Type::Integer],
-            vec![Type::Boolean],
-        )?;
+        // Addition, subtraction, multiplication, and equality are the same operations for both
+        // signed and unsigned integers
+        for name in &["+", "-", "*"] {
+            let a = self
+                .alloc
+                .generic_type_with_constraints(vec![Constraint::Numeric]);
+            self.add_func(name, vec![a.clone(), a.clone()], vec![a])?;
+        }
+        for name in &["==", "!="] {
+            let a = self
+                .alloc
+                .generic_type_with_constraints(vec![Constraint::Numeric]);
+            self.add_func(name, vec![a.clone(), a], vec![Type::Boolean])?;
+        }
+        for name in &[">", ">=", "<", "<="] {
+            self.add_func(
+                name,
+                vec![Type::Integer, Type::Integer],
+                vec![Type::Boolean],
+            )?;
+        }
+        for name in &["s>", "s>=", "s<", "s<="] {
+            self.add_func(
+                name,
+                vec![Type::SignedInteger, Type::SignedInteger],
+                vec![Type::Boolean],
+            )?;
+        }
+        self.add_func("toSint", vec![Type::Integer], vec![Type::SignedInteger])?;
+        self.add_func("fromSint", vec![Type::SignedInteger], vec![Type::Integer])?;
+        self.add_func("neg", vec![Type::SignedInteger], vec![Type::SignedInteger])?;
+        for name in &["s/", "s%"] {
+            self.add_func(
+                name,
+                vec![Type::SignedInteger, Type::SignedInteger],
+                vec![Type::SignedInteger],
+            )?;
+        }
         self.add_func(
             "and",
             vec![Type::Boolean, Type::Boolean],
@@ -184,19 +288,302 @@ impl TypeChecker {
         )?;
         self.add_func("not", vec![Type::Boolean], vec![Type::Boolean])?;
 
+        // Bitwise operations, shifts, and the arithmetic operations without a single instruction
+        for name in &["/", "%", "xor", "bitand", "bitor", "asl", "lsl", "lsr"] {
+            self.add_func(
+                name,
+                vec![Type::Integer, Type::Integer],
+                vec![Type::Integer],
+            )?;
+        }
+        self.add_func("bitnot", vec![Type::Integer], vec![Type::Integer])?;
+        // Shifting right arithmetically keeps the sign, so it also applies to signed integers
+        let a = self
+            .alloc
+            .generic_type_with_constraints(vec![Constraint::Numeric]);
+        self.add_func("asr", vec![a.clone(), Type::Integer], vec![a])?;
+
         let apply_type = self.alloc.apply_type();
         self.add_to_environment("apply", apply_type, true)?;
 
         Ok(())
     }
 
+    /**
+     * Each module has its own namespace. A name used in a module refers to that module's own
+     * declaration if there is one, and otherwise to a declaration in one of the modules that it
+     * imports. All declarations and references are renamed to their qualified names, so later
+     * stages can treat the program as having a single global environment.
+     */
+    fn qualify_names(&self, program: &mut Program) -> TypeCheckResult<()> {
+        struct Visitor {
+            local_names: HashSet<String>,
+            imported_names: HashMap<String, Vec<String>>,
+            module: ModuleName,
+        }
+
+        impl Visitor {
+            fn qualify(&self, name: &mut String) -> TypeCheckResult<()> {
+                if self.local_names.contains(name) {
+                    *name = qualified_name(&self.module, name);
+                } else if let Some(modules) = self.imported_names.get(name) {
+                    if modules.len() > 1 {
+                        return Err(TypeError::AmbiguousName(name.to_string(), modules.clone()));
+                    }
+                    *name = qualified_name(&Some(modules[0].to_string()), name);
+                }
+                Ok(())
+            }
+        }
+
+        /// Renames uses of templates to their instances, recording each one
+        struct Instances<'a> {
+            templates: &'a HashMap<String, Declaration>,
+            uses: Vec<(String, u16)>,
+        }
+
+        impl<'a> Instances<'a> {
+            fn instantiate(
+                &mut self,
+                name: &mut String,
+                k: &mut Option<u16>,
+            ) -> TypeCheckResult<()> {
+                if self.templates.contains_key(name.as_str()) {
+                    match k.take() {
+                        Some(k) => {
+                            self.uses.push((name.to_string(), k));
+                            *name = format!("{}_{}", name, k);
+                        }
+                        None => return Err(TypeError::NameNeedsParameter(name.to_string())),
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        impl<'a> MutAstVisitor<TypeError> for Instances<'a> {
+            fn visit_term(&mut self, term: &mut Term) -> TypeCheckResult<()> {
+                visit_located_term(self, term)
+            }
+
+            fn visit_named_term_app(
+                &mut self,
+                name: &mut String,
+                k: &mut Option<u16>,
+            ) -> TypeCheckResult<()> {
+                self.instantiate(name, k)
+            }
+
+            fn visit_named_term_ref(
+                &mut self,
+                name: &mut String,
+                k: &mut Option<u16>,
+            ) -> TypeCheckResult<()> {
+                self.instantiate(name, k)
+            }
+        }
+
+        impl MutAstVisitor<TypeError> for Visitor {
+            fn visit_term(&mut self, term: &mut Term) -> TypeCheckResult<()> {
+                visit_located_term(self, term)
+            }
+
+            fn visit_named_term_app(
+                &mut self,
+                name: &mut String,
+                _k: &mut Option<u16>,
+            ) -> TypeCheckResult<()> {
+                self.qualify(name)
+            }
+
+            fn visit_named_term_ref(
+                &mut self,
+                name: &mut String,
+                _k: &mut Option<u16>,
+            ) -> TypeCheckResult<()> {
+                self.qualify(name)
+            }
+        }
+
+        let mut module_names: HashMap<ModuleName, HashSet<String>> = HashMap::new();
+        for decl in &program.declarations {
+            module_names
+                .entry(decl.module.clone())
+                .or_default()
+                .insert(decl.name.to_string());
+        }
+
+        let mut visitors = HashMap::new();
+        for module in module_names.keys() {
+            let mut imported_names: HashMap<String, Vec<String>> = HashMap::new();
+            for import in program.imports.get(module).into_iter().flatten() {
+                let imported_module = Some(import.module.to_string());
+                for name in module_names.get(&imported_module).into_iter().flatten() {
+                    let modules = imported_names.entry(name.to_string()).or_default();
+                    if !modules.contains(&import.module) {
+                        modules.push(import.module.to_string());
+                    }
+                }
+            }
+            let visitor = Visitor {
+                local_names: module_names[module].clone(),
+                imported_names,
+                module: module.clone(),
+            };
+            visitors.insert(module.clone(), visitor);
+        }
+
+        for decl in &mut program.declarations {
+            visitors
+                .get_mut(&decl.module)
+                .unwrap()
+                .visit_declaration(decl)?;
+            decl.name = qualified_name(&decl.module, &decl.name);
+        }
+
+        // Each use of a parameterised declaration becomes a use of an instance for that value.
+        // Instances are qualified in the module of their template, and may use further instances.
+        let (templates, declarations): (Vec<Declaration>, Vec<Declaration>) = program
+            .declarations
+            .drain(..)
+            .partition(|decl| decl.template.is_some());
+        program.declarations = declarations;
+        let template_names: Vec<String> = templates.iter().map(|t| t.name.to_string()).collect();
+        let mut used_templates = HashSet::new();
+        let templates: HashMap<String, Declaration> = templates
+            .into_iter()
+            .map(|decl| (decl.name.to_string(), decl))
+            .collect();
+        let mut instantiated = HashSet::new();
+        // How many instances led to each declaration
+        let mut depths = vec![0; program.declarations.len()];
+        let mut i = 0;
+        while i < program.declarations.len() {
+            let mut instances = Instances {
+                templates: &templates,
+                uses: vec![],
+            };
+            instances.visit_declaration(&mut program.declarations[i])?;
+            for (name, k) in instances.uses {
+                used_templates.insert(name.to_string());
+                let instance_name = format!("{}_{}", name, k);
+                if !instantiated.insert(instance_name.to_string()) {
+                    continue;
+                }
+                let template = &templates[&name];
+                if depths[i] == MAX_INSTANTIATION_DEPTH {
+                    return Err(TypeError::InstantiationTooDeep(name).at(template.span, vec![]));
+                }
+                let term = parse_template_instance(template.template.as_ref().unwrap(), k)
+                    .map_err(|e| {
+                        TypeError::InstantiationFailed(name.to_string(), k, e.to_string())
+                            .at(template.span, vec![])
+                    })?;
+                let mut decl = Declaration {
+                    name: instance_name,
+                    span: template.span,
+                    term: Box::new(term),
+                    module: template.module.clone(),
+                    signature: template.signature.clone(),
+                    template: None,
+                };
+                visitors
+                    .get_mut(&decl.module)
+                    .unwrap()
+                    .visit_declaration(&mut decl)?;
+                program.declarations.push(decl);
+                depths.push(depths[i] + 1);
+            }
+            i += 1;
+        }
+        // Only the file being compiled is held to this, as a module may offer templates for the
+        // programs that import it
+        let unused = template_names.iter().find(|name| {
+            templates[name.as_str()].module.is_none() && !used_templates.contains(name.as_str())
+        });
+        match unused {
+            Some(name) => {
+                Err(TypeError::UnusedTemplate(name.to_string()).at(templates[name].span, vec![]))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /**
+     * The types of declarations that had syntax errors are unknown, so anything that uses them
+     * can't be checked either. The rest of the program is still checked for errors.
+     */
+    fn remove_unparsed_dependents(&mut self, program: &mut Program) -> TypeCheckResult<()> {
+        struct Visitor<'a> {
+            unparsed: &'a HashSet<String>,
+            uses_unparsed: bool,
+        }
+
+        impl<'a> AstVisitor<TypeError> for Visitor<'a> {
+            fn visit_named_term_app(&mut self, name: &str, _k: Option<u16>) -> TypeCheckResult<()> {
+                self.uses_unparsed |= self.unparsed.contains(name);
+                Ok(())
+            }
+
+            fn visit_named_term_ref(&mut self, name: &str, _k: Option<u16>) -> TypeCheckResult<()> {
+                self.uses_unparsed |= self.unparsed.contains(name);
+                Ok(())
+            }
+        }
+
+        self.unparsed = program.unparsed.clone();
+        loop {
+            let mut dependents = vec![];
+            for (i, decl) in program.declarations.iter().enumerate() {
+                let mut visitor = Visitor {
+                    unparsed: &self.unparsed,
+                    uses_unparsed: false,
+                };
+                visitor.visit_declaration(decl)?;
+                if visitor.uses_unparsed {
+                    dependents.push(i);
+                }
+            }
+            if dependents.is_empty() {
+                return Ok(());
+            }
+            for i in dependents.into_iter().rev() {
+                let decl = program.declarations.remove(i);
+                self.unparsed.insert(decl.name);
+            }
+        }
+    }
+
+    /// A constant's name is replaced with its value, so it would hide a word with the same name
+    fn check_constant_names(&self, program: &Program) -> TypeCheckResult<()> {
+        for constant in &program.constants {
+            let name = &constant.name;
+            let error = TypeError::ConstantNameInUse(name.to_string());
+            if self.environment.contains_key(name) || TypeChecker::is_parameterised_word(name) {
+                return Err(error.at(constant.span, vec![]));
+            }
+            let declaration = program
+                .declarations
+                .iter()
+                .find(|decl| &decl.name == name && decl.module == constant.module);
+            if let Some(decl) = declaration {
+                let note = Note::new(decl.span, "also declared here".to_string());
+                return Err(error.at(constant.span, vec![note]));
+            }
+        }
+        Ok(())
+    }
+
     fn check_for_duplicate_names(&self, program: &Program) -> TypeCheckResult<()> {
-        let mut names = HashSet::new();
+        let mut names = HashMap::new();
         for decl in &program.declarations {
-            if names.contains(&decl.name) {
-                return Err(TypeError::DuplicateName(decl.name.to_string()));
+            if let Some(span) = names.get(&decl.name) {
+                let note = Note::new(*span, "first defined here".to_string());
+                return Err(
+                    TypeError::DuplicateName(decl.name.to_string()).at(decl.span, vec![note])
+                );
             }
-            names.insert(decl.name.to_string());
+            names.insert(decl.name.to_string(), decl.span);
         }
         Ok(())
     }
@@ -214,37 +601,47 @@ impl TypeChecker {
             constraints.insert(StackConstraint::AllowBottom);
             let output_t = self.alloc.type_stack(constraints);
             let func_t = Type::Function(Box::new(input_t), Box::new(output_t));
-            self.add_to_environment(&decl.name, func_t, true)?;
+            // Recursive uses of a declaration with a signature already see the declared type
+            let func_t = match &decl.signature {
+                Some(signature) => {
+                    let signature_t = elaborate_signature(signature, &mut self.alloc);
+                    let func_t = self
+                        .check_signature(&decl.name, &func_t, &signature_t)
+                        .map_err(|e| e.at(decl.span, vec![]))?
+                        .apply(&func_t);
+                    self.signatures.insert(decl.name.to_string(), signature_t);
+                    func_t
+                }
+                None => func_t,
+            };
+            self.add_to_environment(&decl.name, func_t, true)
+                .map_err(|e| e.at(decl.span, vec![]))?;
         }
         Ok(())
     }
 
-    fn sort_definitions_topologically<'a>(
-        &self,
-        program: &'a Program,
-    ) -> TypeCheckResult<Vec<usize>> {
-        struct Visitor<'a, 'b> {
-            order: Vec<String>,
-            visited: HashSet<String>,
-            program: &'a Program,
-            declarations: HashMap<String, usize>,
-            environment: &'b HashMap<String, Type>,
+    /// Groups declarations that use each other, with each group after the groups that it uses
+    fn sort_definitions_topologically(&self, program: &Program) -> TypeCheckResult<Vec<Group>> {
+        struct Visitor<'a> {
+            /// The declarations used by the declaration being visited
+            uses: Vec<usize>,
+            declarations: HashMap<&'a str, usize>,
+            environment: &'a HashMap<String, Type>,
         }
 
-        impl<'a, 'b> Visitor<'a, 'b> {
+        impl<'a> Visitor<'a> {
             fn visit_name(&mut self, name: &str, _k: Option<u16>) -> TypeCheckResult<()> {
                 match self.declarations.get(name) {
-                    Some(idx) => self.visit_declaration(&self.program.declarations[*idx]),
+                    Some(idx) => {
+                        if !self.uses.contains(idx) {
+                            self.uses.push(*idx);
+                        }
+                        Ok(())
+                    }
                     None => {
                         // Check if it is in the standard library
                         if self.environment.contains_key(name)
-                            // Standard library functions that can be parameterised with numbers
-                            // See |get_environment_type|
-                            || name == "chan"
-                            || name == "proc"
-                            || name == "?"
-                            || name == "!"
-                            || name == "del"
+                            || TypeChecker::is_parameterised_word(name)
                         {
                             Ok(())
                         } else {
@@ -255,12 +652,11 @@ impl TypeChecker {
             }
         }
 
-        impl<'a, 'b> AstVisitor<TypeError> for Visitor<'a, 'b> {
-            fn visit_declaration(&mut self, decl: &Declaration) -> TypeCheckResult<()> {
-                if !self.visited.contains(&decl.name) {
-                    self.visited.insert(decl.name.to_string());
-                    self.visit_term(&decl.term)?;
-                    self.order.push(decl.name.to_string());
+        impl<'a> AstVisitor<TypeError> for Visitor<'a> {
+            fn visit_term(&mut self, term: &Term) -> TypeCheckResult<()> {
+                for expr in &term.expressions {
+                    self.visit_expression(expr)
+                        .map_err(|e| e.at(expr.span, vec![]))?;
                 }
                 Ok(())
             }
@@ -274,41 +670,28 @@ impl TypeChecker {
             }
         }
 
-        let order = Vec::new();
-        let visited = HashSet::new();
         let mut declarations = HashMap::new();
         for (i, decl) in program.declarations.iter().enumerate() {
-            declarations.insert(decl.name.to_string(), i);
+            declarations.insert(decl.name.as_str(), i);
         }
-        let environment = &self.environment;
-
         let mut visitor = Visitor {
-            order,
-            visited,
-            program,
+            uses: vec![],
             declarations,
-            environment,
+            environment: &self.environment,
         };
-        visitor.visit_program(program)?;
-
-        let Visitor {
-            order: order_names,
-            declarations,
-            ..
-        } = visitor;
-
-        let mut order = Vec::new();
-        for order_name in order_names {
-            order.push(declarations[&order_name]);
+        let mut dependencies = vec![];
+        for decl in &program.declarations {
+            visitor.visit_declaration(decl)?;
+            dependencies.push(std::mem::take(&mut visitor.uses));
         }
 
-        Ok(order)
+        Ok(scc::strongly_connected_components(&dependencies))
     }
 
     fn annotate<'a>(
         &'a mut self,
         program: &mut Program,
-        topo_order: Vec<usize>,
+        groups: Vec<Group>,
     ) -> TypeCheckResult<()> {
         struct Visitor<'a> {
             checker: &'a mut TypeChecker,
@@ -318,22 +701,142 @@ impl TypeChecker {
             fn visit_program_topologically(
                 &mut self,
                 program: &mut Program,
-                topo_order: Vec<usize>,
+                groups: Vec<Group>,
             ) -> TypeCheckResult<()> {
-                // Visit declarations in the order found in the previous stage
-                for i in topo_order {
-                    self.visit_declaration(&mut program.declarations[i])?;
-                    let name = program.declarations[i].name.to_string();
-                    let t = program.declarations[i]
-                        .term
-                        .t_type
-                        .as_ref()
-                        .unwrap()
-                        .clone();
-                    self.checker.add_to_environment(&name, t, false)?;
+                /// Narrows the types recorded in a declaration once its group's types are known
+                struct Narrower<'a> {
+                    unifier: &'a Unifier,
+                }
+
+                impl<'a> MutAstVisitor<TypeError> for Narrower<'a> {
+                    fn visit_term(&mut self, term: &mut Term) -> TypeCheckResult<()> {
+                        term.t_type = term.t_type.as_ref().map(|t| self.unifier.apply(t));
+                        for expr in &mut term.expressions {
+                            expr.e_type = expr.e_type.as_ref().map(|t| self.unifier.apply(t));
+                            self.visit_expression(expr)?;
+                        }
+                        Ok(())
+                    }
+                }
+
+                // Visit groups in the order found in the previous stage
+                for group in groups {
+                    let first_hole = self.checker.holes.len();
+                    // Every use within the group is recorded, and once the whole group has been
+                    // visited they are unified with the group's types together, so the types
+                    // don't depend on the order of the declarations. The group is then
+                    // generalised, as uses after it get fresh copies of its types.
+                    self.checker.group = group
+                        .iter()
+                        .map(|&i| &program.declarations[i].name)
+                        .filter(|name| !self.checker.signatures.contains_key(name.as_str()))
+                        .cloned()
+                        .collect();
+                    for &i in &group {
+                        self.infer_declaration(&mut program.declarations[i])?;
+                    }
+                    let unifier = self.checker.fit_group_uses(first_hole)?;
+                    for &i in &group {
+                        let decl = &mut program.declarations[i];
+                        Narrower { unifier: &unifier }.visit_declaration(decl)?;
+                        decl.term.t_type = Some(self.checker.environment[&decl.name].clone());
+                    }
+                    // A hole can leave a stack that has nothing to do with the one it was given,
+                    // and so can anything that uses the declaration that it is in
+                    let has_hole = self.checker.holes.len() > first_hole
+                        || std::mem::take(&mut self.checker.group_uses_hole);
+                    for &i in &group {
+                        let decl = &program.declarations[i];
+                        if has_hole {
+                            self.checker.with_holes.insert(decl.name.to_string());
+                        } else {
+                            self.checker.environment[&decl.name]
+                                .check_valid_function_type()
+                                .map_err(|e| e.at(decl.span, vec![]))?;
+                        }
+                    }
                 }
                 Ok(())
             }
+
+            /// Adds the declaration's type to the environment, narrowed by its signature
+            fn infer_declaration(&mut self, decl: &mut Declaration) -> TypeCheckResult<()> {
+                let first_hole = self.checker.holes.len();
+                self.visit_declaration(decl)?;
+                let mut t = decl.term.t_type.as_ref().unwrap().clone();
+                if let Some(signature_t) = self.checker.signatures.get(&decl.name).cloned() {
+                    let unifier = self
+                        .checker
+                        .check_signature(&decl.name, &t, &signature_t)
+                        .map_err(|e| e.at(decl.span, vec![]))?;
+                    t = unifier.apply(&t);
+                    for hole in &mut self.checker.holes[first_hole..] {
+                        hole.e_type = unifier.apply(&hole.e_type);
+                    }
+                    self.checker.refine_shared_types(&unifier);
+                    decl.term.t_type = Some(t.clone());
+                }
+                self.checker
+                    .add_to_environment(&decl.name, t, true)
+                    .map_err(|e| e.at(decl.span, vec![]))
+            }
+
+            /// The type of a term after `expr` is applied to a term of type `t`
+            fn visit_applied_expression(
+                &mut self,
+                t: &Type,
+                expr: &mut Expression,
+            ) -> TypeCheckResult<(Type, Unifier)> {
+                if let ExpressionType::NamedTermApp(name, k) = &mut expr.expression {
+                    match name.as_ref() {
+                        _ if self.checker.environment.contains_key(name.as_str()) => {}
+                        "proc" => *k = Some(TypeChecker::proc_word_count(name, t, *k)?),
+                        "spawn" => {
+                            if TypeChecker::function_on_top(t).is_none() {
+                                return Err(TypeError::SpawnedFunctionUnknown(t.clone()));
+                            }
+                            *k = Some(TypeChecker::proc_word_count(name, t, *k)?);
+                        }
+                        "join" => *k = Some(TypeChecker::join_word_count(t)?),
+                        _ => {}
+                    }
+                }
+                self.visit_expression(expr)?;
+                let e_type = expr.e_type.as_ref().unwrap();
+                let (new_t, u) = self.checker.type_after_application(t, &e_type)?;
+                new_t.check_valid_expression_type()?;
+                Ok((new_t, u))
+            }
+
+            /// The condition is run before the guard is enabled and again before it is disabled
+            fn visit_condition(&mut self, arm: &mut AlternationArm) -> TypeCheckResult<()> {
+                let condition = match &mut arm.condition {
+                    Some(condition) => condition,
+                    None => return Ok(()),
+                };
+                self.visit_term(condition)?;
+                let condition_t = condition.t_type.clone().unwrap();
+                let s = self.checker.alloc.type_stack(StackConstraints::default());
+                let expected_t =
+                    self.checker
+                        .alloc
+                        .function_type(s.clone(), vec![], vec![Type::Boolean]);
+                let unifier = match mgu::of_types(&condition_t, &expected_t) {
+                    Ok(unifier) => unifier,
+                    Err(_) => {
+                        return Err(TypeError::GuardConditionNotBoolean(condition_t)
+                            .at(condition.span, vec![]))
+                    }
+                };
+                self.checker.refine_shared_types(&unifier);
+                // The stack that the condition reads must be the one that the arm starts with
+                let guard_t = unifier.apply(&Type::Function(Box::new(s.clone()), Box::new(s)));
+                let (a_type, _) = self
+                    .checker
+                    .type_after_application(&guard_t, arm.a_type.as_ref().unwrap())?;
+                arm.a_type = Some(a_type);
+                Ok(())
+            }
         }
 
         impl<'a> MutAstVisitor<TypeError> for Visitor<'a> {
@@ -341,14 +844,24 @@ impl TypeChecker {
                 let s = self.checker.alloc.type_stack(StackConstraints::default());
                 let mut t = Type::Function(Box::new(s.clone()), Box::new(s));
                 let mut unifier = Unifier::default();
+                // The expressions before the current one
+                let mut before: Option<Span> = None;
+                let first_hole = self.checker.holes.len();
 
                 for mut expr in &mut term.expressions {
-                    self.visit_expression(&mut expr)?;
-                    let e_type = expr.e_type.as_ref().unwrap();
-                    let (new_t, u) = self.checker.type_after_application(&t, &e_type)?;
-                    new_t.check_valid_expression_type()?;
+                    let span = expr.span;
+                    let (new_t, u) = self.visit_applied_expression(&t, &mut expr).map_err(|e| {
+                        let notes = match (before, &t) {
+                            (Some(before), Type::Function(_, o)) => {
+                                vec![Note::new(before, format!("this leaves the stack as {}", o))]
+                            }
+                            _ => vec![],
+                        };
+                        e.at(span, notes)
+                    })?;
                     t = new_t;
                     unifier.compose(u);
+                    before = Some(before.map_or(span, |before| before.to(span)));
                 }
 
                 // All expressions that appear within this term get updated with their final
@@ -360,6 +873,9 @@ impl TypeChecker {
                 for mut expr in &mut term.expressions {
                     expr.e_type = Some(unifier.apply(expr.e_type.as_ref().unwrap()));
                 }
+                for hole in &mut self.checker.holes[first_hole..] {
+                    hole.e_type = unifier.apply(&hole.e_type);
+                }
 
                 term.t_type = Some(t);
 
@@ -367,6 +883,7 @@ impl TypeChecker {
             }
 
             fn visit_expression(&mut self, expr: &mut Expression) -> TypeCheckResult<()> {
+                let span = expr.span;
                 match &mut expr.expression {
                     ExpressionType::Number(_) => {
                         let s = self.checker.alloc.type_stack(StackConstraints::default());
@@ -376,22 +893,72 @@ impl TypeChecker {
                             vec![Type::Integer],
                         ));
                     }
+                    ExpressionType::SignedNumber(_) => {
+                        let s = self.checker.alloc.type_stack(StackConstraints::default());
+                        expr.e_type = Some(self.checker.alloc.function_type(
+                            s,
+                            vec![],
+                            vec![Type::SignedInteger],
+                        ));
+                    }
                     ExpressionType::Offset(k) => {
                         expr.e_type = Some(self.checker.alloc.offset_type(*k));
                     }
+                    ExpressionType::Hole => {
+                        let i = self.checker.alloc.type_stack(StackConstraints::default());
+                        let o = self.checker.alloc.type_stack(StackConstraints::default());
+                        let e_type = Type::Function(Box::new(i), Box::new(o));
+                        self.checker.holes.push(Hole {
+                            span: expr.span,
+                            e_type: e_type.clone(),
+                        });
+                        expr.e_type = Some(e_type);
+                    }
+                    ExpressionType::Let(names, body) => {
+                        let first_hole = self.checker.holes.len();
+                        let scope_size = self.checker.locals.len();
+                        for name in names.iter() {
+                            let t = self.checker.alloc.generic_type();
+                            self.checker.locals.push((name.to_string(), t));
+                        }
+                        let result = self.visit_term(body);
+                        let bindings = self.checker.locals.split_off(scope_size);
+                        result?;
+                        let bindings = bindings.into_iter().map(|(_, t)| t).collect();
+                        let let_t = self.checker.let_type(names, body, bindings)?;
+                        // The bindings stay on the stack beneath the body, where a hole sees them
+                        if let (Some(Type::Function(body_i, _)), Type::Function(i, _)) =
+                            (&body.t_type, &let_t)
+                        {
+                            if let Ok(unifier) = mgu::of_stacks(body_i, i) {
+                                for hole in &mut self.checker.holes[first_hole..] {
+                                    hole.e_type = unifier.apply(&hole.e_type);
+                                }
+                            }
+                        }
+                        expr.e_type = Some(let_t);
+                        locals::assign_local_offsets(body, names)?;
+                    }
+                    ExpressionType::Local(name, _) => {
+                        let t = match self.checker.locals.iter().rev().find(|(n, _)| n == name) {
+                            Some((_, t)) => t.clone(),
+                            None => return Err(TypeError::UnknownName(name.to_string())),
+                        };
+                        let s = self.checker.alloc.type_stack(StackConstraints::default());
+                        expr.e_type = Some(self.checker.alloc.function_type(s, vec![], vec![t]));
+                    }
                     ExpressionType::NamedTermApp(n, k) => {
-                        let t = self
-                            .checker
-                            .get_environment_type(&n, *k)?
-                            .deep_clone(&mut self.checker.alloc);
+                        let t = self.checker.use_type(&n, *k, span)?;
                         expr.e_type = Some(t);
                     }
                     ExpressionType::NamedTermRef(n, k) => {
+                        // Their word counts are read from the stack where they are applied
+                        let is_spawn_or_join = n == "spawn" || n == "join";
+                        if is_spawn_or_join && !self.checker.environment.contains_key(n.as_str()) {
+                            return Err(TypeError::CantQuote(n.to_string()));
+                        }
                         let s = self.checker.alloc.type_stack(StackConstraints::default());
-                        let t = self
-                            .checker
-                            .get_environment_type(&n, *k)?
-                            .deep_clone(&mut self.checker.alloc);
+                        let t = self.checker.use_type(&n, *k, span)?;
                         let push_type_id = self.checker.alloc.function_type(s, vec![], vec![t]);
                         expr.e_type = Some(push_type_id);
                     }
@@ -498,7 +1065,7 @@ impl TypeChecker {
                         };
                         expr.e_type = Some(Type::Function(Box::new(s), Box::new(new_s)));
                     }
-                    ExpressionType::Alternation(arms) => {
+                    ExpressionType::Alternation(arms, _) => {
                         if arms.is_empty() {
                             return Err(TypeError::EmptyAlternationsNotAllowed);
                         } else {
@@ -506,16 +1073,144 @@ impl TypeChecker {
                             let mut t = arms[0].a_type.as_ref().unwrap().clone();
                             for arm in arms.iter_mut().skip(1) {
                                 self.visit_arm(arm)?;
-                                t = mgu::of_types(&t, arm.a_type.as_ref().unwrap())?.apply(&t);
+                                let unifier = mgu::of_types(&t, arm.a_type.as_ref().unwrap())?;
+                                self.checker.refine_shared_types(&unifier);
+                                t = unifier.apply(&t);
                             }
                             expr.e_type = Some(t);
                         }
                     }
+                    ExpressionType::Parallel(branches) => {
+                        // The branches take disjoint parts of the stack, with the first deepest
+                        let s = self.checker.alloc.type_stack(StackConstraints::default());
+                        let mut input = s.clone();
+                        let mut output = s;
+                        for branch in branches.iter_mut() {
+                            self.visit_term(branch)?;
+                            let branch_t = branch.t_type.clone().unwrap();
+                            if let Type::Function(i, o) = &branch_t {
+                                if o.get_base_stack() != i.get_base_stack() {
+                                    return Err(TypeError::ParallelBranchDoesntReturn(branch_t));
+                                }
+                                input = i.with_base(input);
+                                output = o.with_base(output);
+                            }
+                        }
+                        expr.e_type = Some(Type::Function(Box::new(input), Box::new(output)));
+                    }
+                    ExpressionType::ReplicatedParallel(n, body) => {
+                        if *n == 0 {
+                            return Err(TypeError::ReplicationZero("par".to_string()));
+                        }
+                        self.visit_term(body)?;
+                        let body_t = body.t_type.clone().unwrap();
+                        // A copy that takes nothing isn't given its index
+                        let copy_t = match &body_t {
+                            Type::Function(i, _) if i.height() == 0 => body_t.clone(),
+                            _ => {
+                                let s = self.checker.alloc.type_stack(StackConstraints::default());
+                                let index_t = self.checker.alloc.function_type(
+                                    s,
+                                    vec![],
+                                    vec![Type::Integer],
+                                );
+                                let (copy_t, _) =
+                                    self.checker.type_after_application(&index_t, &body_t)?;
+                                copy_t
+                            }
+                        };
+                        // Each copy has its own type variables, so the channels in one part of the
+                        // stack are used independently of the channels in the others
+                        let s = self.checker.alloc.type_stack(StackConstraints::default());
+                        let mut input = s.clone();
+                        let mut output = s;
+                        for copy in 0..*n {
+                            let copy_t = if copy == 0 {
+                                copy_t.clone()
+                            } else {
+                                copy_t.deep_clone(&mut self.checker.alloc)
+                            };
+                            if let Type::Function(i, o) = &copy_t {
+                                if o.get_base_stack() != i.get_base_stack() {
+                                    return Err(TypeError::ParallelBranchDoesntReturn(body_t));
+                                }
+                                input = i.with_base(input);
+                                output = o.with_base(output);
+                            }
+                        }
+                        expr.e_type = Some(Type::Function(Box::new(input), Box::new(output)));
+                    }
+                    ExpressionType::ReplicatedAlternation(n, body) => {
+                        if *n == 0 {
+                            return Err(TypeError::ReplicationZero("alt".to_string()));
+                        }
+                        if *n > MAX_ALTERNATION_CHANNELS {
+                            return Err(TypeError::AlternationTooWide(*n));
+                        }
+                        self.visit_term(body)?;
+                        let body_t = body.t_type.clone().unwrap();
+                        // The body runs once for each channel, so it must leave the words beneath
+                        // the value and index as it found them
+                        let kept = match &body_t {
+                            Type::Function(i, o)
+                                if o.get_base_stack() == i.get_base_stack()
+                                    && i.height() == o.height() + 2 =>
+                            {
+                                o.deref().clone()
+                            }
+                            _ => return Err(TypeError::AlternationBodyDoesntReturn(body_t)),
+                        };
+                        let t = self.checker.alloc.generic_type();
+                        let received = Stack::Stack(
+                            Box::new(Stack::Stack(Box::new(kept.clone()), Box::new(t.clone()))),
+                            Box::new(Type::Integer),
+                        );
+                        let receive_t = Type::Function(Box::new(kept), Box::new(received));
+                        let (round_t, unifier) =
+                            self.checker.type_after_application(&receive_t, &body_t)?;
+                        let kept = match round_t {
+                            Type::Function(i, _) => *i,
+                            _ => panic!("Type of a round of alt should be a function"),
+                        };
+                        let alt_t = self.checker.alloc.replicated_alternation_type(
+                            *n,
+                            &kept,
+                            &unifier.apply(&t),
+                        );
+                        expr.e_type = Some(alt_t);
+                    }
+                    ExpressionType::Case(arms, default) => {
+                        // Like the branches of an if, every arm must have the same type
+                        self.visit_term(default)?;
+                        let mut t = default.t_type.clone().unwrap();
+                        for arm in arms.iter_mut() {
+                            self.visit_term(&mut arm.term)?;
+                            let unifier = mgu::of_types(&t, arm.term.t_type.as_ref().unwrap())?;
+                            self.checker.refine_shared_types(&unifier);
+                            t = unifier.apply(&t);
+                        }
+                        let s = self.checker.alloc.type_stack(StackConstraints::default());
+                        let scrutinee_t =
+                            self.checker
+                                .alloc
+                                .function_type(s, vec![Type::Integer], vec![]);
+                        let (case_t, _) = self.checker.type_after_application(&scrutinee_t, &t)?;
+                        expr.e_type = Some(case_t);
+                    }
                 };
                 Ok(())
             }
 
             fn visit_arm(&mut self, arm: &mut AlternationArm) -> TypeCheckResult<()> {
+                let offset = match arm.guard {
+                    Guard::Channel(offset) => offset,
+                    Guard::Timer(_) | Guard::Else => {
+                        // Nothing is received, so the arm has the type of its term
+                        self.visit_term(&mut arm.term)?;
+                        arm.a_type = arm.term.t_type.clone();
+                        return self.visit_condition(arm);
+                    }
+                };
                 let v = self.checker.alloc.next_channel_var_counter();
                 let (t, rx, _tx) = self
                     .checker
@@ -527,7 +1222,7 @@ impl TypeChecker {
                     Box::new(t.clone()),
                 );
                 let mut channel_read_inputs = vec![rx];
-                for _ in 0..arm.offset {
+                for _ in 0..offset {
                     channel_read_inputs.push(self.checker.alloc.generic_type());
                 }
                 let mut channel_read_output = channel_read_inputs.clone();
@@ -544,16 +1239,48 @@ impl TypeChecker {
                     .checker
                     .type_after_application(&channel_read_type, &term_type)?;
                 arm.a_type = Some(a_type);
-                Ok(())
+                self.visit_condition(arm)
             }
         }
 
         let mut visitor = Visitor { checker: self };
-        visitor.visit_program_topologically(program, topo_order)?;
+        visitor.visit_program_topologically(program, groups)?;
 
         Ok(())
     }
 
+    /**
+     * Returns the unifier that narrows the inferred type to the signature. The signature must be
+     * an instance of the inferred type, so unifying them may not narrow the signature itself.
+     */
+    fn check_signature(
+        &self,
+        name: &str,
+        inferred: &Type,
+        signature: &Type,
+    ) -> TypeCheckResult<Unifier> {
+        let u = match mgu::of_types(inferred, signature) {
+            Ok(u) => u,
+            Err(e) => {
+                return Err(TypeError::SignatureMismatch(
+                    name.to_string(),
+                    Box::new(signature.clone()),
+                    Box::new(inferred.clone()),
+                    Box::new(e),
+                ))
+            }
+        };
+        if only_renames(signature, &u.apply(signature)) {
+            Ok(u)
+        } else {
+            Err(TypeError::SignatureTooGeneral(
+                name.to_string(),
+                Box::new(signature.clone()),
+                Box::new(inferred.clone()),
+            ))
+        }
+    }
+
     fn add_to_environment(
         &mut self,
         name: &str,
@@ -562,7 +1289,9 @@ impl TypeChecker {
     ) -> TypeCheckResult<()> {
         let t = if self.environment.contains_key(name) {
             let existing = self.environment[name].clone();
-            mgu::of_types(&t, &existing)?.apply(&t)
+            let unifier = mgu::of_types(&t, &existing)?;
+            self.refine_shared_types(&unifier);
+            unifier.apply(&t)
         } else {
             t
         };
@@ -579,6 +1308,14 @@ impl TypeChecker {
         self.add_to_environment(name, t, false)
     }
 
+    /// Standard library words that can be parameterised with numbers, see |get_environment_type|
+    fn is_parameterised_word(name: &str) -> bool {
+        matches!(
+            name,
+            "chan" | "chanbuf" | "proc" | "spawn" | "join" | "?" | "!" | "del"
+        )
+    }
+
     fn get_environment_type(&mut self, name: &str, k: Option<u16>) -> TypeCheckResult<Type> {
         match self.environment.get(name) {
             Some(t) => {
@@ -590,7 +1327,16 @@ impl TypeChecker {
             }
             None => match name {
                 "proc" => Ok(self.alloc.proc_type(k)),
+                "spawn" => Ok(self.alloc.spawn_type(k.unwrap_or(0))),
+                "join" => Ok(self.alloc.join_type(k.unwrap_or(0))),
                 "chan" => Ok(self.alloc.chan_type(k)),
+                "chanbuf" => match k {
+                    Some(k) if k > MAX_CHANNEL_CAPACITY => {
+                        Err(TypeError::ChannelCapacityTooLarge(k))
+                    }
+                    Some(k) if k > 0 => Ok(self.alloc.chanbuf_type()),
+                    _ => Err(TypeError::BufferedChannelWithoutCapacity),
+                },
                 "?" => Ok(self.alloc.receive_type(k.unwrap_or(0))),
                 "!" => Ok(self.alloc.send_type(k.unwrap_or(0))),
                 "del" => Ok(self.alloc.del_type(k.unwrap_or(0))),
@@ -599,6 +1345,201 @@ impl TypeChecker {
         }
     }
 
+    /// Each use of a declaration gets a fresh copy of its type
+    fn use_type(&mut self, name: &str, k: Option<u16>, span: Span) -> TypeCheckResult<Type> {
+        let t = self
+            .get_environment_type(name, k)?
+            .deep_clone(&mut self.alloc);
+        self.group_uses_hole |= self.with_holes.contains(name);
+        if self.group.iter().any(|n| n == name) {
+            self.group_uses.push(GroupUse {
+                name: name.to_string(),
+                span,
+                t: t.clone(),
+            });
+        }
+        Ok(t)
+    }
+
+    /**
+     * Unifies every use within the group being inferred with the type of the declaration that it
+     * uses, and returns the unifier that did so. Only the stack beneath what a declaration takes
+     * can differ between uses, as a declaration can use itself with more values beneath. Unifying
+     * can make a declaration take more values, so the uses are unified again until none does.
+     */
+    fn fit_group_uses(&mut self, first_hole: usize) -> TypeCheckResult<Unifier> {
+        let names = std::mem::take(&mut self.group);
+        let mut uses = std::mem::take(&mut self.group_uses);
+        let mut unifier = Unifier::default();
+        for _ in 0..MAX_RECURSION_PASSES {
+            let shapes = |checker: &Self| -> Vec<_> {
+                names
+                    .iter()
+                    .map(|name| TypeChecker::base_stack_shape(&checker.environment[name]))
+                    .collect()
+            };
+            let before = shapes(self);
+            for i in 0..uses.len() {
+                let t = self.environment[&uses[i].name].clone();
+                let t = self.with_fresh_base_stacks(&t, &uses[i].t);
+                let u = mgu::of_types(&uses[i].t, &t).map_err(|e| {
+                    TypeError::InconsistentUse(
+                        uses[i].name.to_string(),
+                        Box::new(t.clone()),
+                        Box::new(uses[i].t.clone()),
+                        Box::new(e),
+                    )
+                    .at(uses[i].span, vec![])
+                })?;
+                for name in &names {
+                    let t = u.apply(&self.environment[name]);
+                    self.environment.insert(name.to_string(), t);
+                }
+                for group_use in &mut uses {
+                    group_use.t = u.apply(&group_use.t);
+                }
+                for hole in &mut self.holes[first_hole..] {
+                    hole.e_type = u.apply(&hole.e_type);
+                }
+                unifier.compose(u);
+            }
+            let after = shapes(self);
+            if before == after {
+                return Ok(unifier);
+            }
+        }
+        // A use made a declaration take more values in the last pass, so there is one
+        Err(TypeError::UnboundedRecursion(names.join(", ")).at(uses[0].span, vec![]))
+    }
+
+    /// The stacks beneath the inputs and outputs of a function type
+    fn base_stacks(t: &Type) -> Vec<Stack> {
+        match t {
+            Type::Function(i, o) => vec![i.get_base_stack(), o.get_base_stack()],
+            _ => vec![],
+        }
+    }
+
+    /**
+     * How many values a function type takes and leaves, with which of the stacks beneath them are
+     * the same, and None for the bottom of the stack. Fitting a use depends on these, as the
+     * stacks beneath can differ between uses.
+     */
+    fn base_stack_shape(t: &Type) -> Vec<(usize, Option<usize>)> {
+        let bases = TypeChecker::base_stacks(t);
+        let heights = match t {
+            Type::Function(i, o) => vec![i.height(), o.height()],
+            _ => vec![],
+        };
+        heights
+            .into_iter()
+            .zip(&bases)
+            .map(|(height, base)| match base {
+                Stack::Generic(_, _) => (height, bases.iter().position(|b| b == base)),
+                _ => (height, None),
+            })
+            .collect()
+    }
+
+    /**
+     * A function type with new variables for the stacks beneath its inputs and outputs. If the
+     * function is used as a process then nothing can be beneath the words that it takes.
+     */
+    fn with_fresh_base_stacks(&mut self, t: &Type, used_t: &Type) -> Type {
+        let is_process = match TypeChecker::base_stacks(used_t).first() {
+            Some(Stack::Generic(_, cs)) => cs.constraints.contains(&StackConstraint::MustBeBase),
+            _ => false,
+        };
+        let mut unifier = Unifier::default();
+        let mut renamed = vec![];
+        for (i, base) in TypeChecker::base_stacks(t).into_iter().enumerate() {
+            if let Stack::Generic(n, mut cs) = base {
+                if !renamed.contains(&n) {
+                    if i == 0 && is_process {
+                        cs.insert(StackConstraint::MustBeBase);
+                    }
+                    renamed.push(n);
+                    unifier.add(UnifierStep::Stack(n, self.alloc.type_stack(cs)));
+                }
+            }
+        }
+        unifier.apply(t)
+    }
+
+    /**
+     * A process starts with the words that its function expects above the base of its stack, so
+     * the count can be read from the function on top of the stack before proc. An explicit count
+     * must agree with it, unless the function is a declaration whose type is still being inferred.
+     */
+    fn proc_word_count(name: &str, t: &Type, k: Option<u16>) -> TypeCheckResult<u16> {
+        let f = TypeChecker::function_on_top(t);
+        let inferred = match f {
+            // A declaration in the group being inferred has a placeholder type whose input isn't
+            // known yet, so proc_k narrows it instead
+            Some(Type::Function(i, o))
+                if matches!(i.deref(), Stack::Generic(_, _)) && !o.contains_stack(i) =>
+            {
+                None
+            }
+            Some(Type::Function(i, _)) => Some(i.height()),
+            _ => None,
+        };
+        match (k, inferred) {
+            (Some(k), Some(n)) if usize::from(k) != n => Err(TypeError::ProcWordCountMismatch(
+                name.to_string(),
+                k,
+                n,
+                f.unwrap().clone(),
+            )),
+            (Some(k), _) => Ok(k),
+            (None, Some(n)) => Ok(n as u16),
+            (None, None) => Err(TypeError::ProcWordCountUnknown(t.clone())),
+        }
+    }
+
+    /// join pushes the results of the function that the handle on top of the stack was spawned with
+    fn join_word_count(t: &Type) -> TypeCheckResult<u16> {
+        if let Type::Function(_, o) = t {
+            if let Stack::Stack(_, h) = o.deref() {
+                if let Type::Handle(f) = h.deref() {
+                    if let Type::Function(_, results) = f.deref() {
+                        return Ok(results.height() as u16);
+                    }
+                }
+            }
+        }
+        Err(TypeError::JoinWithoutHandle(t.clone()))
+    }
+
+    fn function_on_top(t: &Type) -> Option<&Type> {
+        let f = match t {
+            Type::Function(_, o) => match o.deref() {
+                Stack::Stack(_, f) => f.deref(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match f {
+            Type::Function(_, _) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// Refines the types of local bindings, which their uses share, of uses of the group being
+    /// inferred, which are fitted to the group's types later, and of holes, which are reported with
+    /// the stacks of the terms around them
+    fn refine_shared_types(&mut self, unifier: &Unifier) {
+        for (_, t) in &mut self.locals {
+            *t = unifier.apply(t);
+        }
+        for hole in &mut self.holes {
+            hole.e_type = unifier.apply(&hole.e_type);
+        }
+        for group_use in &mut self.group_uses {
+            group_use.t = unifier.apply(&group_use.t);
+        }
+    }
+
     fn type_after_application(
         &mut self,
         lhs: &Type,
@@ -608,6 +1549,7 @@ impl TypeChecker {
             if let Type::Function(right_input, right_output) = rhs {
                 // Done to verify consumption properties
                 let unifier = mgu::of_stacks(left_output, right_input)?;
+                self.refine_shared_types(&unifier);
                 let rhs = unifier.apply(rhs);
                 struct VisitSubTypes {}
                 impl VisitSubTypes {
@@ -642,9 +1584,10 @@ impl TypeChecker {
         }
     }
 
-    fn check_main_takes_empty_stack(&self) -> TypeCheckResult<()> {
+    fn check_main_takes_empty_stack(&self, program: &Program) -> TypeCheckResult<()> {
         let t = match self.environment.get("main") {
             Some(t) => t.clone(),
+            None if self.unparsed.contains("main") => return Ok(()),
             None => return Err(TypeError::UndefinedMain),
         };
         if let Type::Function(i, _) = &t {
@@ -652,7 +1595,11 @@ impl TypeChecker {
                 return Ok(());
             }
         }
-        Err(TypeError::BadMain(t))
+        let error = TypeError::BadMain(t);
+        match program.declarations.iter().find(|decl| decl.name == "main") {
+            Some(main) => Err(error.at(main.span, vec![])),
+            None => Err(error),
+        }
     }
 
     fn check_inferred_types_match(&mut self, program: &Program) -> TypeCheckResult<()> {
@@ -661,11 +1608,26 @@ impl TypeChecker {
         }
 
         impl<'a> Visitor<'a> {
-            fn check_types(&mut self, actual: &Type, inferred: &Type) -> TypeCheckResult<()> {
+            /// Checks that the type of a use of `name` fits the type that `name` was given
+            fn check_types(
+                &mut self,
+                name: &str,
+                span: Span,
+                actual: &Type,
+                inferred: &Type,
+            ) -> TypeCheckResult<()> {
                 // Deep clone in case of recursive function
                 let inferred = inferred.deep_clone(&mut self.checker.alloc);
-                let _u = mgu::of_types(actual, &inferred);
-                Ok(())
+                match mgu::of_types(actual, &inferred) {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(TypeError::InconsistentUse(
+                        name.to_string(),
+                        Box::new(actual.clone()),
+                        Box::new(inferred),
+                        Box::new(e),
+                    )
+                    .at(span, vec![])),
+                }
             }
         }
 
@@ -673,14 +1635,22 @@ impl TypeChecker {
             fn visit_expression(&mut self, expr: &Expression) -> TypeCheckResult<()> {
                 use ExpressionType::*;
                 match &expr.expression {
-                    Alternation(arms) => self.visit_alternation(arms),
+                    Alternation(arms, _) => self.visit_alternation(arms),
                     AnonymousTerm(term) => self.visit_anonymous_term(term),
                     If(c, t, f) => self.visit_if(c, t, f),
                     While(c, b) => self.visit_while(c, b),
                     Forever(b) => self.visit_forever(b),
                     Repeat(k, b) => self.visit_repeat(*k, b),
                     Number(n) => self.visit_number(*n),
+                    SignedNumber(n) => self.visit_signed_number(*n),
                     Offset(o) => self.visit_offset(*o),
+                    Let(names, body) => self.visit_let(names, body),
+                    Local(n, _) => self.visit_local(n),
+                    Case(arms, default) => self.visit_case(arms, default),
+                    Parallel(branches) => self.visit_parallel(branches),
+                    ReplicatedParallel(n, b) => self.visit_replicated_parallel(*n, b),
+                    ReplicatedAlternation(n, b) => self.visit_replicated_alternation(*n, b),
+                    Hole => self.visit_hole(),
                     NamedTermApp(n, _) => {
                         let t_clone = if let Some(t) = self.checker.environment.get(n) {
                             t.clone()
@@ -688,7 +1658,7 @@ impl TypeChecker {
                             // Ignore standard library functions
                             return Ok(());
                         };
-                        self.check_types(&t_clone, expr.e_type.as_ref().unwrap())
+                        self.check_types(n, expr.span, &t_clone, expr.e_type.as_ref().unwrap())
                     }
                     NamedTermRef(n, _) => {
                         let t_clone = if let Some(t) = self.checker.environment.get(n) {
@@ -706,7 +1676,7 @@ impl TypeChecker {
                         } else {
                             panic!("Expression not a function");
                         };
-                        self.check_types(&t_clone, &inferred)
+                        self.check_types(n, expr.span, &t_clone, &inferred)
                     }
                 }
             }
@@ -14,6 +14,9 @@ pub enum Constraint {
     Duplicable,
     MustConsume,
     IntLike,
+    Numeric,
+    /// An end of a shared channel, which is counted until it is released
+    Releasable,
 }
 
 impl fmt::Display for Constraint {
@@ -24,6 +27,8 @@ impl fmt::Display for Constraint {
             Duplicable => write!(f, "Duplicable"),
             MustConsume => write!(f, "MustConsume"),
             IntLike => write!(f, "IntLike"),
+            Numeric => write!(f, "Numeric"),
+            Releasable => write!(f, "Releasable"),
         }
     }
 }
@@ -0,0 +1,35 @@
+use super::super::diagnostic::{Diagnostic, Severity};
+use super::super::lexer::Span;
+use super::type_fmt::{fmt_part, Name};
+use super::Type;
+use std::fmt;
+
+/// A `_?` that the type checker found, which is reported rather than treated as an error
+#[derive(Debug)]
+pub struct Hole {
+    pub span: Span,
+    /// From the stack at the hole to the stack that the rest of the definition needs after it
+    pub e_type: Type,
+}
+
+impl Hole {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new(self.to_string(), Some(self.span));
+        diagnostic.severity = Severity::Info;
+        diagnostic
+    }
+}
+
+impl fmt::Display for Hole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.e_type {
+            Type::Function(i, o) => {
+                write!(f, "the stack here is ")?;
+                fmt_part(f, &self.e_type, i.as_ref(), Name::Greek)?;
+                write!(f, ", and the rest of the definition needs ")?;
+                fmt_part(f, &self.e_type, o.as_ref(), Name::Greek)
+            }
+            t => write!(f, "hole of type {}", t),
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use super::super::ast::{ExpressionType, Guard, Term};
+use super::{mgu, Constraint, Stack, StackConstraints, Type, TypeCheckResult, TypeChecker};
+use super::{TypeError, Unifier};
+
+use std::ops::Deref;
+
+/**
+ * The values bound by a let stay on the stack, beneath everything that the body of the let pushes.
+ * The body may therefore only push values, and each use of a name reads the value from a fixed
+ * offset that depends on how many values are above it at that point.
+ */
+impl TypeChecker {
+    /// Returns the type of `let names in (body)`, given the body has already been checked.
+    pub(super) fn let_type(
+        &mut self,
+        names: &[String],
+        body: &Term,
+        bindings: Vec<Type>,
+    ) -> TypeCheckResult<Type> {
+        let body_t = body.t_type.clone().unwrap();
+        let (i, o) = match &body_t {
+            Type::Function(i, o) => (i.deref(), o.deref()),
+            _ => return Err(TypeError::NotAFunction(body_t)),
+        };
+        if let Stack::Stack(_, _) = i {
+            return Err(TypeError::LetBodyConsumesStack(body_t));
+        }
+
+        // Bindings are affine: one that can't be duplicated may be used at most once, and one that
+        // can't be dropped must be used
+        let mut unifier = Unifier::default();
+        for (name, t) in names.iter().zip(&bindings) {
+            let t = unifier.apply(t);
+            let (min_uses, max_uses) = count_uses(body, name);
+            if max_uses > 1 {
+                let duplicable = self
+                    .alloc
+                    .generic_type_with_constraints(vec![Constraint::Duplicable]);
+                match mgu::of_types(&t, &duplicable) {
+                    Ok(u) => unifier.compose(u),
+                    Err(_) => return Err(TypeError::LocalNotDuplicable(name.to_string(), t)),
+                }
+            }
+            if min_uses == 0 {
+                let t = unifier.apply(&t);
+                let droppable = self
+                    .alloc
+                    .generic_type_with_constraints(vec![Constraint::Droppable]);
+                match mgu::of_types(&t, &droppable) {
+                    Ok(u) => unifier.compose(u),
+                    Err(_) => return Err(TypeError::LocalNotDroppable(name.to_string(), t)),
+                }
+            }
+        }
+
+        let s = self.alloc.type_stack(StackConstraints::default());
+        unifier.compose(mgu::of_stacks(i, &s)?);
+        let mut input = s;
+        for t in bindings {
+            input = Stack::Stack(Box::new(input), Box::new(t));
+        }
+        let t = Type::Function(Box::new(input), Box::new(o.clone()));
+        Ok(unifier.apply(&t))
+    }
+}
+
+/**
+ * Sets the offset of each use of `names` in the body of a let. `names` are in the order they were
+ * bound, so the last name is nearest the top of the stack.
+ */
+pub fn assign_local_offsets(body: &mut Term, names: &[String]) -> TypeCheckResult<()> {
+    let names: Vec<&str> = names.iter().map(|n| n.as_ref()).collect();
+    assign_term_offsets(body, &names, Context::Depth(0))
+}
+
+#[derive(Clone, Copy)]
+enum Context {
+    /// The number of values above the bindings when the term starts
+    Depth(isize),
+    Quoted,
+    UnbalancedRepeat,
+}
+
+fn assign_term_offsets(term: &mut Term, names: &[&str], context: Context) -> TypeCheckResult<()> {
+    let term_height = match &term.t_type {
+        Some(Type::Function(i, _)) => i.height() as isize,
+        _ => 0,
+    };
+    for expr in &mut term.expressions {
+        // Expressions are annotated with types that share the stack variable of their term
+        let context = match (context, &expr.e_type) {
+            (Context::Depth(d), Some(Type::Function(i, _))) => {
+                Context::Depth(d + i.height() as isize - term_height)
+            }
+            _ => context,
+        };
+        match &mut expr.expression {
+            ExpressionType::Local(name, offset) => {
+                if let Some(i) = names.iter().rposition(|n| n == name) {
+                    match context {
+                        Context::Depth(d) => {
+                            let below = (names.len() - 1 - i) as isize;
+                            *offset = Some((d + below) as u16);
+                        }
+                        Context::Quoted => return Err(TypeError::CapturedLocal(name.to_string())),
+                        Context::UnbalancedRepeat => {
+                            return Err(TypeError::LocalInUnbalancedRepeat(name.to_string()))
+                        }
+                    }
+                }
+            }
+            ExpressionType::Let(inner_names, body) => {
+                // Inner bindings shadow outer ones with the same name
+                let shadowed: Vec<&str> = names
+                    .iter()
+                    .map(|n| {
+                        if inner_names.iter().any(|m| m == n) {
+                            ""
+                        } else {
+                            n
+                        }
+                    })
+                    .collect();
+                // The inner bindings are already counted, since they were on the stack before the let
+                assign_term_offsets(body, &shadowed, context)?;
+            }
+            ExpressionType::AnonymousTerm(t) => {
+                assign_term_offsets(t, names, Context::Quoted)?;
+            }
+            // Each branch runs in a process of its own, which can't see the parent's stack
+            ExpressionType::Parallel(branches) => {
+                for branch in branches {
+                    assign_term_offsets(branch, names, Context::Quoted)?;
+                }
+            }
+            ExpressionType::ReplicatedParallel(_, body) => {
+                assign_term_offsets(body, names, Context::Quoted)?;
+            }
+            // The body starts with the received value and the channel's index on top of the stack,
+            // and the mask of channels already received from beneath the words it keeps
+            ExpressionType::ReplicatedAlternation(_, body) => {
+                let context = match context {
+                    Context::Depth(d) => Context::Depth(d + 3),
+                    _ => context,
+                };
+                assign_term_offsets(body, names, context)?;
+            }
+            ExpressionType::If(c, t, f) => {
+                assign_term_offsets(c, names, context)?;
+                // The branches start once the condition has been popped
+                let context = match context {
+                    Context::Depth(d) => Context::Depth(d + net_effect(c) - 1),
+                    _ => context,
+                };
+                assign_term_offsets(t, names, context)?;
+                assign_term_offsets(f, names, context)?;
+            }
+            ExpressionType::While(c, b) => {
+                assign_term_offsets(c, names, context)?;
+                assign_term_offsets(b, names, context)?;
+            }
+            ExpressionType::Forever(b) => {
+                assign_term_offsets(b, names, context)?;
+            }
+            ExpressionType::Repeat(_, b) => {
+                // The loop counter is kept on top of the stack
+                let context = match context {
+                    Context::Depth(d) if net_effect(b) == 0 => Context::Depth(d + 1),
+                    Context::Depth(_) => Context::UnbalancedRepeat,
+                    _ => context,
+                };
+                assign_term_offsets(b, names, context)?;
+            }
+            ExpressionType::Alternation(arms, _) => {
+                for arm in arms {
+                    if let Some(condition) = &mut arm.condition {
+                        assign_term_offsets(condition, names, context)?;
+                    }
+                    // Arms guarded by a channel start with the received value on top of the stack
+                    let context = match (context, arm.guard) {
+                        (Context::Depth(d), Guard::Channel(_)) => Context::Depth(d + 1),
+                        _ => context,
+                    };
+                    assign_term_offsets(&mut arm.term, names, context)?;
+                }
+            }
+            ExpressionType::Case(arms, default) => {
+                // The arms start once the value being matched has been popped
+                let context = match context {
+                    Context::Depth(d) => Context::Depth(d - 1),
+                    _ => context,
+                };
+                for arm in arms {
+                    assign_term_offsets(&mut arm.term, names, context)?;
+                }
+                assign_term_offsets(default, names, context)?;
+            }
+            ExpressionType::Number(_)
+            | ExpressionType::SignedNumber(_)
+            | ExpressionType::Offset(_)
+            | ExpressionType::NamedTermApp(_, _)
+            | ExpressionType::NamedTermRef(_, _)
+            | ExpressionType::Hole => {}
+        }
+    }
+    Ok(())
+}
+
+/// The number of values that the term adds to the stack
+fn net_effect(term: &Term) -> isize {
+    match &term.t_type {
+        Some(Type::Function(i, o)) => o.height() as isize - i.height() as isize,
+        _ => 0,
+    }
+}
+
+/**
+ * The fewest and most times that `name` may be used on any path through the term. Uses within
+ * loops count as many uses.
+ */
+fn count_uses(term: &Term, name: &str) -> (usize, usize) {
+    let mut min = 0;
+    let mut max = 0;
+    for expr in &term.expressions {
+        let (expr_min, expr_max) = match &expr.expression {
+            ExpressionType::Local(n, _) if n == name => (1, 1),
+            ExpressionType::Let(names, _) if names.iter().any(|n| n == name) => (0, 0),
+            ExpressionType::Let(_, body) | ExpressionType::AnonymousTerm(body) => {
+                count_uses(body, name)
+            }
+            ExpressionType::If(c, t, f) => {
+                let (c_min, c_max) = count_uses(c, name);
+                let (t_min, t_max) = count_uses(t, name);
+                let (f_min, f_max) = count_uses(f, name);
+                (c_min + t_min.min(f_min), c_max + t_max.max(f_max))
+            }
+            ExpressionType::While(c, b) => {
+                let (c_min, c_max) = count_uses(c, name);
+                let (_, b_max) = count_uses(b, name);
+                (c_min, if c_max + b_max > 0 { 2 } else { 0 })
+            }
+            ExpressionType::Forever(b)
+            | ExpressionType::Repeat(_, b)
+            | ExpressionType::ReplicatedAlternation(_, b) => {
+                let (b_min, b_max) = count_uses(b, name);
+                (b_min, if b_max > 0 { 2 } else { 0 })
+            }
+            ExpressionType::Alternation(arms, _) => {
+                let uses: Vec<(usize, usize)> = arms
+                    .iter()
+                    .map(|arm| {
+                        let (min, max) = count_uses(&arm.term, name);
+                        match &arm.condition {
+                            // Conditions are run both when enabling and disabling the guard
+                            Some(condition) => {
+                                let (c_min, c_max) = count_uses(condition, name);
+                                (min + c_min, if c_max > 0 { 2 } else { max })
+                            }
+                            None => (min, max),
+                        }
+                    })
+                    .collect();
+                (
+                    uses.iter().map(|u| u.0).min().unwrap_or(0),
+                    uses.iter().map(|u| u.1).max().unwrap_or(0),
+                )
+            }
+            ExpressionType::Case(arms, default) => {
+                let uses: Vec<(usize, usize)> = arms
+                    .iter()
+                    .map(|arm| count_uses(&arm.term, name))
+                    .chain(std::iter::once(count_uses(default, name)))
+                    .collect();
+                (
+                    uses.iter().map(|u| u.0).min().unwrap_or(0),
+                    uses.iter().map(|u| u.1).max().unwrap_or(0),
+                )
+            }
+            _ => (0, 0),
+        };
+        min += expr_min;
+        max = (max + expr_max).min(2);
+    }
+    (min, max)
+}
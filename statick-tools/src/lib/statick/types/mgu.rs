@@ -20,7 +20,10 @@ fn visit_types(a: &Type, b: &Type, mut unifier: Unifier) -> TypeCheckResult<Unif
             if d1 == d2 {
                 visit_types(c_a, c_b, unifier)
             } else {
-                Err(TypeError::NonUnifiableTypes(a.clone(), b.clone()))
+                Err(TypeError::NonUnifiableTypes(
+                    Box::new(a.clone()),
+                    Box::new(b.clone()),
+                ))
             }
         }
         (Type::Function(i_a, o_a), Type::Function(i_b, o_b)) => {
@@ -30,6 +33,7 @@ fn visit_types(a: &Type, b: &Type, mut unifier: Unifier) -> TypeCheckResult<Unif
             unifier.compose(new_unifier);
             visit_stacks(&i_a, &i_b, unifier)
         }
+        (Type::Handle(f_a), Type::Handle(f_b)) => visit_types(f_a, f_b, unifier),
         (Type::Generic(n, cs), _) => {
             if !b.contains(a) {
                 let step = match b {
@@ -47,6 +51,18 @@ fn visit_types(a: &Type, b: &Type, mut unifier: Unifier) -> TypeCheckResult<Unif
                             }
                         }
 
+                        // A channel whose uses aren't known yet may be an end of a shared channel
+                        if missing.contains(&Constraint::Releasable) {
+                            if let Type::Channel(ChannelUse::Variable(v, _), d, t) = b {
+                                let shared = Type::Channel(ChannelUse::Shared, *d, t.clone());
+                                if missing.iter().all(|c| shared.has_constraint(*c)) {
+                                    unifier.add(UnifierStep::Channel(*v, ChannelUse::Shared));
+                                    unifier.add(UnifierStep::Type(*n, shared));
+                                    return Ok(unifier);
+                                }
+                            }
+                        }
+
                         if missing.len() == 1 && missing.contains(&Constraint::Droppable) {
                             if let Type::Channel(ChannelUse::Variable(n, o), _, _) = b {
                                 if *o == 0 {
@@ -55,16 +71,16 @@ fn visit_types(a: &Type, b: &Type, mut unifier: Unifier) -> TypeCheckResult<Unif
                                 } else {
                                     let constraints = ConstraintSet::new(missing);
                                     return Err(TypeError::MissingConstraints(
-                                        a.clone(),
-                                        b.clone(),
+                                        Box::new(a.clone()),
+                                        Box::new(b.clone()),
                                         constraints,
                                     ));
                                 }
                             } else {
                                 let constraints = ConstraintSet::new(missing);
                                 return Err(TypeError::MissingConstraints(
-                                    a.clone(),
-                                    b.clone(),
+                                    Box::new(a.clone()),
+                                    Box::new(b.clone()),
                                     constraints,
                                 ));
                             }
@@ -75,8 +91,8 @@ fn visit_types(a: &Type, b: &Type, mut unifier: Unifier) -> TypeCheckResult<Unif
                         } else {
                             let constraints = ConstraintSet::new(missing);
                             return Err(TypeError::MissingConstraints(
-                                a.clone(),
-                                b.clone(),
+                                Box::new(a.clone()),
+                                Box::new(b.clone()),
                                 constraints,
                             ));
                         }
@@ -87,7 +103,10 @@ fn visit_types(a: &Type, b: &Type, mut unifier: Unifier) -> TypeCheckResult<Unif
             } else if a == b {
                 Ok(unifier)
             } else {
-                Err(TypeError::NonUnifiableTypes(a.clone(), b.clone()))
+                Err(TypeError::NonUnifiableTypes(
+                    Box::new(a.clone()),
+                    Box::new(b.clone()),
+                ))
             }
         }
         (_, Type::Generic(_, _)) => {
@@ -98,7 +117,10 @@ fn visit_types(a: &Type, b: &Type, mut unifier: Unifier) -> TypeCheckResult<Unif
             if a == b {
                 Ok(unifier)
             } else {
-                Err(TypeError::NonUnifiableTypes(a.clone(), b.clone()))
+                Err(TypeError::NonUnifiableTypes(
+                    Box::new(a.clone()),
+                    Box::new(b.clone()),
+                ))
             }
         }
     }
@@ -117,7 +139,10 @@ fn visit_stacks(a: &Stack, b: &Stack, mut unifier: Unifier) -> TypeCheckResult<U
                 }
                 if let Stack::Bottom = b {
                     if !cs.constraints.contains(&StackConstraint::AllowBottom) {
-                        return Err(TypeError::BottomNotAllowed(a.clone(), b.clone()));
+                        return Err(TypeError::BottomNotAllowed(
+                            Box::new(a.clone()),
+                            Box::new(b.clone()),
+                        ));
                     }
                     // Doesn't matter if there are missing constraints; we can just union the
                     // constraints.
@@ -126,8 +151,8 @@ fn visit_stacks(a: &Stack, b: &Stack, mut unifier: Unifier) -> TypeCheckResult<U
                     }
                 } else if !missing.is_empty() {
                     return Err(TypeError::MissingStackConstraints(
-                        a.clone(),
-                        b.clone(),
+                        Box::new(a.clone()),
+                        Box::new(b.clone()),
                         missing,
                     ));
                 }
@@ -138,7 +163,10 @@ fn visit_stacks(a: &Stack, b: &Stack, mut unifier: Unifier) -> TypeCheckResult<U
             } else if a == b {
                 Ok(unifier)
             } else {
-                Err(TypeError::NonUnifiableStacks(a.clone(), b.clone()))
+                Err(TypeError::NonUnifiableStacks(
+                    Box::new(a.clone()),
+                    Box::new(b.clone()),
+                ))
             }
         }
         (_, Stack::Generic(_, _)) => visit_stacks(b, a, unifier),
@@ -150,9 +178,10 @@ fn visit_stacks(a: &Stack, b: &Stack, mut unifier: Unifier) -> TypeCheckResult<U
             visit_stacks(&b_a, &b_b, unifier)
         }
         (Stack::Bottom, Stack::Bottom) => Ok(unifier),
-        (Stack::Bottom, _) | (_, Stack::Bottom) => {
-            Err(TypeError::NonUnifiableStacks(a.clone(), b.clone()))
-        }
+        (Stack::Bottom, _) | (_, Stack::Bottom) => Err(TypeError::NonUnifiableStacks(
+            Box::new(a.clone()),
+            Box::new(b.clone()),
+        )),
     }
 }
 
@@ -177,6 +206,7 @@ fn visit_channel_use(
         (ChannelUse::Variable(n, o), _) => {
             let repl = match b {
                 ChannelUse::Infinity => ChannelUse::Infinity,
+                ChannelUse::Shared => ChannelUse::Shared,
                 ChannelUse::Constant(k) => {
                     if *k >= *o {
                         ChannelUse::Constant(*k - *o)
@@ -197,8 +227,8 @@ fn visit_channel_use(
         }
         (_, ChannelUse::Variable(_, _)) => visit_channel_use(b, a, unifier),
         (ChannelUse::Infinity, ChannelUse::Constant(_))
-        | (ChannelUse::Constant(_), ChannelUse::Infinity) => {
-            Err(TypeError::NonUnifiableChannelUses(*a, *b))
-        }
+        | (ChannelUse::Constant(_), ChannelUse::Infinity)
+        | (ChannelUse::Shared, _)
+        | (_, ChannelUse::Shared) => Err(TypeError::NonUnifiableChannelUses(*a, *b)),
     }
 }
@@ -0,0 +1,73 @@
+/**
+ * Splits a graph into its strongly connected components with Tarjan's algorithm, where `edges[v]`
+ * lists the vertices that v has an edge to. A component is listed after every component that it
+ * has an edge to, and the vertices of each component are in ascending order.
+ */
+pub fn strongly_connected_components(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut tarjan = Tarjan {
+        edges,
+        index: vec![None; edges.len()],
+        low_link: vec![0; edges.len()],
+        on_stack: vec![false; edges.len()],
+        stack: vec![],
+        next_index: 0,
+        components: vec![],
+    };
+    for v in 0..edges.len() {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.components
+}
+
+struct Tarjan<'a> {
+    edges: &'a [Vec<usize>],
+    /// The order in which each vertex was first visited
+    index: Vec<Option<usize>>,
+    /// The earliest visited vertex on the stack that each vertex can reach
+    low_link: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_index: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, v: usize) {
+        self.index[v] = Some(self.next_index);
+        self.low_link[v] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+
+        let edges = self.edges;
+        for &w in &edges[v] {
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.low_link[v] = self.low_link[v].min(self.low_link[w]);
+                }
+                Some(index) if self.on_stack[w] => {
+                    self.low_link[v] = self.low_link[v].min(index);
+                }
+                Some(_) => {}
+            }
+        }
+
+        // v is the first vertex of its component to be visited, so the rest are above it
+        if self.index[v] == Some(self.low_link[v]) {
+            let mut component = vec![];
+            loop {
+                let w = self.stack.pop().unwrap();
+                self.on_stack[w] = false;
+                component.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            component.sort_unstable();
+            self.components.push(component);
+        }
+    }
+}
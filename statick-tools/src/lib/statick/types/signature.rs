@@ -0,0 +1,157 @@
+use super::super::ast::{SignatureChannelUse, SignatureStack, SignatureType};
+use super::{ChannelUse, ChannelVariable, Stack, StackConstraints, Type, TypeAllocator};
+
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/**
+ * Converts a user-written signature in to a type. Each distinct name in the signature is given a
+ * fresh type, stack or channel variable, and uses of the same name refer to the same variable.
+ * None of the variables have constraints; these are added when the signature is unified with the
+ * inferred type.
+ */
+pub fn elaborate_signature(signature: &SignatureType, alloc: &mut TypeAllocator) -> Type {
+    SignatureElaborator {
+        alloc,
+        generics: HashMap::new(),
+        stacks: HashMap::new(),
+        channel_variables: HashMap::new(),
+    }
+    .elaborate_type(signature)
+}
+
+struct SignatureElaborator<'a> {
+    alloc: &'a mut TypeAllocator,
+    generics: HashMap<String, Type>,
+    stacks: HashMap<String, Stack>,
+    channel_variables: HashMap<String, ChannelVariable>,
+}
+
+impl<'a> SignatureElaborator<'a> {
+    fn elaborate_type(&mut self, t: &SignatureType) -> Type {
+        match t {
+            SignatureType::Integer => Type::Integer,
+            SignatureType::SignedInteger => Type::SignedInteger,
+            SignatureType::Boolean => Type::Boolean,
+            SignatureType::Generic(name) => {
+                if !self.generics.contains_key(name) {
+                    let g = self.alloc.generic_type();
+                    self.generics.insert(name.to_string(), g);
+                }
+                self.generics[name].clone()
+            }
+            SignatureType::Channel(chan_use, direction, c) => {
+                let chan_use = self.elaborate_channel_use(chan_use);
+                let c = self.elaborate_type(c);
+                Type::Channel(chan_use, *direction, Box::new(c))
+            }
+            SignatureType::Function(i, o) => {
+                let i = self.elaborate_stack(i);
+                let o = self.elaborate_stack(o);
+                Type::Function(Box::new(i), Box::new(o))
+            }
+        }
+    }
+
+    fn elaborate_stack(&mut self, stack: &SignatureStack) -> Stack {
+        match stack {
+            SignatureStack::Bottom => Stack::Bottom,
+            SignatureStack::Stack(name, types) => {
+                if !self.stacks.contains_key(name) {
+                    let s = self.alloc.type_stack(StackConstraints::default());
+                    self.stacks.insert(name.to_string(), s);
+                }
+                let mut stack = self.stacks[name].clone();
+                for t in types {
+                    let t = self.elaborate_type(t);
+                    stack = Stack::Stack(Box::new(stack), Box::new(t));
+                }
+                stack
+            }
+        }
+    }
+
+    fn elaborate_channel_use(&mut self, chan_use: &SignatureChannelUse) -> ChannelUse {
+        match chan_use {
+            SignatureChannelUse::Infinity => ChannelUse::Infinity,
+            SignatureChannelUse::Shared => ChannelUse::Shared,
+            SignatureChannelUse::Constant(k) => ChannelUse::Constant(*k),
+            SignatureChannelUse::Variable(name, offset) => {
+                if !self.channel_variables.contains_key(name) {
+                    let v = self.alloc.next_channel_var_counter();
+                    self.channel_variables.insert(name.to_string(), v);
+                }
+                ChannelUse::Variable(self.channel_variables[name], *offset)
+            }
+        }
+    }
+}
+
+/**
+ * True if `t` is the signature with each of its variables replaced by a different variable, so the
+ * type that the signature was unified with is at least as general as the signature. Constraints
+ * may be added to the variables, as a signature can't give them.
+ */
+pub fn only_renames(signature: &Type, t: &Type) -> bool {
+    Renaming::default().types(signature, t)
+}
+
+#[derive(Default)]
+struct Renaming {
+    generics: HashMap<usize, usize>,
+    stacks: HashMap<usize, usize>,
+    channel_variables: HashMap<ChannelVariable, ChannelVariable>,
+}
+
+impl Renaming {
+    /// Renames `from` to `to` unless either has already been renamed differently
+    fn rename(names: &mut HashMap<usize, usize>, from: usize, to: usize) -> bool {
+        match names.get(&from) {
+            Some(existing) => *existing == to,
+            None if names.values().any(|v| *v == to) => false,
+            None => {
+                names.insert(from, to);
+                true
+            }
+        }
+    }
+
+    fn types(&mut self, a: &Type, b: &Type) -> bool {
+        match (a, b) {
+            (Type::Generic(n, _), Type::Generic(m, _)) => {
+                Renaming::rename(&mut self.generics, *n, *m)
+            }
+            (Type::Channel(u_a, d_a, c_a), Type::Channel(u_b, d_b, c_b)) => {
+                d_a == d_b && self.channel_uses(u_a, u_b) && self.types(c_a, c_b)
+            }
+            (Type::Function(i_a, o_a), Type::Function(i_b, o_b)) => {
+                self.stacks(i_a, i_b) && self.stacks(o_a, o_b)
+            }
+            (Type::Handle(f_a), Type::Handle(f_b)) => self.types(f_a, f_b),
+            (Type::Generic(_, _), _) | (_, Type::Generic(_, _)) => false,
+            _ => a == b,
+        }
+    }
+
+    fn stacks(&mut self, a: &Stack, b: &Stack) -> bool {
+        match (a, b) {
+            (Stack::Generic(n, _), Stack::Generic(m, _)) => {
+                Renaming::rename(&mut self.stacks, *n, *m)
+            }
+            (Stack::Stack(s_a, t_a), Stack::Stack(s_b, t_b)) => {
+                self.stacks(s_a.deref(), s_b.deref()) && self.types(t_a, t_b)
+            }
+            (Stack::Bottom, Stack::Bottom) => true,
+            _ => false,
+        }
+    }
+
+    fn channel_uses(&mut self, a: &ChannelUse, b: &ChannelUse) -> bool {
+        match (a, b) {
+            (ChannelUse::Variable(v, o_a), ChannelUse::Variable(w, o_b)) => {
+                o_a == o_b && Renaming::rename(&mut self.channel_variables, *v, *w)
+            }
+            _ => a == b,
+        }
+    }
+}
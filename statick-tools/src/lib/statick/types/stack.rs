@@ -91,6 +91,22 @@ impl Stack {
         }
     }
 
+    /// The number of types above the base of the stack
+    pub fn height(&self) -> usize {
+        match self {
+            Stack::Stack(s, _) => s.height() + 1,
+            Stack::Generic(_, _) | Stack::Bottom => 0,
+        }
+    }
+
+    /// This stack with its base replaced by `base`
+    pub fn with_base(&self, base: Stack) -> Stack {
+        match self {
+            Stack::Stack(s, t) => Stack::Stack(Box::new(s.with_base(base)), t.clone()),
+            Stack::Generic(_, _) | Stack::Bottom => base,
+        }
+    }
+
     pub fn get_base_stack(&self) -> Stack {
         let mut s = self.clone();
         while let Stack::Stack(new_s, _) = s {
@@ -132,8 +148,8 @@ impl Stack {
             Ok(())
         } else {
             Err(TypeError::ConsumedTypesWerentConsumed(
-                self.clone(),
-                right.clone(),
+                Box::new(self.clone()),
+                Box::new(right.clone()),
                 visitor.unconsumed_types,
             ))
         }
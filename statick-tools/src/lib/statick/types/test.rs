@@ -1,6 +1,6 @@
 use std::ops::Deref;
 
-use super::super::ast::Program;
+use super::super::ast::{ExpressionType, Program};
 use super::super::lexer::lex;
 use super::super::parser::parse;
 use super::{
@@ -28,7 +28,7 @@ fn duplicated_names_produces_error() {
         "main = swap
         main = dup",
     );
-    let res = type_check(&mut program);
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
     assert_eq!(
         res.unwrap_err(),
         TypeError::DuplicateName("main".to_string())
@@ -38,7 +38,7 @@ fn duplicated_names_produces_error() {
 #[test]
 fn undefined_name_produces_error() {
     let mut program = lex_and_parse("main = something");
-    let res = type_check(&mut program);
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
     assert_eq!(
         res.unwrap_err(),
         TypeError::UnknownName("something".to_string())
@@ -69,6 +69,89 @@ fn addition_type_checks() -> TypeCheckResult<()> {
     type_check(&mut program)
 }
 
+#[test]
+fn signed_arithmetic_type_checks() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse("main = -1 2 toSint + neg -3 s< 1 2 + drop drop");
+    type_check(&mut program)
+}
+
+#[test]
+fn signed_and_unsigned_dont_mix() {
+    let mut program = lex_and_parse("main = -1 2 +");
+    assert!(type_check(&mut program).is_err());
+    let mut program = lex_and_parse("main = -1 2 toSint <");
+    assert!(type_check(&mut program).is_err());
+    let mut program = lex_and_parse("main = 1 2 s<");
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn let_type_checks() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse("main = 1 2 let fst snd in (fst snd + fst -) drop");
+    type_check(&mut program)?;
+    let mut program = lex_and_parse("main = chan_1 (1 ! drop) proc_1 let rx in (rx ? drop del)");
+    type_check(&mut program)
+}
+
+#[test]
+fn let_channel_binding_is_affine() {
+    let mut program =
+        lex_and_parse("main = chan_1 (1 ! drop) proc_1 let rx in (rx ? drop del rx ? drop del)");
+    match type_check(&mut program).map_err(TypeError::into_cause) {
+        Err(TypeError::LocalNotDuplicable(name, _)) => assert_eq!(name, "rx"),
+        r => panic!("Expected LocalNotDuplicable, got {:?}", r),
+    }
+    // The binding must be droppable, which fails once it is unified with the channel
+    let mut program = lex_and_parse("main = chan_1 (1 ! drop) proc_1 let rx in ()");
+    assert!(type_check(&mut program).is_err());
+    // Using the channel in both branches is a single use on each path
+    let mut program = lex_and_parse(
+        "main = chan_1 (1 ! drop) proc_1 let rx in (if (true) then (rx ? drop del) else (rx ? drop del))",
+    );
+    assert!(type_check(&mut program).is_ok());
+}
+
+#[test]
+fn let_body_cant_consume_outer_stack() {
+    let mut program = lex_and_parse("main = 1 2 let val in (+)");
+    match type_check(&mut program).map_err(TypeError::into_cause) {
+        Err(TypeError::LetBodyConsumesStack(_)) => {}
+        r => panic!("Expected LetBodyConsumesStack, got {:?}", r),
+    }
+}
+
+#[test]
+fn let_binding_cant_be_captured() {
+    let mut program = lex_and_parse("main = 1 let val in ((val) apply)");
+    match type_check(&mut program).map_err(TypeError::into_cause) {
+        Err(TypeError::CapturedLocal(name)) => assert_eq!(name, "val"),
+        r => panic!("Expected CapturedLocal, got {:?}", r),
+    }
+}
+
+#[test]
+fn case_type_checks() -> TypeCheckResult<()> {
+    let mut program =
+        lex_and_parse("main = 3 1 case [ 0 -> 1 + | 1 -> 2 + | else -> drop 0 ] drop");
+    type_check(&mut program)?;
+    let mut program = lex_and_parse("main = 1 let val in (val case [ 1 -> val | else -> 0 ]) drop");
+    type_check(&mut program)
+}
+
+#[test]
+fn case_arms_must_agree() {
+    let mut program = lex_and_parse("main = 1 case [ 0 -> 1 | else -> true ] drop");
+    assert!(type_check(&mut program).is_err());
+    let mut program = lex_and_parse("main = 1 case [ 0 -> 1 | else -> ] drop");
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn case_needs_an_integer() {
+    let mut program = lex_and_parse("main = true case [ 0 -> 1 | else -> 2 ] drop");
+    assert!(type_check(&mut program).is_err());
+}
+
 #[test]
 fn push_offset_for_bad_offset_fails() {
     let mut program = lex_and_parse("main = @0");
@@ -105,7 +188,7 @@ fn if_in_another_fn_type_checks() -> TypeCheckResult<()> {
 #[test]
 fn dup_apply_doesnt_unify() {
     let mut program = lex_and_parse("main = dup apply");
-    let res = type_check(&mut program);
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
     if let TypeError::NonUnifiableStacks(_, _) = res.unwrap_err() {
     } else {
         panic!("Shouldn't be able to unify types because of self recursion");
@@ -178,6 +261,101 @@ fn two_channel_alternation_type_checks() -> TypeCheckResult<()> {
     type_check(&mut program)
 }
 
+#[test]
+fn chanbuf_infers_uses() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main = chanbuf_4 'other proc_1 ? swap ? swap del + drop
+        other = 1 ! 2 ! drop",
+    );
+    type_check(&mut program)
+}
+
+#[test]
+fn chanbuf_needs_capacity() {
+    let mut program = lex_and_parse("main = chanbuf 1 ! drop ? swap del drop");
+    assert_eq!(
+        type_check(&mut program).map_err(TypeError::into_cause),
+        Err(TypeError::BufferedChannelWithoutCapacity)
+    );
+}
+
+#[test]
+fn chanbuf_capacity_must_fit_in_memory() {
+    let mut program = lex_and_parse("main = chanbuf_251 1 ! drop ? swap del drop");
+    assert_eq!(
+        type_check(&mut program).map_err(TypeError::into_cause),
+        Err(TypeError::ChannelCapacityTooLarge(251))
+    );
+    let mut program = lex_and_parse("main = chanbuf_250 1 ! drop ? swap del drop");
+    assert!(type_check(&mut program).is_ok());
+}
+
+#[test]
+fn shared_sender_can_be_shared() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main = sharedchan share 'client proc_1 'client proc_1 ? swap ? tuck + swap release
+        client = 7 ! release",
+    );
+    type_check(&mut program)
+}
+
+#[test]
+fn shared_receiver_cant_be_duplicated() {
+    let mut program = lex_and_parse("main = sharedchan release dup");
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn shared_channel_ends_must_be_released() {
+    for src in &[
+        "main = sharedchan dup release release release",
+        "main = sharedchan drop release",
+        "main = sharedchan release drop",
+    ] {
+        let mut program = lex_and_parse(src);
+        assert!(type_check(&mut program).is_err(), "{} type checked", src);
+    }
+}
+
+#[test]
+fn shared_receiver_cant_be_deleted_while_senders_remain() {
+    let mut program = lex_and_parse("main = sharedchan swap del 5 ! release");
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::NonUnifiableChannelUses(_, _)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn shared_sender_cant_be_passed_as_one_with_uses() {
+    let mut program = lex_and_parse(
+        "main = sharedchan takesOne release
+        takesOne :: Rest × chan(1, Tx, int) → Rest
+        takesOne = 5 ! drop",
+    );
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn sender_with_uses_cant_be_duplicated() {
+    let mut program = lex_and_parse(
+        "main = chan_2 dup 'client proc_1 'client proc_1 ? swap ? swap del +
+        client = 7 ! drop",
+    );
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn sender_with_uses_cant_be_passed_as_shared() {
+    let mut program = lex_and_parse(
+        "main = chan_1 takesShared ? swap del
+        takesShared :: Rest × chan(shared, Tx, int) → Rest
+        takesShared = dup drop drop",
+    );
+    assert!(type_check(&mut program).is_err());
+}
+
 #[test]
 fn cant_del_unused_channel() {
     let mut program = lex_and_parse("main = chan_1 del drop");
@@ -203,6 +381,70 @@ fn process_creation_needs_right_number_of_args() {
     assert!(type_check(&mut program).is_err());
 }
 
+#[test]
+fn proc_infers_word_count() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse("main = chan_1 (1 ! drop) proc ? drop del");
+    type_check(&mut program)?;
+    assert_eq!(
+        program.declarations[0].term.expressions[2].expression,
+        ExpressionType::NamedTermApp("proc".to_string(), Some(1))
+    );
+    Ok(())
+}
+
+#[test]
+fn proc_word_count_must_match_function() {
+    let mut program = lex_and_parse("main = chan_1 0 (1 ! drop) proc_2 ? drop del");
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::ProcWordCountMismatch(_, 2, 1, _)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn proc_word_count_cant_be_inferred_from_unknown_function() {
+    let mut program = lex_and_parse(
+        "main = () spawn
+        spawn = proc",
+    );
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::ProcWordCountUnknown(_)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn recursive_proc_word_count_is_checked_against_the_inferred_type() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main = 3 (w) proc_1
+        w = if (dup 0 ==) then (drop) else (1 - (w) proc_1)",
+    );
+    type_check(&mut program)?;
+    let mut program = lex_and_parse(
+        "main = 3 (w) proc_1
+        w = if (dup 0 ==) then (drop) else (1 - 0 (w) proc_2)",
+    );
+    assert!(type_check(&mut program).is_err());
+    Ok(())
+}
+
+#[test]
+fn spawn_and_join_cant_be_quoted() {
+    for (src, name) in &[
+        ("main = 'spawn drop", "spawn"),
+        ("main = 1 (2) 'spawn apply join", "spawn"),
+        ("main = (chan_1) spawn 'join apply", "join"),
+    ] {
+        let mut program = lex_and_parse(src);
+        assert_eq!(
+            type_check(&mut program).map_err(TypeError::into_cause),
+            Err(TypeError::CantQuote(name.to_string()))
+        );
+    }
+}
+
 #[test]
 fn proc_consumes_a_chan() -> TypeCheckResult<()> {
     let mut program = lex_and_parse("main = chan_1 (1 ! drop) proc_1 ? drop del");
@@ -581,7 +823,7 @@ fn alternation_with_two_finite_channels() -> TypeCheckResult<()> {
 #[test]
 fn cant_use_offset_to_clone_a_chan() {
     let mut program = lex_and_parse("main = chan_1 @1");
-    let res = type_check(&mut program);
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
     assert!(res.is_err());
     if let TypeError::MissingConstraints(_,_,cs) = res.unwrap_err() {
         assert!(cs.contains(Constraint::Duplicable));
@@ -589,3 +831,488 @@ fn cant_use_offset_to_clone_a_chan() {
         panic!("Didn't have expected error");
     }
 }
+
+#[test]
+fn signature_matches_inferred_type() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main :: Rest → Rest × int
+        main = 1
+        sender :: Rest × chan(uses+1, Tx, int) → Rest × chan(uses, Tx, int)
+        sender = 42 !",
+    );
+    type_check(&mut program)
+}
+
+#[test]
+fn signature_narrows_generic_type() {
+    let mut program = lex_and_parse(
+        "main = 1 identity
+        identity :: Rest × int → Rest × int
+        identity = dup drop",
+    );
+    type_check(&mut program).unwrap();
+    let identity_t = program.declarations[1].term.t_type.as_ref().unwrap();
+    if let Type::Function(i, o) = identity_t {
+        assert_eq!(
+            i.deref(),
+            &Stack::Stack(Box::new(i.get_base_stack()), Box::new(Type::Integer))
+        );
+        assert_eq!(
+            o.deref(),
+            &Stack::Stack(Box::new(o.get_base_stack()), Box::new(Type::Integer))
+        );
+    } else {
+        panic!("{} is not a function", identity_t);
+    }
+
+    let mut program = lex_and_parse(
+        "main = true identity
+        identity :: Rest × int → Rest × int
+        identity = dup drop",
+    );
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn signature_mismatch_produces_error() {
+    let mut program = lex_and_parse(
+        "main = bad drop
+        bad :: Rest → Rest × bool
+        bad = 1",
+    );
+    match type_check(&mut program).unwrap_err().into_cause() {
+        TypeError::SignatureMismatch(name, _, _, e) => {
+            assert_eq!(name, "bad");
+            assert_eq!(
+                *e,
+                TypeError::NonUnifiableTypes(Box::new(Type::Integer), Box::new(Type::Boolean))
+            );
+        }
+        e => panic!("Expected a signature mismatch, not {}", e),
+    }
+}
+
+#[test]
+fn signature_cant_be_more_general_than_inferred_type() -> TypeCheckResult<()> {
+    for src in &[
+        "main = 1 bad drop
+        bad :: S × a → S × a
+        bad = drop 2",
+        "main = 1 2 bad drop drop
+        bad :: S × a × b → S × a × b
+        bad = drop dup",
+    ] {
+        match type_check(&mut lex_and_parse(src)).map_err(TypeError::into_cause) {
+            Err(TypeError::SignatureTooGeneral(name, _, _)) => assert_eq!(name, "bad"),
+            r => panic!("Expected SignatureTooGeneral, got {:?}", r),
+        }
+    }
+    // A signature may still narrow the inferred type
+    let mut program = lex_and_parse(
+        "main = 1 2 narrow drop
+        narrow :: S × int × int → S × int
+        narrow = drop",
+    );
+    type_check(&mut program)
+}
+
+#[test]
+fn guard_condition_must_only_push_a_boolean() {
+    let mut program = lex_and_parse("main = 1 [ (drop true) & after 5 -> ]");
+    match type_check(&mut program).map_err(TypeError::into_cause) {
+        Err(TypeError::GuardConditionNotBoolean(_)) => {}
+        r => panic!("Expected GuardConditionNotBoolean, got {:?}", r),
+    }
+    let mut program = lex_and_parse("main = 1 [ (@0) & after 5 -> ]");
+    assert!(type_check(&mut program).is_err());
+    let mut program = lex_and_parse("main = 1 [ (@0 1 ==) & after 5 -> drop ]");
+    type_check(&mut program).unwrap();
+}
+
+#[test]
+fn default_arm_unifies_with_other_arms() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse("main = [ after 5 -> 1 | else -> 2 ] drop");
+    type_check(&mut program)?;
+    let mut program = lex_and_parse("main = [ after 5 -> 1 | else -> true ] drop");
+    assert!(type_check(&mut program).is_err());
+    Ok(())
+}
+
+#[test]
+fn join_pushes_results_of_spawned_function() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse("main = 1 (true) spawn join drop drop");
+    type_check(&mut program)?;
+    assert_eq!(
+        program.declarations[0].term.expressions[2].expression,
+        ExpressionType::NamedTermApp("spawn".to_string(), Some(0))
+    );
+    assert_eq!(
+        program.declarations[0].term.expressions[3].expression,
+        ExpressionType::NamedTermApp("join".to_string(), Some(1))
+    );
+    Ok(())
+}
+
+#[test]
+fn handle_cant_be_dropped() {
+    let mut program = lex_and_parse("main = (1) spawn drop");
+    assert!(type_check(&mut program).is_err());
+    let mut program = lex_and_parse("main = (1) spawn dup join join");
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn handle_must_be_joined() {
+    let mut program = lex_and_parse("main = (1) spawn");
+    assert!(type_check(&mut program).is_err());
+    let mut program = lex_and_parse(
+        "main = (1) spawn ignore
+        ignore = 2 swap",
+    );
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn spawn_needs_known_function() {
+    let mut program = lex_and_parse(
+        "main = (1) start join drop
+        start = spawn_0",
+    );
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::SpawnedFunctionUnknown(_)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn par_branches_split_the_stack() -> TypeCheckResult<()> {
+    let mut program =
+        lex_and_parse("main = chan_1 true par ( ? swap del | 5 ! drop | not ) drop drop");
+    type_check(&mut program)
+}
+
+#[test]
+fn par_branches_must_consume_their_channels() {
+    let mut program = lex_and_parse("main = chan_1 par ( ? swap del | drop )");
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn par_branch_cant_use_local() {
+    let mut program = lex_and_parse("main = 1 let val in (par ( val | 2 )) drop drop");
+    match type_check(&mut program).map_err(TypeError::into_cause) {
+        Err(TypeError::CapturedLocal(name)) => assert_eq!(name, "val"),
+        r => panic!("Expected CapturedLocal, got {:?}", r),
+    }
+}
+
+#[test]
+fn par_branch_must_return() {
+    let mut program = lex_and_parse("main = par ( repeat (1 drop) | 1 ) drop");
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::ParallelBranchDoesntReturn(_)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn par_branch_must_leave_a_fixed_number_of_values() {
+    let mut program = lex_and_parse("main = (3) par ( apply | 2 )");
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::ParallelBranchDoesntReturn(_)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn par_copies_use_their_own_channels() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main = chan_1 chan_1 swap rot par_2 (1 + ! drop) ? swap del swap ? swap del drop drop",
+    );
+    type_check(&mut program)
+}
+
+#[test]
+fn par_copy_cant_exhaust_its_channel() {
+    let mut program = lex_and_parse("main = chan_1 par_1 (! 2 ! drop) ? swap del drop");
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn alt_passes_index_as_int() {
+    let mut program = lex_and_parse(
+        "main = sharedchan release sharedchan release alt_2 (not drop drop) release release",
+    );
+    assert!(type_check(&mut program).is_err());
+}
+
+#[test]
+fn alt_spends_one_use_of_each_channel() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main = chan_1 chan_1 swap rot (6 ! drop) proc (5 ! drop) proc 0 alt_2 (drop +) rot del del drop",
+    );
+    type_check(&mut program)?;
+    let mut program = lex_and_parse(
+        "main = chan_2 chan_2 swap rot (6 ! 6 ! drop) proc (5 ! 5 ! drop) proc alt_2 (drop drop) del del",
+    );
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::NonUnifiableChannelUses(_, _)) = res {
+        Ok(())
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn alt_body_must_leave_the_stack_as_it_was() {
+    let mut program =
+        lex_and_parse("main = sharedchan release sharedchan release alt_2 (+) release release");
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::AlternationBodyDoesntReturn(_)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn alt_has_a_bit_for_each_channel() {
+    let mut program = lex_and_parse("main = sharedchan release alt_17 (drop drop)");
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::AlternationTooWide(17)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
+
+#[test]
+fn replication_must_be_for_more_than_zero_copies() {
+    for src in &[
+        "main = par_0 (drop)",
+        "main = sharedchan release alt_0 (drop)",
+    ] {
+        let mut program = lex_and_parse(src);
+        match type_check(&mut program).map_err(TypeError::into_cause) {
+            Err(TypeError::ReplicationZero(_)) => {}
+            r => panic!("Expected ReplicationZero, got {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn constant_subscripts_are_checked() -> TypeCheckResult<()> {
+    let src = "
+        const SIZE = 2
+        main = chan_SIZE (repeat_SIZE (1 !_2) drop) proc_1 repeat_SIZE (?_1 drop) del";
+    type_check(&mut lex_and_parse(src))?;
+    let mut program = lex_and_parse(&src.replace("repeat_SIZE (1", "repeat_(SIZE+1) (1"));
+    assert!(type_check(&mut program).is_err());
+    Ok(())
+}
+
+#[test]
+fn constant_cant_share_a_name_with_a_word() {
+    for (src, name) in &[
+        ("const f = 5\nmain = f drop\nf = 7", "f"),
+        ("const drop = 5\nmain = 1 drop", "drop"),
+        ("const chan = 5\nmain = 1 drop", "chan"),
+    ] {
+        let mut program = lex_and_parse(src);
+        assert_eq!(
+            type_check(&mut program).map_err(TypeError::into_cause),
+            Err(TypeError::ConstantNameInUse(name.to_string()))
+        );
+    }
+}
+
+#[test]
+fn parameterised_declaration_is_checked_per_instance() -> TypeCheckResult<()> {
+    let template = "sendAll_count = repeat_count (1 !_2) drop
+        recvAll_count = repeat_count (?_1 drop) del";
+    let src = format!("{}\nmain = chan_2 'sendAll_2 proc_1 recvAll_2", template);
+    type_check(&mut lex_and_parse(&src))?;
+    let src = format!("{}\nmain = chan_2 'sendAll_3 proc_1 recvAll_2", template);
+    assert!(type_check(&mut lex_and_parse(&src)).is_err());
+    Ok(())
+}
+
+#[test]
+fn parameterised_declaration_needs_parameter() {
+    let mut program = lex_and_parse("add_count = count +\nmain = 1 add");
+    match type_check(&mut program).map_err(TypeError::into_cause) {
+        Err(TypeError::NameNeedsParameter(name)) => assert_eq!(name, "add"),
+        r => panic!("Expected NameNeedsParameter, got {:?}", r),
+    }
+}
+
+#[test]
+fn parameterised_declaration_arithmetic_is_checked_per_instance() -> TypeCheckResult<()> {
+    let template = "take_n = 0 repeat_(n-2) (swap 1 + swap)";
+    type_check(&mut lex_and_parse(&format!("{}\nmain = take_3", template)))?;
+    let mut program = lex_and_parse(&format!("{}\nmain = take_1", template));
+    match type_check(&mut program).map_err(TypeError::into_cause) {
+        Err(TypeError::InstantiationFailed(name, 1, _)) => assert_eq!(name, "take"),
+        r => panic!("Expected InstantiationFailed, got {:?}", r),
+    }
+    Ok(())
+}
+
+#[test]
+fn unused_parameterised_declaration_is_reported() {
+    let mut program = lex_and_parse("main = 1\nunused_n = n +\nused_n = n\nother = used_1");
+    assert_eq!(
+        type_check(&mut program).map_err(TypeError::into_cause),
+        Err(TypeError::UnusedTemplate("unused".to_string()))
+    );
+}
+
+#[test]
+fn parameterised_declaration_cant_instantiate_itself_forever() {
+    let mut program = lex_and_parse("main = f_0\nf_n = f_(n+1)");
+    assert_eq!(
+        type_check(&mut program).map_err(TypeError::into_cause),
+        Err(TypeError::InstantiationTooDeep("f".to_string()))
+    );
+}
+
+#[test]
+fn type_errors_point_at_the_expression_that_caused_them() {
+    let mut program = lex_and_parse("main = 1 true +");
+    match type_check(&mut program) {
+        Err(TypeError::Located(_, span, notes)) => {
+            assert_eq!((span.start.line_offset, span.end.line_offset), (14, 15));
+            assert_eq!(notes.len(), 1);
+            let before = notes[0].span;
+            assert_eq!((before.start.line_offset, before.end.line_offset), (7, 13));
+        }
+        r => panic!("Expected a located error, got {:?}", r),
+    }
+    let mut program = lex_and_parse("main = if (true) then (1 true +) else (1)");
+    match type_check(&mut program) {
+        Err(TypeError::Located(_, span, _)) => assert_eq!(span.start.line_offset, 30),
+        r => panic!("Expected a located error, got {:?}", r),
+    }
+}
+
+#[test]
+fn holes_report_the_stack_and_what_the_rest_of_the_definition_needs() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "f :: S × int → S × bool
+        f = 1 + _?
+        g = true _? 2 +
+        main = h
+        h = _?",
+    );
+    type_check(&mut program)?;
+    let holes: Vec<String> = program.holes.iter().map(|h| h.to_string()).collect();
+    assert_eq!(
+        holes,
+        vec![
+            "the stack here is S × int, and the rest of the definition needs S × bool",
+            "the stack here is S × bool, and the rest of the definition needs S' × int",
+            "the stack here is S, and the rest of the definition needs S'",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn holes_in_nested_terms_report_the_enclosing_stack() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main = 1 2 if (_? true) then (drop) else (drop) drop
+        other = 1 2 3 let a b c in (_? a b c + + drop)",
+    );
+    type_check(&mut program)?;
+    let holes: Vec<String> = program.holes.iter().map(|h| h.to_string()).collect();
+    assert_eq!(
+        holes,
+        vec![
+            "the stack here is S × int × int, and the rest of the definition needs S' × α × β",
+            "the stack here is S × int × int × int, and the rest of the definition needs S'",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn mutually_recursive_definitions_are_inferred_together() -> TypeCheckResult<()> {
+    let mut program = lex_and_parse(
+        "main = 5 even drop
+        even = if (@0 0 ==) then (drop true) else (1 - odd)
+        odd = if (@0 0 ==) then (drop false) else (1 - even)",
+    );
+    type_check(&mut program)?;
+    for decl in &program.declarations[1..] {
+        let t = decl.term.t_type.as_ref().unwrap();
+        assert_eq!(t.to_string(), "∀ S . S × int → S × bool");
+    }
+    Ok(())
+}
+
+#[test]
+fn mutually_recursive_uses_must_agree() {
+    // odd uses the result of even as an integer
+    let mut program = lex_and_parse(
+        "main = 5 even drop
+        even = if (@0 0 ==) then (drop true) else (1 - odd)
+        odd = if (@0 0 ==) then (drop false) else (1 - even 1 + drop false)",
+    );
+    match type_check(&mut program) {
+        Err(TypeError::Located(_, span, _)) => assert_eq!(span.start.line_number, 3),
+        r => panic!("Expected a located error, got {:?}", r),
+    }
+}
+
+#[test]
+fn mutually_recursive_uses_must_fit_the_inferred_type() {
+    // even is inferred before the type of odd is known
+    let mut program = lex_and_parse(
+        "main = 5 even drop
+        even = if (@0 0 ==) then (drop true) else (1 - odd 1 + drop true)
+        odd = if (@0 0 ==) then (drop false) else (1 - even)",
+    );
+    match type_check(&mut program) {
+        Err(TypeError::Located(e, span, _)) => {
+            assert!(matches!(*e, TypeError::InconsistentUse(..)));
+            assert_eq!(span.start.line_number, 2);
+        }
+        r => panic!("Expected a located error, got {:?}", r),
+    }
+}
+
+#[test]
+fn recursive_group_type_doesnt_depend_on_declaration_order() -> TypeCheckResult<()> {
+    let declarations = [
+        "main = 3 a",
+        "a = b",
+        "b = c",
+        "c = if (dup 0 ==) then (drop) else (1 - a)",
+    ];
+    for order in &[[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1], [1, 3, 0, 2]] {
+        let src: Vec<&str> = order.iter().map(|&i| declarations[i]).collect();
+        let mut program = lex_and_parse(&src.join("\n"));
+        type_check(&mut program)?;
+        for decl in &program.declarations {
+            let t = decl.term.t_type.as_ref().unwrap();
+            if decl.name != "main" {
+                assert_eq!(t.to_string(), "∀ S : AllowBottom . S × int → S");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn recursion_that_needs_ever_more_values_is_rejected() {
+    let mut program = lex_and_parse(
+        "main = 1 2 f
+        f = drop f",
+    );
+    let res = type_check(&mut program).map_err(TypeError::into_cause);
+    if let Err(TypeError::UnboundedRecursion(_)) = res {
+    } else {
+        panic!("Didn't have expected error");
+    }
+}
@@ -65,6 +65,80 @@ impl TypeAllocator {
         Type::Function(Box::new(this_stack_type), Box::new(base))
     }
 
+    /// Like a process, but the function may leave results behind that join collects. The handle
+    /// keeps the function's type so that join knows what they are.
+    pub fn spawn_type(&mut self, k: u16) -> Type {
+        let base = self.type_stack(StackConstraints::default());
+        let mut this_stack_type = base.clone();
+        let mut sc = StackConstraints::default();
+        sc.insert(StackConstraint::MustBeBase);
+        let mut proc_stack_type = self.type_stack(sc);
+        for _ in 0..k {
+            let a = self.generic_type();
+            this_stack_type = Stack::Stack(Box::new(this_stack_type), Box::new(a.clone()));
+            proc_stack_type = Stack::Stack(Box::new(proc_stack_type), Box::new(a));
+        }
+        let results = self.type_stack(StackConstraints::default());
+        let function_type = Type::Function(Box::new(proc_stack_type), Box::new(results));
+        this_stack_type = Stack::Stack(Box::new(this_stack_type), Box::new(function_type.clone()));
+        let handle = Type::Handle(Box::new(function_type));
+        Type::Function(
+            Box::new(this_stack_type),
+            Box::new(Stack::Stack(Box::new(base), Box::new(handle))),
+        )
+    }
+
+    /// Pushes the `m` results of a spawned function
+    pub fn join_type(&mut self, m: u16) -> Type {
+        let proc_in = self.type_stack(StackConstraints::default());
+        let proc_base = self.type_stack(StackConstraints::default());
+        let s = self.type_stack(StackConstraints::default());
+        let mut proc_out = proc_base;
+        let mut output = s.clone();
+        for _ in 0..m {
+            let r = self.generic_type();
+            proc_out = Stack::Stack(Box::new(proc_out), Box::new(r.clone()));
+            output = Stack::Stack(Box::new(output), Box::new(r));
+        }
+        let function_type = Type::Function(Box::new(proc_in), Box::new(proc_out));
+        let handle = Type::Handle(Box::new(function_type));
+        Type::Function(
+            Box::new(Stack::Stack(Box::new(s), Box::new(handle))),
+            Box::new(output),
+        )
+    }
+
+    /// Receives once from each of the `n` channels beneath the `kept` words, so each spends a use
+    /// whichever order they are ready in
+    pub fn replicated_alternation_type(&mut self, n: u16, kept: &Stack, t: &Type) -> Type {
+        let s = self.type_stack(StackConstraints::default());
+        let mut input = s.clone();
+        let mut output = s;
+        for _ in 0..n {
+            let v = self.next_channel_var_counter();
+            input = Stack::Stack(
+                Box::new(input),
+                Box::new(Type::Channel(
+                    ChannelUse::Variable(v, 1),
+                    Direction::Rx,
+                    Box::new(t.clone()),
+                )),
+            );
+            output = Stack::Stack(
+                Box::new(output),
+                Box::new(Type::Channel(
+                    ChannelUse::Variable(v, 0),
+                    Direction::Rx,
+                    Box::new(t.clone()),
+                )),
+            );
+        }
+        Type::Function(
+            Box::new(kept.with_base(input)),
+            Box::new(kept.with_base(output)),
+        )
+    }
+
     pub fn receive_type(&mut self, offset: u16) -> Type {
         let v = self.next_channel_var_counter();
         let (t, rx, _tx) = self.generic_channel_type(ChannelUse::Variable(v, 1));
@@ -144,6 +218,14 @@ impl TypeAllocator {
         self.function_type(s, vec![], vec![c_rx, c_tx])
     }
 
+    /// The subscript of a buffered channel is its capacity, so its number of uses is inferred
+    pub fn chanbuf_type(&mut self) -> Type {
+        let chan_use = ChannelUse::Variable(self.next_channel_var_counter(), 0);
+        let (_, c_rx, c_tx) = self.generic_channel_type(chan_use);
+        let s = self.type_stack(StackConstraints::default());
+        self.function_type(s, vec![], vec![c_rx, c_tx])
+    }
+
     pub fn offset_type(&mut self, offset: u16) -> Type {
         let s = self.type_stack(StackConstraints::default());
         let mut input = vec![];
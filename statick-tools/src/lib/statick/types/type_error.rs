@@ -1,4 +1,10 @@
-use super::{subscripted, ChannelUse, Stack, StackConstraints, Type, TypeConstraints};
+use super::super::diagnostic::{Diagnostic, Note};
+use super::super::lexer::Span;
+use super::{
+    subscripted, ChannelUse, Stack, StackConstraints, Type, TypeConstraints,
+    MAX_ALTERNATION_CHANNELS, MAX_INSTANTIATION_DEPTH, MAX_RECURSION_PASSES,
+};
+use crate::processor::MAX_CHANNEL_CAPACITY;
 use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
@@ -6,33 +12,108 @@ use std::fmt;
 #[derive(Debug, Eq, PartialEq)]
 pub enum TypeError {
     DuplicateName(String),
+    ConstantNameInUse(String),
     UnknownName(String),
+    AmbiguousName(String, Vec<String>),
     ExpressionMissingType,
-    AlreadyHasMapping(Type, Type, Type),
-    NonUnifiableTypes(Type, Type),
-    MissingConstraints(Type, Type, TypeConstraints),
-    MissingStackConstraints(Stack, Stack, StackConstraints),
-    NonUnifiableStacks(Stack, Stack),
+    AlreadyHasMapping(Box<Type>, Box<Type>, Box<Type>),
+    NonUnifiableTypes(Box<Type>, Box<Type>),
+    MissingConstraints(Box<Type>, Box<Type>, TypeConstraints),
+    MissingStackConstraints(Box<Stack>, Box<Stack>, StackConstraints),
+    NonUnifiableStacks(Box<Stack>, Box<Stack>),
     NonUnifiableChannelUses(ChannelUse, ChannelUse),
-    BottomNotAllowed(Stack, Stack),
+    BottomNotAllowed(Box<Stack>, Box<Stack>),
     BadMain(Type),
     UndefinedMain,
     InputOutputStacksDontMatch(Type),
     NotAFunction(Type),
-    ConsumedTypesWerentConsumed(Stack, Stack, HashSet<Type>),
+    ConsumedTypesWerentConsumed(Box<Stack>, Box<Stack>, HashSet<Type>),
     NameHasNoParameter(String, u16),
+    NameNeedsParameter(String),
+    InstantiationFailed(String, u16, String),
+    InstantiationTooDeep(String),
+    /// A parameterised declaration that nothing uses, so that its term is never checked
+    UnusedTemplate(String),
     CantUseExhaustedChannel,
     EmptyAlternationsNotAllowed,
     RepeatZero,
+    SignatureMismatch(String, Box<Type>, Box<Type>, Box<TypeError>),
+    /// A signature that claims more than the inferred type, such as a type variable for an int
+    SignatureTooGeneral(String, Box<Type>, Box<Type>),
+    /// A use of a declaration in its own group that doesn't fit the type inferred for it
+    InconsistentUse(String, Box<Type>, Box<Type>, Box<TypeError>),
+    UnboundedRecursion(String),
+    LetBodyConsumesStack(Type),
+    CapturedLocal(String),
+    LocalInUnbalancedRepeat(String),
+    LocalNotDuplicable(String, Type),
+    LocalNotDroppable(String, Type),
+    GuardConditionNotBoolean(Type),
+    BufferedChannelWithoutCapacity,
+    ChannelCapacityTooLarge(u16),
+    ProcWordCountMismatch(String, u16, usize, Type),
+    ProcWordCountUnknown(Type),
+    SpawnedFunctionUnknown(Type),
+    JoinWithoutHandle(Type),
+    CantQuote(String),
+    ParallelBranchDoesntReturn(Type),
+    ReplicationZero(String),
+    AlternationTooWide(u16),
+    AlternationBodyDoesntReturn(Type),
+    /// An error caused by the code at the span
+    Located(Box<TypeError>, Span, Vec<Note>),
+}
+
+impl TypeError {
+    /**
+     * Points the error at the code that caused it. Errors are located as they propagate out of
+     * the AST, so an error that already has a location keeps the innermost one.
+     */
+    pub fn at(self, span: Span, notes: Vec<Note>) -> TypeError {
+        match self {
+            TypeError::Located(_, _, _) => self,
+            _ => TypeError::Located(Box::new(self), span, notes),
+        }
+    }
+
+    /// The error without its location
+    pub fn into_cause(self) -> TypeError {
+        match self {
+            TypeError::Located(e, _, _) => *e,
+            _ => self,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            TypeError::Located(e, span, notes) => {
+                let mut diagnostic = Diagnostic::new(e.to_string(), Some(*span));
+                diagnostic.notes = notes.clone();
+                diagnostic
+            }
+            _ => Diagnostic::new(self.to_string(), None),
+        }
+    }
 }
 
 impl fmt::Display for TypeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             TypeError::DuplicateName(n) => write!(f, "{} is already defined", n),
+            TypeError::ConstantNameInUse(n) => write!(
+                f,
+                "{} can't be a constant, since it is also the name of a word",
+                n
+            ),
             TypeError::UnknownName(n) => {
                 write!(f, "{} is not defined in the global environment", n)
             }
+            TypeError::AmbiguousName(n, modules) => write!(
+                f,
+                "{} is ambiguous because it is defined in modules {}",
+                n,
+                modules.join(", ")
+            ),
             TypeError::ExpressionMissingType => {
                 write!(f, "Expression was not annotated with a type")
             }
@@ -90,11 +171,147 @@ impl fmt::Display for TypeError {
                 n,
                 subscripted(k)
             ),
+            TypeError::NameNeedsParameter(n) => write!(
+                f,
+                "{} has a numeric parameter, so must be called as e.g. {}{}",
+                n,
+                n,
+                subscripted(1)
+            ),
+            TypeError::InstantiationFailed(n, k, e) => {
+                write!(f, "Couldn't instantiate {}{}: {}", n, subscripted(*k), e)
+            }
+            TypeError::UnusedTemplate(n) => write!(
+                f,
+                "{} is never used with a parameter, so its term can't be checked",
+                n
+            ),
+            TypeError::InstantiationTooDeep(n) => write!(
+                f,
+                "Instantiating {} needed more than {} nested instances, so it may never stop",
+                n, MAX_INSTANTIATION_DEPTH
+            ),
             TypeError::CantUseExhaustedChannel => write!(f, "Can't use an exhausted channel"),
             TypeError::EmptyAlternationsNotAllowed => {
                 write!(f, "Empty alternations are not permitted")
             }
             TypeError::RepeatZero => write!(f, "Repeat must be for more than zero occurrences"),
+            TypeError::ProcWordCountMismatch(name, k, n, t) => write!(
+                f,
+                "{}{} passes {} word(s) to a process, but its function {} expects {}",
+                name,
+                subscripted(k),
+                k,
+                t,
+                n
+            ),
+            TypeError::ProcWordCountUnknown(t) => write!(
+                f,
+                "Can't infer how many words proc passes to a process from {}; use proc with a subscript",
+                t
+            ),
+            TypeError::SpawnedFunctionUnknown(t) => write!(
+                f,
+                "spawn must know the function it runs to know what join returns, but has {}",
+                t
+            ),
+            TypeError::JoinWithoutHandle(t) => write!(
+                f,
+                "join needs a process handle on top of the stack, but has {}",
+                t
+            ),
+            TypeError::CantQuote(name) => write!(
+                f,
+                "{} can't be quoted, since what it takes and leaves depends on where it is applied",
+                name
+            ),
+            TypeError::ParallelBranchDoesntReturn(t) => write!(
+                f,
+                "Every branch of a par must finish, taking and leaving a fixed number of values, but one has type {}",
+                t
+            ),
+            TypeError::AlternationTooWide(n) => write!(
+                f,
+                "alt{} alternates over more than {} channels",
+                subscripted(*n),
+                MAX_ALTERNATION_CHANNELS
+            ),
+            TypeError::AlternationBodyDoesntReturn(t) => write!(
+                f,
+                "The body of an alt runs once for each channel, so it must take the value and index and leave the rest of the stack as it was, but it has type {}",
+                t
+            ),
+            TypeError::ReplicationZero(name) => write!(
+                f,
+                "{}{} has nothing to replicate, so must be for more than zero copies",
+                name,
+                subscripted(0)
+            ),
+            TypeError::BufferedChannelWithoutCapacity => write!(
+                f,
+                "A buffered channel must be created with a capacity, e.g. chanbuf{}",
+                subscripted(4)
+            ),
+            TypeError::ChannelCapacityTooLarge(k) => write!(
+                f,
+                "A buffered channel can hold at most {} values, so chanbuf{} is too large",
+                MAX_CHANNEL_CAPACITY,
+                subscripted(*k)
+            ),
+            TypeError::SignatureMismatch(n, signature, inferred, e) => write!(
+                f,
+                "{} has type {}, which doesn't match its signature {} because: {}",
+                n, inferred, signature, e
+            ),
+            TypeError::SignatureTooGeneral(n, signature, inferred) => write!(
+                f,
+                "{} has type {}, which is less general than its signature {}",
+                n, inferred, signature
+            ),
+            TypeError::InconsistentUse(n, t, used, e) => write!(
+                f,
+                "{} has type {}, which doesn't match its use as {} because: {}",
+                n, t, used, e
+            ),
+            TypeError::UnboundedRecursion(names) => write!(
+                f,
+                "Each recursive call in {} reaches deeper into the stack, so its type hadn't \
+                 settled after {} passes",
+                names, MAX_RECURSION_PASSES
+            ),
+            TypeError::LetBodyConsumesStack(t) => write!(
+                f,
+                "The body of a let has type {}, but may only push values on to the stack",
+                t
+            ),
+            TypeError::CapturedLocal(n) => {
+                write!(
+                    f,
+                    "{} is a local binding, so it can't be used in a quotation",
+                    n
+                )
+            }
+            TypeError::LocalInUnbalancedRepeat(n) => write!(
+                f,
+                "{} can't be used in a repeat whose body changes the size of the stack",
+                n
+            ),
+            TypeError::LocalNotDuplicable(n, t) => write!(
+                f,
+                "{} may be used more than once, but its type {} can't be duplicated",
+                n, t
+            ),
+            TypeError::LocalNotDroppable(n, t) => write!(
+                f,
+                "{} may not be used, but its type {} can't be dropped",
+                n, t
+            ),
+            TypeError::GuardConditionNotBoolean(t) => write!(
+                f,
+                "The condition of a guard has type {}, but may only push a boolean",
+                t
+            ),
+            TypeError::Located(e, span, _) => write!(f, "{} (line {})", e, span.start.line_number),
         }
     }
 }
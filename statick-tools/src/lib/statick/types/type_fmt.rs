@@ -33,38 +33,60 @@ pub trait TypeFmt {
         stack_order: &[usize],
     ) -> fmt::Result;
 
-    fn fmt_with_mode(&self, f: &mut fmt::Formatter, mode: Name) -> fmt::Result {
-        let mut generics = vec![];
-        let mut stacks = vec![];
-        let mut counters = vec![];
-        self.collect_vars(&mut generics, &mut stacks, &mut counters);
-        let mut constraints = HashMap::new();
-        self.collect_constraints(&mut constraints);
-        let mut stack_constraints = HashMap::new();
-        self.collect_stack_constraints(&mut stack_constraints);
-        let (generic_map, stack_map, counter_map) = name_vars(mode, &generics, &stacks, &counters);
-        self.fmt_with_generics_and_stacks(
-            f,
-            &generic_map,
-            &stack_map,
-            &counter_map,
-            true,
-            &constraints,
-            &stack_constraints,
-            &generics,
-            &stacks,
-        )
+    fn fmt_with_mode(&self, f: &mut fmt::Formatter, mode: Name) -> fmt::Result
+    where
+        Self: Sized,
+    {
+        fmt_part(f, self, self, mode)
     }
 
-    fn fmt_debug(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_debug(&self, f: &mut fmt::Formatter) -> fmt::Result
+    where
+        Self: Sized,
+    {
         self.fmt_with_mode(f, Name::Original)
     }
 
-    fn fmt_display(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_display(&self, f: &mut fmt::Formatter) -> fmt::Result
+    where
+        Self: Sized,
+    {
         self.fmt_with_mode(f, Name::Greek)
     }
 }
 
+/**
+ * Writes part of a type with its variables named as they would be if the whole type was written,
+ * so that the parts of a type that are written separately still agree on their names
+ */
+pub fn fmt_part(
+    f: &mut fmt::Formatter,
+    whole: &dyn TypeFmt,
+    part: &dyn TypeFmt,
+    mode: Name,
+) -> fmt::Result {
+    let mut generics = vec![];
+    let mut stacks = vec![];
+    let mut counters = vec![];
+    whole.collect_vars(&mut generics, &mut stacks, &mut counters);
+    let mut constraints = HashMap::new();
+    whole.collect_constraints(&mut constraints);
+    let mut stack_constraints = HashMap::new();
+    whole.collect_stack_constraints(&mut stack_constraints);
+    let (generic_map, stack_map, counter_map) = name_vars(mode, &generics, &stacks, &counters);
+    part.fmt_with_generics_and_stacks(
+        f,
+        &generic_map,
+        &stack_map,
+        &counter_map,
+        true,
+        &constraints,
+        &stack_constraints,
+        &generics,
+        &stacks,
+    )
+}
+
 fn name_vars(
     mode: Name,
     generics: &[usize],
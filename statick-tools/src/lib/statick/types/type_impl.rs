@@ -14,6 +14,9 @@ pub enum ChannelUse {
     Infinity,
     Constant(ChannelVariableOffset),
     Variable(ChannelVariable, ChannelVariableOffset),
+    /// A channel with many senders. Both ends can be used indefinitely, and the sending end can be
+    /// duplicated.
+    Shared,
 }
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
@@ -26,11 +29,14 @@ pub enum Direction {
 pub enum Type {
     Boolean,
     Integer,
+    SignedInteger,
     Counter(usize),
     Void,
     Channel(ChannelUse, Direction, Box<Type>),
     Generic(usize, TypeConstraints),
     Function(Box<Stack>, Box<Stack>),
+    /// A spawned process that must be joined, along with the type of the function that it runs
+    Handle(Box<Type>),
 }
 
 impl Type {
@@ -39,21 +45,28 @@ impl Type {
         use Constraint::*;
         use Type::*;
         match self {
-            Boolean | Integer | Void | Function(_, _) => match constraint {
-                MustConsume => false,
+            Boolean | Integer | SignedInteger | Void | Function(_, _) => match constraint {
+                MustConsume | Releasable => false,
                 Droppable | Duplicable => true,
                 IntLike => self == &Boolean || self == &Integer,
+                Numeric => self == &Integer || self == &SignedInteger,
             },
             Counter(_) => constraint == IntLike,
             Channel(chan_use, dir, _) => match chan_use {
                 Infinity | Variable(_, _) => false,
+                // Each end is counted by the simulator, so is shared and released explicitly
+                Shared => match constraint {
+                    MustConsume | Releasable => true,
+                    Duplicable | Droppable | IntLike | Numeric => false,
+                },
                 Constant(k) => match constraint {
-                    IntLike => false,
+                    IntLike | Numeric | Releasable => false,
                     MustConsume => *k > 0,
                     Duplicable => false,
                     Droppable => *k == 0 && *dir == Direction::Rx, // The sender is deleted with del
                 },
             },
+            Handle(_) => constraint == MustConsume,
             Generic(_, cs) => cs.contains(constraint),
         }
     }
@@ -91,21 +104,27 @@ impl Type {
                 Type::Generic(_, _)
                 | Type::Boolean
                 | Type::Integer
+                | Type::SignedInteger
                 | Type::Void
                 | Type::Counter(_) => false,
                 Type::Channel(_, _, c) => c.contains(a),
                 Type::Function(i, o) => i.contains(a) || o.contains(a),
+                Type::Handle(r) => r.contains(a),
             }
         }
     }
 
     pub fn contains_stack(&self, s: &Stack) -> bool {
         match self {
-            Type::Boolean | Type::Integer | Type::Void | Type::Generic(_, _) | Type::Counter(_) => {
-                false
-            }
+            Type::Boolean
+            | Type::Integer
+            | Type::SignedInteger
+            | Type::Void
+            | Type::Generic(_, _)
+            | Type::Counter(_) => false,
             Type::Channel(_, _, t) => t.contains_stack(s),
             Type::Function(i, o) => i.contains_stack(s) || o.contains_stack(s),
+            Type::Handle(r) => r.contains_stack(s),
         }
     }
 
@@ -122,8 +141,13 @@ impl Type {
                 i.collect_channel_variables(vars);
                 o.collect_channel_variables(vars);
             }
-            Type::Integer | Type::Boolean | Type::Void | Type::Generic(_, _) | Type::Counter(_) => {
-            }
+            Type::Handle(r) => r.collect_channel_variables(vars),
+            Type::Integer
+            | Type::SignedInteger
+            | Type::Boolean
+            | Type::Void
+            | Type::Generic(_, _)
+            | Type::Counter(_) => {}
         }
     }
 
@@ -173,7 +197,11 @@ impl Type {
         impl TypeVisitor {
             fn deep_copy_type(&mut self, t: &Type) -> Type {
                 match t {
-                    Type::Boolean | Type::Integer | Type::Void | Type::Counter(_) => t.clone(),
+                    Type::Boolean
+                    | Type::Integer
+                    | Type::SignedInteger
+                    | Type::Void
+                    | Type::Counter(_) => t.clone(),
                     Type::Channel(u, d, c) => Type::Channel(
                         self.deep_copy_channel_use(u),
                         *d,
@@ -185,6 +213,7 @@ impl Type {
                         let new_o = self.deep_copy_stack(&o);
                         Type::Function(Box::new(new_i), Box::new(new_o))
                     }
+                    Type::Handle(r) => Type::Handle(Box::new(self.deep_copy_type(r))),
                 }
             }
 
@@ -202,7 +231,7 @@ impl Type {
 
             fn deep_copy_channel_use(&mut self, u: &ChannelUse) -> ChannelUse {
                 match u {
-                    ChannelUse::Constant(_) | ChannelUse::Infinity => *u,
+                    ChannelUse::Constant(_) | ChannelUse::Infinity | ChannelUse::Shared => *u,
                     ChannelUse::Variable(n, o) => {
                         ChannelUse::Variable(self.channel_variable_map[n], *o)
                     }
@@ -226,7 +255,7 @@ impl TypeFmt for Type {
         counters: &mut Vec<usize>,
     ) {
         match self {
-            Type::Integer | Type::Boolean | Type::Void => {}
+            Type::Integer | Type::SignedInteger | Type::Boolean | Type::Void => {}
             Type::Counter(n) => counters.push(*n),
             Type::Generic(n, _) => generics.push(*n),
             Type::Channel(_, _, c) => c.collect_vars(generics, stacks, counters),
@@ -234,6 +263,7 @@ impl TypeFmt for Type {
                 i.collect_vars(generics, stacks, counters);
                 o.collect_vars(generics, stacks, counters);
             }
+            Type::Handle(r) => r.collect_vars(generics, stacks, counters),
         }
     }
 
@@ -247,7 +277,9 @@ impl TypeFmt for Type {
                 i.collect_constraints(constraint_map);
                 o.collect_constraints(constraint_map);
             }
-            Type::Integer | Type::Boolean | Type::Void | Type::Counter(_) => {}
+            Type::Handle(r) => r.collect_constraints(constraint_map),
+            Type::Integer | Type::SignedInteger | Type::Boolean | Type::Void | Type::Counter(_) => {
+            }
         }
     }
 
@@ -258,8 +290,13 @@ impl TypeFmt for Type {
                 i.collect_stack_constraints(constraint_map);
                 o.collect_stack_constraints(constraint_map);
             }
-            Type::Generic(_, _) | Type::Integer | Type::Boolean | Type::Void | Type::Counter(_) => {
-            }
+            Type::Handle(r) => r.collect_stack_constraints(constraint_map),
+            Type::Generic(_, _)
+            | Type::Integer
+            | Type::SignedInteger
+            | Type::Boolean
+            | Type::Void
+            | Type::Counter(_) => {}
         }
     }
 
@@ -278,6 +315,7 @@ impl TypeFmt for Type {
         match self {
             Type::Void => write!(f, "void"),
             Type::Integer => write!(f, "int"),
+            Type::SignedInteger => write!(f, "sint"),
             Type::Boolean => write!(f, "bool"),
             Type::Counter(n) => write!(f, "counter{}", subscripted(counters[n])),
             Type::Generic(n, _) => write!(f, "{}", generics[n]),
@@ -288,6 +326,7 @@ impl TypeFmt for Type {
                 };
                 match chan_use {
                     ChannelUse::Infinity => {}
+                    ChannelUse::Shared => write!(f, "(shared)")?,
                     ChannelUse::Constant(k) => {
                         write!(f, "({})", *k)?;
                     }
@@ -379,6 +418,21 @@ impl TypeFmt for Type {
                     write!(f, ")")
                 }
             }
+            Type::Handle(r) => {
+                write!(f, "Handle(")?;
+                r.fmt_with_generics_and_stacks(
+                    f,
+                    generics,
+                    stacks,
+                    counters,
+                    true,
+                    constraint_map,
+                    stack_constraint_map,
+                    generics_order,
+                    stack_order,
+                )?;
+                write!(f, ")")
+            }
         }
     }
 }
@@ -393,11 +447,14 @@ impl ApplyUnifierStep for Type {
                 let new_u = if let UnifierStep::Channel(replace_n, replacement) = step {
                     match chan_use {
                         // These cannot be replaced
-                        ChannelUse::Infinity | ChannelUse::Constant(_) => *chan_use,
+                        ChannelUse::Infinity | ChannelUse::Constant(_) | ChannelUse::Shared => {
+                            *chan_use
+                        }
                         ChannelUse::Variable(name, ops) => {
                             if *name == *replace_n {
                                 match replacement {
                                     ChannelUse::Infinity => ChannelUse::Infinity,
+                                    ChannelUse::Shared => ChannelUse::Shared,
                                     ChannelUse::Constant(k) => ChannelUse::Constant(*k + *ops),
                                     ChannelUse::Variable(new_n, new_ops) => {
                                         ChannelUse::Variable(*new_n, *ops + *new_ops)
@@ -430,7 +487,10 @@ impl ApplyUnifierStep for Type {
                 let new_o = o.apply_unifier_step(step);
                 Type::Function(Box::new(new_i), Box::new(new_o))
             }
-            Type::Integer | Type::Boolean | Type::Void | Type::Counter(_) => self.clone(),
+            Type::Handle(r) => Type::Handle(Box::new(r.apply_unifier_step(step))),
+            Type::Integer | Type::SignedInteger | Type::Boolean | Type::Void | Type::Counter(_) => {
+                self.clone()
+            }
         }
     }
 }